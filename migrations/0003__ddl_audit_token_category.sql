@@ -0,0 +1,8 @@
+-- ==================================================================
+--
+-- AUDIT LOG: token category
+--
+-- ==================================================================
+-- a new enum value cannot be used within the transaction that adds it,
+-- hence this dedicated migration
+ALTER TYPE audit_log_category_type ADD VALUE IF NOT EXISTS 'token';
@@ -0,0 +1,25 @@
+--
+--
+-- TOKEN
+--
+--
+
+-- tag::tokens[]
+CREATE TABLE IF NOT EXISTS tokens (
+                                       id          BIGINT GENERATED BY DEFAULT AS IDENTITY NOT NULL PRIMARY KEY,
+                                       policy_code TEXT   NOT NULL,
+                                       token       TEXT   NOT NULL,
+                                       value       BYTEA  NOT NULL -- encrypted original value
+);
+
+CALL add_tenant_meta('tokens');
+CALL add_tenant_trigger('tokens');
+CALL add_tenant_isolation('tokens');
+CREATE UNIQUE INDEX tokens_token_uniqueness ON tokens (tenant_id, policy_code, token);
+-- end::tokens[]
+
+-- tag::tokens_post_creation[]
+CALL add_audit_meta('tokens');
+CALL add_audit_meta_trigger('tokens');
+CALL add_audit_log_trigger('tokens', 'token', audit_meta_fields() || '{"value"}'::TEXT[]);
+-- end::tokens_post_creation[]
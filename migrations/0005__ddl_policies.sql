@@ -0,0 +1,30 @@
+--
+--
+-- POLICY
+--
+--
+
+-- tag::policies[]
+CREATE TABLE IF NOT EXISTS policies (
+                                         id         BIGINT GENERATED BY DEFAULT AS IDENTITY NOT NULL PRIMARY KEY,
+                                         code       TEXT    NOT NULL,
+                                         format     JSONB   NOT NULL,
+                                         prefix     TEXT,
+                                         keep_left  INTEGER NOT NULL DEFAULT 0 CHECK (keep_left >= 0),
+                                         keep_right INTEGER NOT NULL DEFAULT 0 CHECK (keep_right >= 0)
+);
+
+CALL add_tenant_meta('policies');
+CALL add_tenant_trigger('policies');
+CALL add_tenant_isolation('policies');
+CREATE UNIQUE INDEX policies_code_uniqueness ON policies (tenant_id, code);
+
+CALL add_row_version_meta('policies');
+CALL add_prevent_from_concurrent_update_trigger('policies');
+-- end::policies[]
+
+-- tag::policies_post_creation[]
+CALL add_audit_meta('policies');
+CALL add_audit_meta_trigger('policies');
+CALL add_audit_log_trigger('policies', 'policy', technical_meta_fields());
+-- end::policies_post_creation[]
@@ -0,0 +1,18 @@
+--
+--
+-- DETERMINISTIC TOKENS
+--
+--
+
+-- tag::deterministic_policies[]
+ALTER TABLE policies ADD COLUMN deterministic BOOLEAN NOT NULL DEFAULT FALSE;
+-- end::deterministic_policies[]
+
+-- tag::tokens_value_index[]
+-- keyed hash (HMAC) of the normalized value, only filled for deterministic policies
+ALTER TABLE tokens ADD COLUMN value_index BYTEA;
+CREATE UNIQUE INDEX tokens_value_index_uniqueness ON tokens (tenant_id, policy_code, value_index) WHERE value_index IS NOT NULL;
+
+DROP TRIGGER tokens_audit_trigger ON tokens;
+CALL add_audit_log_trigger('tokens', 'token', audit_meta_fields() || '{"value", "value_index"}'::TEXT[]);
+-- end::tokens_value_index[]
@@ -0,0 +1,39 @@
+--
+--
+-- TOKEN SEQUENCES
+--
+--
+
+-- tag::token_sequences[]
+-- one durable counter per (tenant, policy), shared by every running instance
+CREATE TABLE IF NOT EXISTS token_sequences (
+                                               id          BIGINT GENERATED BY DEFAULT AS IDENTITY NOT NULL PRIMARY KEY,
+                                               policy_code TEXT   NOT NULL,
+                                               next_value  BIGINT NOT NULL DEFAULT 1
+);
+
+CALL add_tenant_meta('token_sequences');
+-- sequences of the already existing policies, before the tenant trigger enforces the current tenant
+INSERT INTO token_sequences (tenant_id, policy_code) SELECT tenant_id, code FROM policies;
+CALL add_tenant_trigger('token_sequences');
+CALL add_tenant_isolation('token_sequences');
+CREATE UNIQUE INDEX token_sequences_policy_uniqueness ON token_sequences (tenant_id, policy_code);
+ALTER TABLE token_sequences
+    ADD CONSTRAINT token_sequences_policy_fk FOREIGN KEY (tenant_id, policy_code)
+        REFERENCES policies (tenant_id, code) ON UPDATE CASCADE ON DELETE CASCADE;
+-- end::token_sequences[]
+
+-- tag::token_sequences_creation[]
+CREATE OR REPLACE FUNCTION create_token_sequence_trigger_func()
+    RETURNS trigger AS $body$
+BEGIN
+    INSERT INTO token_sequences (policy_code) VALUES (NEW.code);
+    RETURN NEW;
+END;
+$body$
+    LANGUAGE plpgsql;
+
+CREATE TRIGGER policies_token_sequence_trigger
+    AFTER INSERT ON policies
+    FOR EACH ROW EXECUTE FUNCTION create_token_sequence_trigger_func();
+-- end::token_sequences_creation[]
@@ -0,0 +1,11 @@
+--
+--
+-- POLICY LENGTH UNIT
+--
+--
+
+-- tag::policies_length_unit[]
+-- unit in which keep_left, keep_right and the value ranges of the templates are counted
+ALTER TABLE policies ADD COLUMN length_unit TEXT NOT NULL DEFAULT 'chars'
+    CONSTRAINT policies_length_unit_check CHECK (length_unit IN ('chars', 'graphemes'));
+-- end::policies_length_unit[]
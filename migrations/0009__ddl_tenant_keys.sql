@@ -0,0 +1,18 @@
+--
+--
+-- TENANT KEYS
+--
+--
+
+-- tag::tenant_keys[]
+-- data encryption key of each tenant, wrapped (encrypted) by the master key encryption key
+CREATE TABLE IF NOT EXISTS tenant_keys (
+                                           id          BIGINT GENERATED BY DEFAULT AS IDENTITY NOT NULL PRIMARY KEY,
+                                           wrapped_key BYTEA  NOT NULL
+);
+
+CALL add_tenant_meta('tenant_keys');
+CALL add_tenant_trigger('tenant_keys');
+CALL add_tenant_isolation('tenant_keys');
+CREATE UNIQUE INDEX tenant_keys_tenant_uniqueness ON tenant_keys (tenant_id);
+-- end::tenant_keys[]
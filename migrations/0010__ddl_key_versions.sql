@@ -0,0 +1,18 @@
+--
+--
+-- KEY VERSIONS
+--
+--
+
+-- tag::tenant_key_versions[]
+-- every version of the data key of a tenant is kept, the latest one encrypting the new values
+ALTER TABLE tenant_keys ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+DROP INDEX tenant_keys_tenant_uniqueness;
+CREATE UNIQUE INDEX tenant_keys_version_uniqueness ON tenant_keys (tenant_id, version);
+-- end::tenant_key_versions[]
+
+-- tag::tokens_key_version[]
+-- version of the data key the value is encrypted with
+ALTER TABLE tokens ADD COLUMN key_version INTEGER NOT NULL DEFAULT 1;
+CREATE INDEX tokens_key_version ON tokens (tenant_id, key_version);
+-- end::tokens_key_version[]
@@ -0,0 +1,35 @@
+--
+--
+-- CRYPTO SHREDDING
+--
+--
+
+-- tag::shredded_tenant_keys[]
+-- shredding destroys the wrapped key: the row remains, to tell erased values from unknown ones
+ALTER TABLE tenant_keys ALTER COLUMN wrapped_key DROP NOT NULL;
+ALTER TABLE tenant_keys ADD COLUMN shredded_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE tenant_keys
+    ADD CONSTRAINT tenant_keys_shredding_check CHECK ((wrapped_key IS NULL) = (shredded_at IS NOT NULL));
+CALL add_audit_log_trigger('tenant_keys', 'key', '{"wrapped_key"}'::TEXT[]);
+-- end::shredded_tenant_keys[]
+
+-- tag::subject_keys[]
+-- data key of a data subject, wrapped by a data key of the tenant
+CREATE TABLE IF NOT EXISTS subject_keys (
+                                            id                 BIGINT GENERATED BY DEFAULT AS IDENTITY NOT NULL PRIMARY KEY,
+                                            subject            TEXT    NOT NULL,
+                                            wrapped_key        BYTEA,
+                                            tenant_key_version INTEGER NOT NULL,
+                                            shredded_at        TIMESTAMP WITH TIME ZONE,
+                                            CONSTRAINT subject_keys_shredding_check CHECK ((wrapped_key IS NULL) = (shredded_at IS NOT NULL))
+);
+
+CALL add_tenant_meta('subject_keys');
+CALL add_tenant_trigger('subject_keys');
+CALL add_tenant_isolation('subject_keys');
+CREATE UNIQUE INDEX subject_keys_subject_uniqueness ON subject_keys (tenant_id, subject);
+CALL add_audit_log_trigger('subject_keys', 'key', '{"wrapped_key"}'::TEXT[]);
+
+-- values of a subject are encrypted with its own key rather than the one of the tenant
+ALTER TABLE tokens ADD COLUMN subject_key_id BIGINT REFERENCES subject_keys (id);
+-- end::subject_keys[]
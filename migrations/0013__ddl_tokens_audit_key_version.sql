@@ -0,0 +1,11 @@
+--
+--
+-- TOKENS AUDIT
+--
+--
+
+-- tag::tokens_audit_key_version[]
+-- re-encrypting the values under a new key version is not a change of the token
+DROP TRIGGER tokens_audit_trigger ON tokens;
+CALL add_audit_log_trigger('tokens', 'token', audit_meta_fields() || '{"value", "value_index", "key_version"}'::TEXT[]);
+-- end::tokens_audit_key_version[]
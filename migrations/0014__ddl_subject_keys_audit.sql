@@ -0,0 +1,11 @@
+--
+--
+-- SUBJECT KEYS AUDIT
+--
+--
+
+-- tag::subject_keys_audit_key_version[]
+-- wrapping a subject key again under a new tenant key version is not a change of the key
+DROP TRIGGER subject_keys_audit_trigger ON subject_keys;
+CALL add_audit_log_trigger('subject_keys', 'key', '{"wrapped_key", "tenant_key_version"}'::TEXT[]);
+-- end::subject_keys_audit_key_version[]
@@ -1,6 +1,7 @@
 use crate::core::context::Permission;
-use crate::error::Error as CError;
+use crate::error::{Error as CError, ErrorCode};
 use regex::Regex;
+use std::collections::HashMap;
 use std::fmt::Formatter;
 use std::ops::Deref;
 use std::{collections::HashSet, fmt};
@@ -67,8 +68,8 @@ impl TryFrom<String> for TenantId {
     type Error = CError;
 
     fn try_from(s: String) -> Result<Self, Self::Error> {
-        // at least two chars code
-        let re = Regex::new(r"^[a-zA-Z0-9_-]{2,}$").unwrap();
+        // either a technical (numeric) identifier or at least two chars code
+        let re = Regex::new(r"^([0-9]+|[a-zA-Z0-9_-]{2,})$").unwrap();
         if re.is_match(s.as_str()) {
             Ok(TenantId(s))
         } else {
@@ -119,6 +120,28 @@ impl ExecutionContext {
         }
         PermissionControlState::Missing
     }
+
+    /// Same as `has_permission` but fails with a `Forbidden` error
+    /// whenever the permission is not granted.
+    pub fn ensure_permission(&self, permission: &Permission) -> Result<(), CError> {
+        match self.has_permission(permission) {
+            PermissionControlState::Authorized => Ok(()),
+            state => Err(CError::Generic(
+                ErrorCode::Forbidden,
+                format!("Permission not granted: {}", state),
+                HashMap::from([("permission".to_string(), permission.to_string())]),
+            )),
+        }
+    }
+
+    /// The tenant as a string, empty when there is none; scopes the keys
+    /// derived per tenant
+    pub fn tenant_label(&self) -> String {
+        self.tenant
+            .as_ref()
+            .map(|t| t.to_string())
+            .unwrap_or_default()
+    }
 }
 
 #[cfg(test)]
@@ -129,7 +152,7 @@ mod tests {
 
     #[test]
     fn caller_type_from_string_case_insensitive() {
-        for t in vec![
+        for t in [
             ("USER".to_string(), CallerType::USER),
             ("SERVICE".to_string(), CallerType::SERVICE),
         ] {
@@ -140,7 +163,7 @@ mod tests {
 
     #[test]
     fn caller_type_from_string() {
-        for t in vec![
+        for t in [
             ("user".to_string(), CallerType::USER),
             ("service".to_string(), CallerType::SERVICE),
         ] {
@@ -161,6 +184,16 @@ mod tests {
         assert_eq!("SERVICE".to_string(), CallerType::SERVICE.to_string());
     }
 
+    #[test]
+    fn tenant_label() {
+        let caller = Caller::new("007".to_string(), CallerType::USER);
+        let tenant: TenantId = "idfm".to_string().try_into().unwrap();
+        let context = ExecutionContext::new(Some(tenant), caller.clone(), HashSet::new());
+        assert_eq!(context.tenant_label(), "idfm");
+        let context = ExecutionContext::new(None, caller, HashSet::new());
+        assert_eq!(context.tenant_label(), "");
+    }
+
     #[test]
     fn tenant_id_from_string() {
         let tid: Result<TenantId, CError> = "idfm".to_string().try_into();
@@ -185,6 +218,12 @@ mod tests {
         }
     }
 
+    #[test]
+    fn tenant_id_from_numeric_string() {
+        let tid: Result<TenantId, CError> = "7".to_string().try_into();
+        assert_eq!(tid.unwrap().deref(), &"7".to_string());
+    }
+
     #[test]
     fn tenant_id_from_string_reject_not_ascii_char() {
         let tid: Result<TenantId, CError> = "az:er".to_string().try_into();
@@ -225,4 +264,20 @@ mod tests {
         assert_eq!(ctx.has_permission(&TokenRead), Authorized);
         assert_eq!(ctx.has_permission(&TokenCreate), Missing);
     }
+
+    #[test]
+    fn execution_context_ensure_permission() {
+        let tenant: TenantId = "idfm".to_string().try_into().unwrap();
+        let ctx = ExecutionContext::new(Some(tenant), sample_caller(), HashSet::from([TokenRead]));
+        assert!(ctx.ensure_permission(&TokenRead).is_ok());
+        match ctx.ensure_permission(&TokenCreate) {
+            Err(CError::Generic(ErrorCode::Forbidden, _, details)) => {
+                assert_eq!(details.get("permission"), Some(&"TokenCreate".to_string()))
+            }
+            _ => panic!("Invalid error"),
+        }
+
+        let ctx = ExecutionContext::new(None, sample_caller(), HashSet::from([TokenRead]));
+        assert!(ctx.ensure_permission(&TokenRead).is_err());
+    }
 }
@@ -5,6 +5,10 @@ use std::{collections::HashSet, fmt};
 pub enum Role {
     Root,
     Agent,
+    /// display services, only allowed to mask values
+    Display,
+    /// operators rotating and shredding the vault keys, not allowed to read the values
+    KeyAdmin,
 }
 
 impl TryFrom<String> for Role {
@@ -14,13 +18,15 @@ impl TryFrom<String> for Role {
         match value.to_lowercase().as_str() {
             "root" => Ok(Role::Root),
             "agent" => Ok(Role::Agent),
+            "display" => Ok(Role::Display),
+            "key_admin" => Ok(Role::KeyAdmin),
             _ => Err(CError::UnknownRole(value.to_string())),
         }
     }
 }
 
 impl Role {
-    pub(crate) fn permissions(&self) -> HashSet<Permission> {
+    pub fn permissions(&self) -> HashSet<Permission> {
         let permissions = match self {
             Role::Root => vec![
                 Permission::TenantCreate,
@@ -34,7 +40,10 @@ impl Role {
                 Permission::PolicyUpdate,
                 Permission::TokenCreate,
                 Permission::TokenRead,
+                Permission::TokenMask,
             ],
+            Role::Display => vec![Permission::PolicyRead, Permission::TokenMask],
+            Role::KeyAdmin => vec![Permission::KeyRotate, Permission::KeyShred],
         };
         permissions.into_iter().collect()
     }
@@ -53,6 +62,10 @@ pub enum Permission {
     PolicyUpdate,
     TokenCreate,
     TokenRead,
+    TokenMask,
+    //
+    KeyRotate,
+    KeyShred,
 }
 
 impl fmt::Display for Permission {
@@ -63,10 +76,10 @@ impl fmt::Display for Permission {
 
 impl Permission {
     pub fn is_tenant_required(&self) -> bool {
-        match self {
-            Permission::TenantCreate | Permission::TenantRead | Permission::TenantUpdate => false,
-            _ => true,
-        }
+        !matches!(
+            self,
+            Permission::TenantCreate | Permission::TenantRead | Permission::TenantUpdate
+        )
     }
 }
 
@@ -77,7 +90,7 @@ mod tests {
 
     #[test]
     fn role_from_string_case_insensitive() {
-        for t in vec![
+        for t in [
             ("AGENT".to_string(), Role::Agent),
             ("ROOT".to_string(), Role::Root),
         ] {
@@ -88,9 +101,11 @@ mod tests {
 
     #[test]
     fn role_from_string() {
-        for t in vec![
+        for t in [
             ("agent".to_string(), Role::Agent),
             ("root".to_string(), Role::Root),
+            ("display".to_string(), Role::Display),
+            ("key_admin".to_string(), Role::KeyAdmin),
         ] {
             let role: Role = t.0.try_into().unwrap();
             assert_eq!(role, t.1);
@@ -107,35 +122,58 @@ mod tests {
     fn role_permissions_root() {
         let role = Role::Root;
         let perms = role.permissions();
-        assert_eq!(&perms.contains(&TenantCreate), &true);
-        assert_eq!(&perms.contains(&TenantRead), &true);
-        assert_eq!(&perms.contains(&TenantUpdate), &true);
+        assert!(perms.contains(&TenantCreate));
+        assert!(perms.contains(&TenantRead));
+        assert!(perms.contains(&TenantUpdate));
         //
-        assert_eq!(&perms.contains(&AuditMetaRead), &false);
-        assert_eq!(&perms.contains(&PolicyCreate), &false);
+        assert!(!perms.contains(&AuditMetaRead));
+        assert!(!perms.contains(&PolicyCreate));
     }
 
     #[test]
     fn role_permissions_agent() {
         let role = Role::Agent;
         let perms = role.permissions();
-        assert_eq!(&perms.contains(&TenantCreate), &false);
-        assert_eq!(&perms.contains(&TenantRead), &false);
-        assert_eq!(&perms.contains(&TenantUpdate), &false);
+        assert!(!perms.contains(&TenantCreate));
+        assert!(!perms.contains(&TenantRead));
+        assert!(!perms.contains(&TenantUpdate));
+        //
+        assert!(perms.contains(&AuditMetaRead));
+        assert!(perms.contains(&PolicyCreate));
+        assert!(!perms.contains(&KeyRotate));
+        assert!(!perms.contains(&KeyShred));
+    }
+
+    #[test]
+    fn role_permissions_display() {
+        let perms = Role::Display.permissions();
+        assert!(perms.contains(&TokenMask));
+        assert!(perms.contains(&PolicyRead));
+        //
+        assert!(!perms.contains(&TokenCreate));
+        assert!(!perms.contains(&TokenRead));
+    }
+
+    #[test]
+    fn role_permissions_key_admin() {
+        let perms = Role::KeyAdmin.permissions();
+        assert!(perms.contains(&KeyRotate));
+        assert!(perms.contains(&KeyShred));
         //
-        assert_eq!(&perms.contains(&AuditMetaRead), &true);
-        assert_eq!(&perms.contains(&PolicyCreate), &true);
+        assert!(!perms.contains(&TokenCreate));
+        assert!(!perms.contains(&TokenRead));
+        assert!(!perms.contains(&PolicyRead));
     }
 
     #[test]
     fn permission_is_tenant_required() {
-        assert_eq!(TenantCreate.is_tenant_required(), false);
-        assert_eq!(TenantRead.is_tenant_required(), false);
-        assert_eq!(TenantUpdate.is_tenant_required(), false);
+        assert!(!TenantCreate.is_tenant_required());
+        assert!(!TenantRead.is_tenant_required());
+        assert!(!TenantUpdate.is_tenant_required());
         //
-        assert_eq!(AuditMetaRead.is_tenant_required(), true);
-        assert_eq!(PolicyCreate.is_tenant_required(), true);
-        assert_eq!(PolicyUpdate.is_tenant_required(), true);
-        assert_eq!(PolicyRead.is_tenant_required(), true);
+        assert!(AuditMetaRead.is_tenant_required());
+        assert!(PolicyCreate.is_tenant_required());
+        assert!(PolicyUpdate.is_tenant_required());
+        assert!(PolicyRead.is_tenant_required());
     }
 }
@@ -0,0 +1,149 @@
+use aes_gcm::aead::{Aead, KeyInit, OsRng, Payload};
+use aes_gcm::{AeadCore, Aes256Gcm, Key, Nonce};
+use base64::{engine::general_purpose, Engine as _};
+use secrecy::zeroize::Zeroizing;
+use secrecy::{ExposeSecret, Secret};
+
+use crate::core::crypto::{Cipher, CryptoError};
+
+const NONCE_LEN: usize = 12;
+
+/// AES-256-GCM cipher; the random nonce is prepended to the ciphertext.
+#[derive(Clone)]
+pub struct Aes256GcmCipher {
+    cipher: Aes256Gcm,
+}
+
+impl Aes256GcmCipher {
+    pub fn new(key: &[u8]) -> Result<Aes256GcmCipher, CryptoError> {
+        if key.len() != 32 {
+            return Err(CryptoError::InvalidKey(format!(
+                "256 bits key expected, got {} bits",
+                key.len() * 8
+            )));
+        }
+        Ok(Aes256GcmCipher {
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key)),
+        })
+    }
+
+    pub fn from_base64(key: &Secret<String>) -> Result<Aes256GcmCipher, CryptoError> {
+        let raw = general_purpose::STANDARD
+            .decode(key.expose_secret())
+            .map_err(|_| CryptoError::InvalidKey("invalid base64".to_string()))?;
+        Aes256GcmCipher::new(&raw)
+    }
+}
+
+impl Aes256GcmCipher {
+    pub(crate) fn seal(&self, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: plaintext,
+            aad,
+        };
+        let encrypted = self
+            .cipher
+            .encrypt(&nonce, payload)
+            .map_err(|_| CryptoError::EncryptionFailure)?;
+        let mut out = Vec::with_capacity(NONCE_LEN + encrypted.len());
+        out.extend_from_slice(nonce.as_slice());
+        out.extend_from_slice(&encrypted);
+        Ok(out)
+    }
+
+    /// The plaintext is zeroized when dropped
+    pub(crate) fn open(
+        &self,
+        ciphertext: &[u8],
+        aad: &[u8],
+    ) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+        if ciphertext.len() < NONCE_LEN {
+            return Err(CryptoError::DecryptionFailure);
+        }
+        let (nonce, encrypted) = ciphertext.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: encrypted,
+            aad,
+        };
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map(Zeroizing::new)
+            .map_err(|_| CryptoError::DecryptionFailure)
+    }
+}
+
+impl Cipher for Aes256GcmCipher {
+    fn encrypt(&self, plaintext: &Secret<String>, aad: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        self.seal(plaintext.expose_secret().as_bytes(), aad)
+    }
+
+    fn decrypt(&self, ciphertext: &[u8], aad: &[u8]) -> Result<Secret<String>, CryptoError> {
+        let decrypted = self.open(ciphertext, aad)?;
+        std::str::from_utf8(&decrypted)
+            .map(|value| Secret::new(value.to_string()))
+            .map_err(|_| CryptoError::DecryptionFailure)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_cipher() -> Aes256GcmCipher {
+        Aes256GcmCipher::new(&[7u8; 32]).unwrap()
+    }
+
+    #[test]
+    fn encrypt_decrypt_roundtrip() {
+        let cipher = sample_cipher();
+        let encrypted = cipher
+            .encrypt(
+                &Secret::new("CARMEN MCCALLUM".to_string()),
+                b"1:sales:TOK-1",
+            )
+            .unwrap();
+        let decrypted = cipher.decrypt(&encrypted, b"1:sales:TOK-1").unwrap();
+        assert_eq!(decrypted.expose_secret(), "CARMEN MCCALLUM");
+    }
+
+    #[test]
+    fn encrypt_uses_a_random_nonce() {
+        let cipher = sample_cipher();
+        let value = Secret::new("CARMEN MCCALLUM".to_string());
+        assert_ne!(
+            cipher.encrypt(&value, b"").unwrap(),
+            cipher.encrypt(&value, b"").unwrap()
+        );
+    }
+
+    #[test]
+    fn decrypt_with_other_associated_data_fails() {
+        let cipher = sample_cipher();
+        let encrypted = cipher
+            .encrypt(
+                &Secret::new("CARMEN MCCALLUM".to_string()),
+                b"1:sales:TOK-1",
+            )
+            .unwrap();
+        assert_eq!(
+            cipher.decrypt(&encrypted, b"2:sales:TOK-1").err(),
+            Some(CryptoError::DecryptionFailure)
+        );
+    }
+
+    #[test]
+    fn invalid_key_length() {
+        assert!(matches!(
+            Aes256GcmCipher::new(&[7u8; 16]),
+            Err(CryptoError::InvalidKey(_))
+        ));
+    }
+
+    #[test]
+    fn from_base64_key() {
+        let key = Secret::new(general_purpose::STANDARD.encode([3u8; 32]));
+        assert!(Aes256GcmCipher::from_base64(&key).is_ok());
+        assert!(Aes256GcmCipher::from_base64(&Secret::new("@@@".to_string())).is_err());
+    }
+}
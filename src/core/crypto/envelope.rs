@@ -0,0 +1,240 @@
+use secrecy::Secret;
+use std::fmt;
+
+use crate::core::crypto::{random_key, Aes256GcmCipher, Cipher, CryptoError, KeyProvider};
+
+/// Envelope encryption: each tenant encrypts its values with its own data
+/// encryption key (DEK), which is only stored wrapped by the `KeyProvider`.
+///
+/// The tenant is bound to the wrapped key: it cannot be unwrapped for another tenant.
+/// The key schedule is zeroized on drop.
+pub struct DataKey {
+    cipher: Aes256GcmCipher,
+}
+
+impl DataKey {
+    fn new(key: &[u8]) -> Result<DataKey, CryptoError> {
+        Ok(DataKey {
+            cipher: Aes256GcmCipher::new(key)?,
+        })
+    }
+
+    /// A new random data key of `tenant`, along with its wrapped form to store
+    pub async fn generate(
+        provider: &dyn KeyProvider,
+        tenant: &str,
+    ) -> Result<(DataKey, Vec<u8>), CryptoError> {
+        let key = random_key();
+        let wrapped = provider.wrap(key.as_ref(), &wrapping_data(tenant)).await?;
+        Ok((DataKey::new(key.as_ref())?, wrapped))
+    }
+
+    pub async fn unwrap(
+        provider: &dyn KeyProvider,
+        tenant: &str,
+        wrapped: &[u8],
+    ) -> Result<DataKey, CryptoError> {
+        let key = provider.unwrap(wrapped, &wrapping_data(tenant)).await?;
+        DataKey::new(&key)
+    }
+
+    /// Wraps the data key again, with the latest version of the master key
+    pub async fn rewrap(
+        provider: &dyn KeyProvider,
+        tenant: &str,
+        wrapped: &[u8],
+    ) -> Result<Vec<u8>, CryptoError> {
+        let aad = wrapping_data(tenant);
+        let key = provider.unwrap(wrapped, &aad).await?;
+        provider.wrap(&key, &aad).await
+    }
+
+    /// A new random data key of `subject`, along with its form wrapped by this
+    /// (tenant) data key: destroying the latter erases every value of the subject.
+    pub fn generate_subject_key(
+        &self,
+        tenant: &str,
+        subject: &str,
+    ) -> Result<(DataKey, Vec<u8>), CryptoError> {
+        let key = random_key();
+        let wrapped = self
+            .cipher
+            .seal(key.as_ref(), &subject_wrapping_data(tenant, subject))?;
+        Ok((DataKey::new(key.as_ref())?, wrapped))
+    }
+
+    pub fn unwrap_subject_key(
+        &self,
+        tenant: &str,
+        subject: &str,
+        wrapped: &[u8],
+    ) -> Result<DataKey, CryptoError> {
+        let key = self
+            .cipher
+            .open(wrapped, &subject_wrapping_data(tenant, subject))?;
+        DataKey::new(&key)
+    }
+
+    /// Wraps again with this (tenant) data key a subject key wrapped by `previous`
+    pub fn rewrap_subject_key(
+        &self,
+        previous: &DataKey,
+        tenant: &str,
+        subject: &str,
+        wrapped: &[u8],
+    ) -> Result<Vec<u8>, CryptoError> {
+        let aad = subject_wrapping_data(tenant, subject);
+        let key = previous.cipher.open(wrapped, &aad)?;
+        self.cipher.seal(&key, &aad)
+    }
+}
+
+fn wrapping_data(tenant: &str) -> Vec<u8> {
+    format!("dek:{tenant}").into_bytes()
+}
+
+fn subject_wrapping_data(tenant: &str, subject: &str) -> Vec<u8> {
+    format!("sdk:{tenant}:{subject}").into_bytes()
+}
+
+impl fmt::Debug for DataKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DataKey").finish_non_exhaustive()
+    }
+}
+
+impl Cipher for DataKey {
+    fn encrypt(&self, plaintext: &Secret<String>, aad: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        self.cipher.encrypt(plaintext, aad)
+    }
+
+    fn decrypt(&self, ciphertext: &[u8], aad: &[u8]) -> Result<Secret<String>, CryptoError> {
+        self.cipher.decrypt(ciphertext, aad)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::crypto::Keyring;
+    use secrecy::ExposeSecret;
+
+    fn sample_provider() -> Keyring {
+        Keyring::new(&[&[9u8; 32]]).unwrap()
+    }
+
+    #[tokio::test]
+    async fn unwrapped_data_key_decrypts_the_values() {
+        let provider = sample_provider();
+        let (key, wrapped) = DataKey::generate(&provider, "1").await.unwrap();
+        let encrypted = key
+            .encrypt(&Secret::new("CARMEN".to_string()), b"1:sales:TOK-1")
+            .unwrap();
+
+        let key = DataKey::unwrap(&provider, "1", &wrapped).await.unwrap();
+        let decrypted = key.decrypt(&encrypted, b"1:sales:TOK-1").unwrap();
+        assert_eq!(decrypted.expose_secret(), "CARMEN");
+    }
+
+    #[tokio::test]
+    async fn data_keys_are_random() {
+        let provider = sample_provider();
+        let (key, _) = DataKey::generate(&provider, "1").await.unwrap();
+        let (other, _) = DataKey::generate(&provider, "1").await.unwrap();
+        let encrypted = key
+            .encrypt(&Secret::new("CARMEN".to_string()), b"")
+            .unwrap();
+        assert_eq!(
+            other.decrypt(&encrypted, b"").err(),
+            Some(CryptoError::DecryptionFailure)
+        );
+    }
+
+    #[tokio::test]
+    async fn data_key_is_bound_to_its_tenant_and_kek() {
+        let provider = sample_provider();
+        let (_, wrapped) = DataKey::generate(&provider, "1").await.unwrap();
+        assert_eq!(
+            DataKey::unwrap(&provider, "2", &wrapped).await.err(),
+            Some(CryptoError::DecryptionFailure)
+        );
+        let other = Keyring::new(&[&[8u8; 32]]).unwrap();
+        assert_eq!(
+            DataKey::unwrap(&other, "1", &wrapped).await.err(),
+            Some(CryptoError::DecryptionFailure)
+        );
+    }
+
+    #[tokio::test]
+    async fn rewrapped_data_key_uses_the_latest_master_key() {
+        let mut keyring = sample_provider();
+        let (key, wrapped) = DataKey::generate(&keyring, "1").await.unwrap();
+        let encrypted = key
+            .encrypt(&Secret::new("CARMEN".to_string()), b"")
+            .unwrap();
+        keyring.add_version(&[8u8; 32]).unwrap();
+
+        let rewrapped = DataKey::rewrap(&keyring, "1", &wrapped).await.unwrap();
+        assert_eq!(&rewrapped[..4], &2u32.to_be_bytes());
+        let key = DataKey::unwrap(&keyring, "1", &rewrapped).await.unwrap();
+        assert_eq!(
+            key.decrypt(&encrypted, b"").unwrap().expose_secret(),
+            "CARMEN"
+        );
+    }
+
+    #[tokio::test]
+    async fn subject_key_is_bound_to_its_subject_and_tenant_key() {
+        let provider = sample_provider();
+        let (tenant_key, _) = DataKey::generate(&provider, "1").await.unwrap();
+        let (key, wrapped) = tenant_key.generate_subject_key("1", "alice").unwrap();
+        let encrypted = key
+            .encrypt(&Secret::new("CARMEN".to_string()), b"")
+            .unwrap();
+
+        let key = tenant_key
+            .unwrap_subject_key("1", "alice", &wrapped)
+            .unwrap();
+        assert_eq!(
+            key.decrypt(&encrypted, b"").unwrap().expose_secret(),
+            "CARMEN"
+        );
+        assert_eq!(
+            tenant_key.unwrap_subject_key("1", "bob", &wrapped).err(),
+            Some(CryptoError::DecryptionFailure)
+        );
+        let (other, _) = DataKey::generate(&provider, "1").await.unwrap();
+        assert_eq!(
+            other.unwrap_subject_key("1", "alice", &wrapped).err(),
+            Some(CryptoError::DecryptionFailure)
+        );
+    }
+
+    #[tokio::test]
+    async fn subject_key_is_rewrapped_with_another_tenant_key() {
+        let provider = sample_provider();
+        let (previous, _) = DataKey::generate(&provider, "1").await.unwrap();
+        let (current, _) = DataKey::generate(&provider, "1").await.unwrap();
+        let (key, wrapped) = previous.generate_subject_key("1", "alice").unwrap();
+        let encrypted = key
+            .encrypt(&Secret::new("CARMEN".to_string()), b"")
+            .unwrap();
+
+        let rewrapped = current
+            .rewrap_subject_key(&previous, "1", "alice", &wrapped)
+            .unwrap();
+        let key = current
+            .unwrap_subject_key("1", "alice", &rewrapped)
+            .unwrap();
+        assert_eq!(
+            key.decrypt(&encrypted, b"").unwrap().expose_secret(),
+            "CARMEN"
+        );
+        assert_eq!(
+            current
+                .rewrap_subject_key(&previous, "1", "bob", &wrapped)
+                .err(),
+            Some(CryptoError::DecryptionFailure)
+        );
+    }
+}
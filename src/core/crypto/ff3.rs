@@ -0,0 +1,268 @@
+use aes::cipher::{generic_array::GenericArray, BlockEncrypt, KeyInit};
+
+use crate::core::crypto::CryptoError;
+
+/// Smallest domain (radix^len) accepted, as recommended by NIST SP 800-38G Rev. 1
+const MIN_DOMAIN_SIZE: u128 = 1_000_000;
+
+/// FF3-1 format preserving encryption (NIST SP 800-38G Rev. 1) over numeral strings.
+pub(crate) struct FF31<C> {
+    cipher: C,
+    radix: u32,
+    min_len: usize,
+    max_len: usize,
+}
+
+impl<C> FF31<C>
+where
+    C: BlockEncrypt + KeyInit,
+{
+    pub fn new(key: &[u8], radix: u32) -> Result<FF31<C>, CryptoError> {
+        if !(2..=(1 << 16)).contains(&radix) {
+            return Err(CryptoError::InvalidInput(format!(
+                "radix {} is not in the range 2..=65536",
+                radix
+            )));
+        }
+        // the cipher is keyed with the reversed key
+        let reversed: Vec<u8> = key.iter().rev().copied().collect();
+        let cipher = C::new_from_slice(&reversed).map_err(|_| {
+            CryptoError::InvalidKey(format!("unsupported key size: {} bits", key.len() * 8))
+        })?;
+        Ok(FF31 {
+            cipher,
+            radix,
+            min_len: min_len(radix),
+            // 2 * floor(log_radix(2^96))
+            max_len: 2 * max_exponent(radix, 1 << 96),
+        })
+    }
+
+    pub fn encrypt(&self, tweak: &[u8; 7], x: &[u16]) -> Result<Vec<u16>, CryptoError> {
+        let (left, right) = split_tweak(tweak);
+        self.encrypt_with(&left, &right, x)
+    }
+
+    pub fn decrypt(&self, tweak: &[u8; 7], x: &[u16]) -> Result<Vec<u16>, CryptoError> {
+        let (left, right) = split_tweak(tweak);
+        self.decrypt_with(&left, &right, x)
+    }
+
+    fn encrypt_with(
+        &self,
+        tweak_left: &[u8; 4],
+        tweak_right: &[u8; 4],
+        x: &[u16],
+    ) -> Result<Vec<u16>, CryptoError> {
+        self.check(x)?;
+        let u = x.len().div_ceil(2);
+        let v = x.len() - u;
+        let (mut a, mut b) = (x[..u].to_vec(), x[u..].to_vec());
+        for i in 0..8u8 {
+            let (m, w) = if i % 2 == 0 {
+                (u, tweak_right)
+            } else {
+                (v, tweak_left)
+            };
+            let modulus = (self.radix as u128).pow(m as u32);
+            let y = self.round(w, i, &b) % modulus;
+            let c = (self.num_rev(&a) + y) % modulus;
+            a = b;
+            b = self.str_rev(c, m);
+        }
+        a.extend(b);
+        Ok(a)
+    }
+
+    fn decrypt_with(
+        &self,
+        tweak_left: &[u8; 4],
+        tweak_right: &[u8; 4],
+        x: &[u16],
+    ) -> Result<Vec<u16>, CryptoError> {
+        self.check(x)?;
+        let u = x.len().div_ceil(2);
+        let v = x.len() - u;
+        let (mut a, mut b) = (x[..u].to_vec(), x[u..].to_vec());
+        for i in (0..8u8).rev() {
+            let (m, w) = if i % 2 == 0 {
+                (u, tweak_right)
+            } else {
+                (v, tweak_left)
+            };
+            let modulus = (self.radix as u128).pow(m as u32);
+            let y = self.round(w, i, &a) % modulus;
+            let c = (self.num_rev(&b) + modulus - y) % modulus;
+            b = a;
+            a = self.str_rev(c, m);
+        }
+        a.extend(b);
+        Ok(a)
+    }
+
+    fn check(&self, x: &[u16]) -> Result<(), CryptoError> {
+        if x.len() < self.min_len || x.len() > self.max_len {
+            return Err(CryptoError::InvalidInput(format!(
+                "length must be between {} and {}, got {}",
+                self.min_len,
+                self.max_len,
+                x.len()
+            )));
+        }
+        if x.iter().any(|d| *d as u32 >= self.radix) {
+            return Err(CryptoError::InvalidInput(format!(
+                "numeral out of radix {}",
+                self.radix
+            )));
+        }
+        Ok(())
+    }
+
+    /// S = REVB(CIPH_REVB(K)(REVB(P))) with P = W xor [i]^4 || [NUM_radix(REV(B))]^12
+    fn round(&self, w: &[u8; 4], i: u8, b: &[u16]) -> u128 {
+        let mut p = [0u8; 16];
+        p[..4].copy_from_slice(w);
+        p[3] ^= i;
+        p[4..].copy_from_slice(&self.num_rev(b).to_be_bytes()[4..]);
+        p.reverse();
+        let mut block = GenericArray::clone_from_slice(&p);
+        self.cipher.encrypt_block(&mut block);
+        let mut s = [0u8; 16];
+        s.copy_from_slice(&block);
+        s.reverse();
+        u128::from_be_bytes(s)
+    }
+
+    /// NUM_radix(REV(x)): the first numeral is the least significant one
+    fn num_rev(&self, x: &[u16]) -> u128 {
+        x.iter()
+            .rev()
+            .fold(0u128, |acc, d| acc * self.radix as u128 + *d as u128)
+    }
+
+    /// REV(STR^m_radix(c))
+    fn str_rev(&self, mut c: u128, m: usize) -> Vec<u16> {
+        let radix = self.radix as u128;
+        (0..m)
+            .map(|_| {
+                let d = (c % radix) as u16;
+                c /= radix;
+                d
+            })
+            .collect()
+    }
+}
+
+/// T_L = T[0..27] || 0^4, T_R = T[32..55] || T[28..31] || 0^4
+fn split_tweak(tweak: &[u8; 7]) -> ([u8; 4], [u8; 4]) {
+    (
+        [tweak[0], tweak[1], tweak[2], tweak[3] & 0xF0],
+        [tweak[4], tweak[5], tweak[6], (tweak[3] & 0x0F) << 4],
+    )
+}
+
+/// Smallest length such that radix^len >= MIN_DOMAIN_SIZE
+fn min_len(radix: u32) -> usize {
+    let mut len = 1;
+    let mut domain = radix as u128;
+    while domain < MIN_DOMAIN_SIZE {
+        domain *= radix as u128;
+        len += 1;
+    }
+    len
+}
+
+/// Largest k such that radix^k <= bound
+fn max_exponent(radix: u32, bound: u128) -> usize {
+    let mut k = 0;
+    let mut value = 1u128;
+    while value <= bound / radix as u128 {
+        value *= radix as u128;
+        k += 1;
+    }
+    k
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes::{Aes128, Aes256};
+
+    fn hex(value: &str) -> Vec<u8> {
+        (0..value.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&value[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn digits(value: &str) -> Vec<u16> {
+        value
+            .chars()
+            .map(|c| c.to_digit(10).unwrap() as u16)
+            .collect()
+    }
+
+    fn tweak64(value: &str) -> ([u8; 4], [u8; 4]) {
+        let raw = hex(value);
+        (raw[..4].try_into().unwrap(), raw[4..].try_into().unwrap())
+    }
+
+    // NIST FF3 samples (64 bits tweak), the Feistel rounds being shared with FF3-1
+    #[test]
+    fn nist_ff3_sample_aes128() {
+        let ff3 = FF31::<Aes128>::new(&hex("EF4359D8D580AA4F7F036D6F04FC6A94"), 10).unwrap();
+        let (left, right) = tweak64("D8E7920AFA330A73");
+        let pt = digits("890121234567890000");
+        let ct = ff3.encrypt_with(&left, &right, &pt).unwrap();
+        assert_eq!(ct, digits("750918814058654607"));
+        assert_eq!(ff3.decrypt_with(&left, &right, &ct).unwrap(), pt);
+    }
+
+    #[test]
+    fn nist_ff3_sample_aes256() {
+        let ff3 = FF31::<Aes256>::new(
+            &hex("EF4359D8D580AA4F7F036D6F04FC6A942B7E151628AED2A6ABF7158809CF4F3C"),
+            10,
+        )
+        .unwrap();
+        let (left, right) = tweak64("D8E7920AFA330A73");
+        let pt = digits("890121234567890000");
+        let ct = ff3.encrypt_with(&left, &right, &pt).unwrap();
+        assert_eq!(ct, digits("922011205562777495"));
+        assert_eq!(ff3.decrypt_with(&left, &right, &ct).unwrap(), pt);
+    }
+
+    // FF3-1 sample (56 bits tweak) from the reference python implementation
+    #[test]
+    fn ff3_1_sample() {
+        let ff3 = FF31::<Aes128>::new(&hex("2DE79D232DF5585D68CE47882AE256D6"), 10).unwrap();
+        let tweak: [u8; 7] = hex("CBD09280979564").try_into().unwrap();
+        let pt = digits("3992520240");
+        let ct = ff3.encrypt(&tweak, &pt).unwrap();
+        assert_eq!(ct, digits("8901801106"));
+        assert_eq!(ff3.decrypt(&tweak, &ct).unwrap(), pt);
+    }
+
+    #[test]
+    fn ff3_1_round_trip() {
+        let ff3 = FF31::<Aes256>::new(&[7u8; 32], 36).unwrap();
+        let tweak = [1, 2, 3, 4, 5, 6, 7];
+        let pt: Vec<u16> = (0..20).map(|i| (i * 7 % 36) as u16).collect();
+        let ct = ff3.encrypt(&tweak, &pt).unwrap();
+        assert_eq!(ct.len(), pt.len());
+        assert_ne!(ct, pt);
+        assert_eq!(ff3.decrypt(&tweak, &ct).unwrap(), pt);
+        assert_ne!(ff3.encrypt(&[1, 2, 3, 4, 5, 6, 8], &pt).unwrap(), ct);
+    }
+
+    #[test]
+    fn length_is_bounded() {
+        let ff3 = FF31::<Aes256>::new(&[7u8; 32], 10).unwrap();
+        let tweak = [0u8; 7];
+        assert!(ff3.encrypt(&tweak, &digits("12345")).is_err());
+        assert!(ff3.encrypt(&tweak, &digits("123456")).is_ok());
+        assert!(ff3.encrypt(&tweak, &[1u16; 56]).is_ok());
+        assert!(ff3.encrypt(&tweak, &[1u16; 57]).is_err());
+        assert!(ff3.encrypt(&tweak, &[1, 2, 3, 4, 5, 10]).is_err());
+    }
+}
@@ -0,0 +1,103 @@
+use hmac::Mac;
+use secrecy::{ExposeSecret, Secret};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::core::crypto::keyed::HmacKey;
+use crate::core::crypto::CryptoError;
+
+/// Keyed (HMAC-SHA256) index of the values, allowing to look a value up
+/// without storing it in clear.
+///
+/// Values are normalized (unicode NFC) beforehand, so that canonically
+/// equivalent strings share the same index.
+pub struct BlindIndex {
+    key: HmacKey,
+}
+
+impl BlindIndex {
+    pub fn new(key: &[u8]) -> Result<BlindIndex, CryptoError> {
+        Ok(BlindIndex {
+            key: HmacKey::new(key)?,
+        })
+    }
+
+    pub fn from_base64(key: &Secret<String>) -> Result<BlindIndex, CryptoError> {
+        Ok(BlindIndex {
+            key: HmacKey::from_base64(key)?,
+        })
+    }
+
+    /// Computes the index of `value` within the given `scope` (e.g. tenant and policy);
+    /// the same value gets unrelated indexes in different scopes.
+    pub fn compute(&self, scope: &[&str], value: &Secret<String>) -> Vec<u8> {
+        let mut mac = self.key.mac(scope);
+        let normalized: String = value.expose_secret().nfc().collect();
+        mac.update(normalized.as_bytes());
+        mac.finalize().into_bytes().to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_index() -> BlindIndex {
+        BlindIndex::new(&[9u8; 32]).unwrap()
+    }
+
+    fn secret(value: &str) -> Secret<String> {
+        Secret::new(value.to_string())
+    }
+
+    #[test]
+    fn same_value_same_index() {
+        let index = sample_index();
+        assert_eq!(
+            index.compute(&["1", "sales"], &secret("CARMEN")),
+            index.compute(&["1", "sales"], &secret("CARMEN"))
+        );
+        assert_ne!(
+            index.compute(&["1", "sales"], &secret("CARMEN")),
+            index.compute(&["1", "sales"], &secret("CARMEM"))
+        );
+    }
+
+    #[test]
+    fn index_depends_on_scope() {
+        let index = sample_index();
+        let value = secret("CARMEN");
+        assert_ne!(
+            index.compute(&["1", "sales"], &value),
+            index.compute(&["2", "sales"], &value)
+        );
+        assert_ne!(
+            index.compute(&["1", "sales"], &value),
+            index.compute(&["1s", "ales"], &value)
+        );
+    }
+
+    #[test]
+    fn index_depends_on_key() {
+        let other = BlindIndex::new(&[8u8; 32]).unwrap();
+        let value = secret("CARMEN");
+        assert_ne!(
+            sample_index().compute(&["1", "sales"], &value),
+            other.compute(&["1", "sales"], &value)
+        );
+    }
+
+    #[test]
+    fn canonically_equivalent_values_share_the_index() {
+        let index = sample_index();
+        // "é" precomposed vs "e" followed by a combining acute accent
+        assert_eq!(
+            index.compute(&["1", "sales"], &secret("Ren\u{00e9}")),
+            index.compute(&["1", "sales"], &secret("Rene\u{0301}"))
+        );
+    }
+
+    #[test]
+    fn short_key_is_rejected() {
+        assert!(BlindIndex::new(&[9u8; 16]).is_err());
+    }
+}
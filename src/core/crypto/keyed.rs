@@ -0,0 +1,96 @@
+use base64::{engine::general_purpose, Engine as _};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+
+use crate::core::crypto::CryptoError;
+
+pub(crate) type HmacSha256 = Hmac<Sha256>;
+
+/// Secret key of the keyed (HMAC-SHA256) constructions, from which the
+/// per-tenant keys are derived.
+pub(crate) struct HmacKey(Secret<Vec<u8>>);
+
+impl HmacKey {
+    pub(crate) fn new(key: &[u8]) -> Result<HmacKey, CryptoError> {
+        if key.len() < 32 {
+            return Err(CryptoError::InvalidKey(format!(
+                "at least 256 bits key expected, got {} bits",
+                key.len() * 8
+            )));
+        }
+        Ok(HmacKey(Secret::new(key.to_vec())))
+    }
+
+    pub(crate) fn from_base64(key: &Secret<String>) -> Result<HmacKey, CryptoError> {
+        let raw = general_purpose::STANDARD
+            .decode(key.expose_secret())
+            .map_err(|_| CryptoError::InvalidKey("invalid base64".to_string()))?;
+        HmacKey::new(&raw)
+    }
+
+    /// HMAC keyed with this key, over the length prefixed `scope`
+    pub(crate) fn mac(&self, scope: &[&str]) -> HmacSha256 {
+        scoped_mac(self.0.expose_secret(), scope)
+    }
+
+    /// Key of `tenant`, HMAC(key, `label` || tenant)
+    pub(crate) fn tenant_key(&self, label: &str, tenant: &str) -> Secret<Vec<u8>> {
+        let mut mac = scoped_mac(self.0.expose_secret(), &[]);
+        mac.update(label.as_bytes());
+        mac.update(tenant.as_bytes());
+        Secret::new(mac.finalize().into_bytes().to_vec())
+    }
+
+    /// Key of the length prefixed `scope`, HMAC(key, `label` || scope)
+    pub(crate) fn scoped_key(&self, label: &str, scope: &[&str]) -> Secret<Vec<u8>> {
+        let mut mac = scoped_mac(self.0.expose_secret(), &[]);
+        mac.update(label.as_bytes());
+        update_scope(&mut mac, scope);
+        Secret::new(mac.finalize().into_bytes().to_vec())
+    }
+}
+
+/// HMAC keyed with `key`, over the length prefixed `scope`
+pub(crate) fn scoped_mac(key: &[u8], scope: &[&str]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any size");
+    update_scope(&mut mac, scope);
+    mac
+}
+
+fn update_scope(mac: &mut HmacSha256, scope: &[&str]) {
+    for part in scope {
+        // length prefixed to prevent ambiguities between ("ab", "c") and ("a", "bc")
+        mac.update(&(part.len() as u64).to_be_bytes());
+        mac.update(part.as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_or_invalid_key_is_rejected() {
+        assert!(HmacKey::new(&[1u8; 16]).is_err());
+        assert!(HmacKey::from_base64(&Secret::new("@@@".to_string())).is_err());
+    }
+
+    #[test]
+    fn tenant_keys_depend_on_label_and_tenant() {
+        let key = HmacKey::new(&[1u8; 32]).unwrap();
+        let derived =
+            |label: &str, tenant: &str| key.tenant_key(label, tenant).expose_secret().clone();
+        assert_ne!(derived("a:", "1"), derived("b:", "1"));
+        assert_ne!(derived("a:", "1"), derived("a:", "2"));
+    }
+
+    #[test]
+    fn scoped_keys_depend_on_label_and_scope() {
+        let key = HmacKey::new(&[1u8; 32]).unwrap();
+        let derived =
+            |label: &str, scope: &[&str]| key.scoped_key(label, scope).expose_secret().clone();
+        assert_ne!(derived("a:", &["1"]), derived("b:", &["1"]));
+        assert_ne!(derived("a:", &["1", "2"]), derived("a:", &["12"]));
+    }
+}
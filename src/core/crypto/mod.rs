@@ -0,0 +1,45 @@
+mod aes;
+mod envelope;
+mod ff3;
+mod fpe;
+mod index;
+mod keyed;
+mod offset;
+mod permutation;
+mod provider;
+mod pseudonym;
+
+pub use self::aes::Aes256GcmCipher;
+pub use self::fpe::{FpeAlgorithm, FpeCipher};
+pub use envelope::DataKey;
+pub use index::BlindIndex;
+pub use offset::KeyedOffset;
+pub use permutation::KeyedPermutation;
+pub use provider::{random_key, KeyProvider, Keyring};
+pub use pseudonym::Pseudonymizer;
+
+use secrecy::Secret;
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq, Clone)]
+pub enum CryptoError {
+    #[error("Invalid key: {0}")]
+    InvalidKey(String),
+    #[error("Encryption failed")]
+    EncryptionFailure,
+    #[error("Decryption failed")]
+    DecryptionFailure,
+    #[error("Invalid input: {0}")]
+    InvalidInput(String),
+    #[error("Key provider failure: {0}")]
+    ProviderFailure(String),
+}
+
+/// Authenticated encryption of the values kept in the vault.
+///
+/// `aad` (associated data) is authenticated but not encrypted: decryption
+/// fails if it differs from the one given at encryption time.
+pub trait Cipher: Send + Sync {
+    fn encrypt(&self, plaintext: &Secret<String>, aad: &[u8]) -> Result<Vec<u8>, CryptoError>;
+    fn decrypt(&self, ciphertext: &[u8], aad: &[u8]) -> Result<Secret<String>, CryptoError>;
+}
@@ -0,0 +1,96 @@
+use hmac::Mac;
+use secrecy::{ExposeSecret, Secret};
+
+use crate::core::crypto::keyed::{scoped_mac, HmacKey};
+use crate::core::crypto::CryptoError;
+
+/// Keyed (HMAC-SHA256) derivation of deterministic non zero offsets, e.g. to shift dates.
+///
+/// Offsets of each tenant derive from a subkey of the given key.
+pub struct KeyedOffset {
+    key: HmacKey,
+}
+
+impl KeyedOffset {
+    pub fn new(key: &[u8]) -> Result<KeyedOffset, CryptoError> {
+        Ok(KeyedOffset {
+            key: HmacKey::new(key)?,
+        })
+    }
+
+    pub fn from_base64(key: &Secret<String>) -> Result<KeyedOffset, CryptoError> {
+        Ok(KeyedOffset {
+            key: HmacKey::from_base64(key)?,
+        })
+    }
+
+    /// Offset in `-window..=window`, never 0, derived from the tenant key and the
+    /// `scope` (e.g. policy and subject)
+    pub fn offset(&self, tenant: &str, scope: &[&str], window: u32) -> Result<i64, CryptoError> {
+        if window == 0 {
+            return Err(CryptoError::InvalidInput(
+                "offset window must not be empty".to_string(),
+            ));
+        }
+        let mac = scoped_mac(
+            self.key.tenant_key("offset:", tenant).expose_secret(),
+            scope,
+        );
+        let digest = mac.finalize().into_bytes();
+        let n = u64::from_be_bytes(digest[..8].try_into().expect("digest is 32 bytes long"));
+        // the modulo bias is negligible for windows far below 2^64
+        let magnitude = (n >> 1) % window as u64 + 1;
+        Ok(if n & 1 == 0 {
+            magnitude as i64
+        } else {
+            -(magnitude as i64)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_offset() -> KeyedOffset {
+        KeyedOffset::new(&[6u8; 32]).unwrap()
+    }
+
+    #[test]
+    fn offset_is_deterministic_and_within_window() {
+        let offset = sample_offset();
+        for subject in 0..200 {
+            let subject = subject.to_string();
+            let value = offset.offset("1", &["births", &subject], 30).unwrap();
+            assert!(value != 0 && (-30..=30).contains(&value));
+            assert_eq!(
+                offset.offset("1", &["births", &subject], 30).unwrap(),
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn offset_depends_on_tenant_and_scope() {
+        let offset = sample_offset();
+        let offsets = |tenant: &str, policy: &str| {
+            (0..20)
+                .map(|s| {
+                    offset
+                        .offset(tenant, &[policy, &s.to_string()], 3650)
+                        .unwrap()
+                })
+                .collect::<Vec<i64>>()
+        };
+        assert_ne!(offsets("1", "births"), offsets("2", "births"));
+        assert_ne!(offsets("1", "births"), offsets("1", "events"));
+    }
+
+    #[test]
+    fn empty_window_is_rejected() {
+        assert!(matches!(
+            sample_offset().offset("1", &["births"], 0),
+            Err(CryptoError::InvalidInput(_))
+        ));
+    }
+}
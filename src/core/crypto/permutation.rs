@@ -0,0 +1,185 @@
+use hmac::Mac;
+use secrecy::{ExposeSecret, Secret};
+use std::fmt;
+
+use crate::core::crypto::keyed::{scoped_mac, HmacKey, HmacSha256};
+use crate::core::crypto::CryptoError;
+
+const ROUNDS: u8 = 8;
+
+/// Keyed bijection over `0..domain`: a balanced Feistel network over the smallest
+/// even number of bits covering the domain, cycle walking until the result falls
+/// back into it.
+///
+/// Tenants get unrelated permutations, keyed with subkeys of the given key.
+pub struct KeyedPermutation {
+    key: HmacKey,
+}
+
+impl KeyedPermutation {
+    pub fn new(key: &[u8]) -> Result<KeyedPermutation, CryptoError> {
+        Ok(KeyedPermutation {
+            key: HmacKey::new(key)?,
+        })
+    }
+
+    pub fn from_base64(key: &Secret<String>) -> Result<KeyedPermutation, CryptoError> {
+        Ok(KeyedPermutation {
+            key: HmacKey::from_base64(key)?,
+        })
+    }
+
+    /// Maps `value` to another value of `0..domain`; `tweak` (e.g. the policy)
+    /// selects another permutation for the same tenant.
+    pub fn permute(
+        &self,
+        tenant: &str,
+        tweak: &str,
+        value: u64,
+        domain: u64,
+    ) -> Result<u64, CryptoError> {
+        self.walk(tenant, tweak, value, domain, true)
+    }
+
+    pub fn unpermute(
+        &self,
+        tenant: &str,
+        tweak: &str,
+        value: u64,
+        domain: u64,
+    ) -> Result<u64, CryptoError> {
+        self.walk(tenant, tweak, value, domain, false)
+    }
+
+    fn walk(
+        &self,
+        tenant: &str,
+        tweak: &str,
+        value: u64,
+        domain: u64,
+        forward: bool,
+    ) -> Result<u64, CryptoError> {
+        if value >= domain {
+            return Err(CryptoError::InvalidInput(format!(
+                "{} is out of the domain 0..{}",
+                value, domain
+            )));
+        }
+        let half_bits = (u64::BITS - (domain - 1).leading_zeros())
+            .div_ceil(2)
+            .max(1);
+        let mac = scoped_mac(
+            self.key.tenant_key("permutation:", tenant).expose_secret(),
+            &[tweak],
+        );
+
+        // the network works over 2^(2 * half_bits) < 4 * domain values
+        let mut x = value;
+        loop {
+            x = feistel(&mac, x, half_bits, forward);
+            if x < domain {
+                return Ok(x);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for KeyedPermutation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("KeyedPermutation").finish_non_exhaustive()
+    }
+}
+
+fn feistel(mac: &HmacSha256, x: u64, half_bits: u32, forward: bool) -> u64 {
+    let mask = (1u64 << half_bits) - 1;
+    let round = |i: u8, half: u64| {
+        let mut mac = mac.clone();
+        mac.update(&[i]);
+        mac.update(&half.to_be_bytes());
+        let digest = mac.finalize().into_bytes();
+        u64::from_be_bytes(digest[..8].try_into().expect("digest is 32 bytes long")) & mask
+    };
+    let (mut left, mut right) = (x >> half_bits, x & mask);
+    if forward {
+        for i in 0..ROUNDS {
+            (left, right) = (right, left ^ round(i, right));
+        }
+    } else {
+        for i in (0..ROUNDS).rev() {
+            (left, right) = (right ^ round(i, left), left);
+        }
+    }
+    (left << half_bits) | right
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn sample_permutation() -> KeyedPermutation {
+        KeyedPermutation::new(&[4u8; 32]).unwrap()
+    }
+
+    #[test]
+    fn permutation_is_a_bijection_over_the_domain() {
+        let permutation = sample_permutation();
+        for domain in [1, 2, 10, 1000, 1234] {
+            let permuted: HashSet<u64> = (0..domain)
+                .map(|v| permutation.permute("1", "sales", v, domain).unwrap())
+                .collect();
+            assert_eq!(permuted.len() as u64, domain);
+            assert!(permuted.iter().all(|v| *v < domain));
+        }
+    }
+
+    #[test]
+    fn unpermute_reverts_permute() {
+        let permutation = sample_permutation();
+        for value in [0, 1, 37, 999_999] {
+            let permuted = permutation.permute("1", "sales", value, 1_000_000).unwrap();
+            assert_eq!(
+                permutation
+                    .unpermute("1", "sales", permuted, 1_000_000)
+                    .unwrap(),
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn permutation_is_deterministic_and_keyed() {
+        let permutation = sample_permutation();
+        let permute = |tenant: &str, tweak: &str| {
+            (1..20)
+                .map(|v| permutation.permute(tenant, tweak, v, 1_000_000).unwrap())
+                .collect::<Vec<u64>>()
+        };
+        assert_eq!(permute("1", "sales"), permute("1", "sales"));
+        assert_ne!(permute("1", "sales"), permute("2", "sales"));
+        assert_ne!(permute("1", "sales"), permute("1", "customers"));
+        let other = KeyedPermutation::new(&[5u8; 32]).unwrap();
+        assert_ne!(
+            permutation.permute("1", "sales", 1, 1_000_000).unwrap(),
+            other.permute("1", "sales", 1, 1_000_000).unwrap()
+        );
+    }
+
+    #[test]
+    fn permuted_values_do_not_look_sequential() {
+        let permutation = sample_permutation();
+        let permuted: Vec<u64> = (1..100)
+            .map(|v| permutation.permute("1", "sales", v, 1_000_000).unwrap())
+            .collect();
+        let sequential = permuted.windows(2).filter(|w| w[1] == w[0] + 1).count();
+        assert!(sequential < 3);
+    }
+
+    #[test]
+    fn value_out_of_the_domain_is_rejected() {
+        assert!(matches!(
+            sample_permutation().permute("1", "sales", 10, 10),
+            Err(CryptoError::InvalidInput(_))
+        ));
+    }
+}
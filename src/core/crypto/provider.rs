@@ -0,0 +1,181 @@
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
+use async_trait::async_trait;
+use base64::{engine::general_purpose, Engine as _};
+use secrecy::zeroize::Zeroizing;
+use secrecy::{ExposeSecret, Secret};
+use std::fmt;
+
+use crate::core::crypto::{Aes256GcmCipher, CryptoError};
+
+/// Holder of the master key encryption key (KEK), which wraps the data keys
+/// without ever leaving the provider.
+///
+/// `aad` (associated data) is bound to the wrapped key: unwrapping fails if it
+/// differs from the one given when wrapping.
+#[async_trait]
+pub trait KeyProvider: Send + Sync {
+    async fn wrap(&self, key: &[u8], aad: &[u8]) -> Result<Vec<u8>, CryptoError>;
+
+    /// The unwrapped key is zeroized when dropped
+    async fn unwrap(&self, wrapped: &[u8], aad: &[u8]) -> Result<Zeroizing<Vec<u8>>, CryptoError>;
+
+    /// Switches to a new version of the KEK for the next wraps; the keys
+    /// wrapped with the previous versions can still be unwrapped.
+    async fn rotate(&self) -> Result<(), CryptoError>;
+}
+
+/// A new random 256 bits key, zeroized when dropped
+pub fn random_key() -> Zeroizing<[u8; 32]> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    OsRng.fill_bytes(key.as_mut());
+    key
+}
+
+/// Versions of a KEK held in memory, the latest one wrapping the keys.
+///
+/// Wrapped keys are prefixed with the (big endian `u32`) version of the KEK.
+pub struct Keyring {
+    versions: Vec<Aes256GcmCipher>,
+}
+
+impl Keyring {
+    /// Every version of the KEK, the oldest first
+    pub fn new(keys: &[&[u8]]) -> Result<Keyring, CryptoError> {
+        if keys.is_empty() {
+            return Err(CryptoError::InvalidKey("no key".to_string()));
+        }
+        let versions = keys
+            .iter()
+            .map(|key| Aes256GcmCipher::new(key))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Keyring { versions })
+    }
+
+    /// base64 encoded 256 bits keys, separated by whitespaces or commas, the oldest first
+    pub fn from_base64(keys: &Secret<String>) -> Result<Keyring, CryptoError> {
+        let raw = keys
+            .expose_secret()
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|key| !key.is_empty())
+            .map(|key| {
+                general_purpose::STANDARD
+                    .decode(key)
+                    .map(Zeroizing::new)
+                    .map_err(|_| CryptoError::InvalidKey("invalid base64".to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Keyring::new(&raw.iter().map(|key| key.as_slice()).collect::<Vec<_>>())
+    }
+
+    /// New latest version
+    pub fn add_version(&mut self, key: &[u8]) -> Result<(), CryptoError> {
+        self.versions.push(Aes256GcmCipher::new(key)?);
+        Ok(())
+    }
+
+    pub fn wrap(&self, key: &[u8], aad: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let version = self.versions.len() as u32;
+        let sealed = self.versions[self.versions.len() - 1].seal(key, aad)?;
+        Ok([version.to_be_bytes().as_slice(), &sealed].concat())
+    }
+
+    pub fn unwrap(&self, wrapped: &[u8], aad: &[u8]) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+        if wrapped.len() < 4 {
+            return Err(CryptoError::DecryptionFailure);
+        }
+        let (version, sealed) = wrapped.split_at(4);
+        let version = u32::from_be_bytes(version.try_into().expect("4 bytes long")) as usize;
+        version
+            .checked_sub(1)
+            .and_then(|i| self.versions.get(i))
+            .ok_or(CryptoError::DecryptionFailure)?
+            .open(sealed, aad)
+    }
+}
+
+/// The keys are given from outside: they cannot be rotated from here
+#[async_trait]
+impl KeyProvider for Keyring {
+    async fn wrap(&self, key: &[u8], aad: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        Keyring::wrap(self, key, aad)
+    }
+
+    async fn unwrap(&self, wrapped: &[u8], aad: &[u8]) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+        Keyring::unwrap(self, wrapped, aad)
+    }
+
+    async fn rotate(&self) -> Result<(), CryptoError> {
+        Err(CryptoError::ProviderFailure(
+            "static keys are rotated by appending a new key to their source".to_string(),
+        ))
+    }
+}
+
+impl fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Keyring")
+            .field("versions", &self.versions.len())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrap_unwrap_roundtrip() {
+        let keyring = Keyring::new(&[&[1u8; 32]]).unwrap();
+        let wrapped = keyring.wrap(&[7u8; 32], b"dek:1").unwrap();
+        assert_eq!(
+            keyring.unwrap(&wrapped, b"dek:1").unwrap().as_slice(),
+            &[7u8; 32]
+        );
+        assert_eq!(
+            keyring.unwrap(&wrapped, b"dek:2").err(),
+            Some(CryptoError::DecryptionFailure)
+        );
+    }
+
+    #[test]
+    fn previous_versions_still_unwrap() {
+        let mut keyring = Keyring::new(&[&[1u8; 32]]).unwrap();
+        let wrapped = keyring.wrap(&[7u8; 32], b"dek:1").unwrap();
+        keyring.add_version(random_key().as_ref()).unwrap();
+        let rewrapped = keyring.wrap(&[7u8; 32], b"dek:1").unwrap();
+
+        assert_eq!(&wrapped[..4], &1u32.to_be_bytes());
+        assert_eq!(&rewrapped[..4], &2u32.to_be_bytes());
+        for wrapped in [wrapped, rewrapped] {
+            assert_eq!(
+                keyring.unwrap(&wrapped, b"dek:1").unwrap().as_slice(),
+                &[7u8; 32]
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_version_fails() {
+        let keyring = Keyring::new(&[&[1u8; 32]]).unwrap();
+        let mut wrapped = keyring.wrap(&[7u8; 32], b"").unwrap();
+        wrapped[..4].copy_from_slice(&2u32.to_be_bytes());
+        assert_eq!(
+            keyring.unwrap(&wrapped, b"").err(),
+            Some(CryptoError::DecryptionFailure)
+        );
+        assert_eq!(
+            keyring.unwrap(&[0, 0], b"").err(),
+            Some(CryptoError::DecryptionFailure)
+        );
+    }
+
+    #[test]
+    fn from_base64_keys() {
+        let key = general_purpose::STANDARD.encode([3u8; 32]);
+        let keyring = Keyring::from_base64(&Secret::new(format!("{key},\n{key}\n"))).unwrap();
+        assert_eq!(keyring.versions.len(), 2);
+        assert!(Keyring::from_base64(&Secret::new(" \n".to_string())).is_err());
+        assert!(Keyring::from_base64(&Secret::new("@@@".to_string())).is_err());
+    }
+}
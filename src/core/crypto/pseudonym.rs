@@ -0,0 +1,156 @@
+use hmac::Mac;
+use secrecy::{ExposeSecret, Secret};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::core::crypto::keyed::{scoped_mac, HmacKey};
+use crate::core::crypto::CryptoError;
+
+/// Bits of the HMAC-SHA256 digest the pseudonyms are drawn from
+const DIGEST_BITS: f64 = 256.0;
+
+/// Non reversible pseudonyms: truncated HMAC-SHA256 of the normalized (unicode NFC)
+/// value, encoded with a given alphabet.
+///
+/// Each (tenant, policy) pseudonymizes with a subkey of the given key.
+pub struct Pseudonymizer {
+    key: HmacKey,
+}
+
+impl Pseudonymizer {
+    pub fn new(key: &[u8]) -> Result<Pseudonymizer, CryptoError> {
+        Ok(Pseudonymizer {
+            key: HmacKey::new(key)?,
+        })
+    }
+
+    pub fn from_base64(key: &Secret<String>) -> Result<Pseudonymizer, CryptoError> {
+        Ok(Pseudonymizer {
+            key: HmacKey::from_base64(key)?,
+        })
+    }
+
+    /// Pseudonym of `length` characters of `alphabet`, which must hold at most
+    /// the 256 bits of the digest; the characters of `alphabet` must be distinct
+    pub fn pseudonym(
+        &self,
+        tenant: &str,
+        policy: &str,
+        alphabet: &[char],
+        length: usize,
+        value: &Secret<String>,
+    ) -> Result<String, CryptoError> {
+        if alphabet.len() < 2
+            || alphabet
+                .iter()
+                .enumerate()
+                .any(|(i, c)| alphabet[..i].contains(c))
+        {
+            return Err(CryptoError::InvalidInput(
+                "alphabet must contain at least 2 distinct characters".to_string(),
+            ));
+        }
+        if length as f64 * (alphabet.len() as f64).log2() > DIGEST_BITS {
+            return Err(CryptoError::InvalidInput(format!(
+                "{length} characters hold more than the {DIGEST_BITS} bits of the digest"
+            )));
+        }
+        let mut mac = scoped_mac(
+            self.key
+                .scoped_key("pseudonym:", &[tenant, policy])
+                .expose_secret(),
+            &[],
+        );
+        let normalized: String = value.expose_secret().nfc().collect();
+        mac.update(normalized.as_bytes());
+        let mut digest = mac.finalize().into_bytes().to_vec();
+
+        // the digest read as a big endian number, written in base alphabet.len()
+        let radix = alphabet.len() as u32;
+        Ok((0..length)
+            .map(|_| alphabet[div_rem(&mut digest, radix) as usize])
+            .collect())
+    }
+}
+
+/// Divides the big endian number in place, returning the remainder
+fn div_rem(number: &mut [u8], divisor: u32) -> u32 {
+    let mut remainder = 0u32;
+    for byte in number.iter_mut() {
+        let acc = (remainder << 8) | *byte as u32;
+        *byte = (acc / divisor) as u8;
+        remainder = acc % divisor;
+    }
+    remainder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEX: [char; 16] = [
+        '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', 'a', 'b', 'c', 'd', 'e', 'f',
+    ];
+
+    fn secret(value: &str) -> Secret<String> {
+        Secret::new(value.to_string())
+    }
+
+    fn sample_pseudonymizer() -> Pseudonymizer {
+        Pseudonymizer::new(&[8u8; 32]).unwrap()
+    }
+
+    #[test]
+    fn div_rem_samples() {
+        let mut number = [0x01, 0x00];
+        assert_eq!(div_rem(&mut number, 10), 6);
+        assert_eq!(number, [0x00, 25]);
+    }
+
+    #[test]
+    fn pseudonym_is_stable_and_normalized() {
+        let pseudonymizer = sample_pseudonymizer();
+        let pseudonym = |value: &str| {
+            pseudonymizer
+                .pseudonym("1", "names", &HEX, 16, &secret(value))
+                .unwrap()
+        };
+        assert_eq!(pseudonym("Zoé"), pseudonym("Zoé"));
+        assert_eq!(pseudonym("Zoé"), pseudonym("Zoe\u{301}"));
+        assert_ne!(pseudonym("Zoé"), pseudonym("Zoe"));
+        assert_eq!(pseudonym("Zoé").len(), 16);
+        assert!(pseudonym("Zoé").chars().all(|c| HEX.contains(&c)));
+    }
+
+    #[test]
+    fn pseudonym_depends_on_tenant_and_policy() {
+        let pseudonymizer = sample_pseudonymizer();
+        let pseudonym = |tenant: &str, policy: &str| {
+            pseudonymizer
+                .pseudonym(tenant, policy, &HEX, 16, &secret("Zoé"))
+                .unwrap()
+        };
+        assert_ne!(pseudonym("1", "names"), pseudonym("2", "names"));
+        assert_ne!(pseudonym("1", "names"), pseudonym("1", "cities"));
+        assert_ne!(pseudonym("1", "ab"), pseudonym("1a", "b"));
+    }
+
+    #[test]
+    fn pseudonym_cannot_exceed_the_digest() {
+        let pseudonymizer = sample_pseudonymizer();
+        assert!(pseudonymizer
+            .pseudonym("1", "names", &HEX, 64, &secret("Zoé"))
+            .is_ok());
+        assert!(matches!(
+            pseudonymizer.pseudonym("1", "names", &HEX, 65, &secret("Zoé")),
+            Err(CryptoError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            pseudonymizer.pseudonym("1", "names", &['a'], 8, &secret("Zoé")),
+            Err(CryptoError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            pseudonymizer.pseudonym("1", "names", &['a', 'a'], 8, &secret("Zoé")),
+            Err(CryptoError::InvalidInput(_))
+        ));
+    }
+}
@@ -1,4 +1,6 @@
 pub mod context;
+pub mod crypto;
+pub mod policy;
 pub mod tenant;
 pub mod token;
 pub mod util;
@@ -0,0 +1,42 @@
+use crate::core::context::ExecutionContext;
+use crate::core::token::Policy;
+use crate::core::util;
+use async_trait::async_trait;
+
+use crate::error::Error;
+
+#[derive(Debug, Clone)]
+pub struct StoredPolicy {
+    /// Unique technical identifier
+    pub id: i64,
+    /// Version of the policy, used for optimistic locking
+    pub row_version: i32,
+    pub policy: Policy,
+}
+
+#[async_trait]
+pub trait Policies {
+    async fn create_policy(
+        &self,
+        context: &ExecutionContext,
+        policy: Policy,
+    ) -> Result<StoredPolicy, Error>;
+    async fn find_policy_by_code(
+        &self,
+        context: &ExecutionContext,
+        code: String,
+    ) -> Result<Option<StoredPolicy>, Error>;
+    async fn find_policies(
+        &self,
+        context: &ExecutionContext,
+        paging: util::Paging,
+    ) -> Result<util::Page<StoredPolicy>, Error>;
+    /// Updates the policy identified by its code; `row_version` is the version
+    /// the update is based on, the update fails if it is not the current one.
+    async fn update_policy(
+        &self,
+        context: &ExecutionContext,
+        policy: Policy,
+        row_version: i32,
+    ) -> Result<StoredPolicy, Error>;
+}
@@ -15,7 +15,7 @@ impl NewTenant {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Tenant {
     /// Unique technical identifier
     pub id: i64,
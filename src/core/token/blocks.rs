@@ -0,0 +1,355 @@
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::task::JoinHandle;
+
+use crate::core::context::{ExecutionContext, Permission};
+use crate::core::token::{Policy, SequenceSource, TokenError};
+use crate::error::Error;
+
+/// Hi/lo allocation in front of a `SequenceSource`: blocks of values are reserved
+/// per (tenant, policy) and handed out from memory, the next block being fetched
+/// in the background once a tenth of the current one remains.
+///
+/// Values of a block that are not handed out (e.g. on restart, or when the
+/// allocator of the least recently used sequence is evicted to keep at most
+/// `max_allocators` of them) are lost: this leaves gaps, never duplicates.
+pub struct BlockSequenceSource<S> {
+    source: Arc<S>,
+    block_size: i64,
+    max_allocators: usize,
+    allocators: Mutex<HashMap<(String, String), Arc<Allocator>>>,
+    /// Logical clock ordering the uses of the allocators
+    clock: AtomicU64,
+}
+
+/// Number of (tenant, policy) allocators kept by default
+const DEFAULT_MAX_ALLOCATORS: usize = 1024;
+
+struct Block {
+    next: AtomicI64,
+    end: i64,
+}
+
+#[derive(Default)]
+struct Allocator {
+    current: Mutex<Arc<Block>>,
+    prefetch: tokio::sync::Mutex<Option<JoinHandle<Result<i64, Error>>>>,
+    last_used: AtomicU64,
+}
+
+impl Default for Block {
+    fn default() -> Self {
+        Block::new(0, 0)
+    }
+}
+
+impl Block {
+    fn new(start: i64, end: i64) -> Block {
+        Block {
+            next: AtomicI64::new(start),
+            end,
+        }
+    }
+}
+
+impl<S> BlockSequenceSource<S>
+where
+    S: SequenceSource + Send + Sync + 'static,
+{
+    pub fn new(source: S, block_size: i64) -> BlockSequenceSource<S> {
+        BlockSequenceSource {
+            source: Arc::new(source),
+            block_size: block_size.max(1),
+            max_allocators: DEFAULT_MAX_ALLOCATORS,
+            allocators: Mutex::new(HashMap::new()),
+            clock: AtomicU64::new(0),
+        }
+    }
+
+    pub fn with_max_allocators(mut self, max_allocators: usize) -> BlockSequenceSource<S> {
+        self.max_allocators = max_allocators.max(1);
+        self
+    }
+
+    fn allocator(&self, context: &ExecutionContext, policy: &Policy) -> Arc<Allocator> {
+        let key = (context.tenant_label(), policy.code.clone());
+        let now = self.clock.fetch_add(1, Ordering::Relaxed);
+        let mut allocators = self.allocators.lock().expect("allocators lock poisoned");
+        if !allocators.contains_key(&key) && allocators.len() >= self.max_allocators {
+            let oldest = allocators
+                .iter()
+                .min_by_key(|(_, allocator)| allocator.last_used.load(Ordering::Relaxed))
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                allocators.remove(&oldest);
+            }
+        }
+        let allocator = allocators.entry(key).or_default().clone();
+        allocator.last_used.store(now, Ordering::Relaxed);
+        allocator
+    }
+
+    fn fetch(&self, context: &ExecutionContext, policy: &Policy) -> JoinHandle<Result<i64, Error>> {
+        let source = self.source.clone();
+        let context = context.clone();
+        let policy = policy.clone();
+        let block_size = self.block_size;
+        tokio::spawn(async move { source.reserve(&context, &policy, block_size).await })
+    }
+
+    /// Starts fetching the next block, unless it is already on its way
+    fn prefetch(
+        &self,
+        allocator: &Allocator,
+        block: &Arc<Block>,
+        context: &ExecutionContext,
+        policy: &Policy,
+    ) {
+        if let Ok(mut prefetch) = allocator.prefetch.try_lock() {
+            let current = allocator
+                .current
+                .lock()
+                .expect("block lock poisoned")
+                .clone();
+            if prefetch.is_none() && Arc::ptr_eq(&current, block) {
+                *prefetch = Some(self.fetch(context, policy));
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<S> SequenceSource for BlockSequenceSource<S>
+where
+    S: SequenceSource + Send + Sync + 'static,
+{
+    async fn reserve(
+        &self,
+        context: &ExecutionContext,
+        policy: &Policy,
+        count: i64,
+    ) -> Result<i64, Error> {
+        context.ensure_permission(&Permission::TokenCreate)?;
+        if count >= self.block_size {
+            return self.source.reserve(context, policy, count).await;
+        }
+
+        let allocator = self.allocator(context, policy);
+        loop {
+            let block = allocator
+                .current
+                .lock()
+                .expect("block lock poisoned")
+                .clone();
+            let first = block.next.fetch_add(count, Ordering::SeqCst);
+            if first + count <= block.end {
+                if block.end - (first + count) <= self.block_size / 10 {
+                    self.prefetch(&allocator, &block, context, policy);
+                }
+                return Ok(first);
+            }
+
+            // the block is exhausted: the first one getting there installs the next one
+            let mut prefetch = allocator.prefetch.lock().await;
+            if !Arc::ptr_eq(
+                &allocator.current.lock().expect("block lock poisoned"),
+                &block,
+            ) {
+                continue;
+            }
+            let handle = prefetch
+                .take()
+                .unwrap_or_else(|| self.fetch(context, policy));
+            let start = handle
+                .await
+                .map_err(|e| TokenError::GenerationFailure(e.to_string()))??;
+            *allocator.current.lock().expect("block lock poisoned") =
+                Arc::new(Block::new(start, start + self.block_size));
+        }
+    }
+
+    /// The values of the reserved blocks count as used
+    async fn next_value(&self, context: &ExecutionContext, policy: &Policy) -> Result<i64, Error> {
+        self.source.next_value(context, policy).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::context::{Caller, CallerType, TenantId};
+    use crate::core::token::{SequenceFormat, TokenFormat};
+    use std::collections::HashSet;
+
+    /// In memory source keeping track of the reservations
+    #[derive(Default)]
+    struct CountingSource {
+        next: AtomicI64,
+        calls: AtomicI64,
+    }
+
+    #[async_trait]
+    impl SequenceSource for Arc<CountingSource> {
+        async fn reserve(
+            &self,
+            _context: &ExecutionContext,
+            _policy: &Policy,
+            count: i64,
+        ) -> Result<i64, Error> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(self.next.fetch_add(count, Ordering::SeqCst) + 1)
+        }
+
+        async fn next_value(
+            &self,
+            _context: &ExecutionContext,
+            _policy: &Policy,
+        ) -> Result<i64, Error> {
+            Ok(self.next.load(Ordering::SeqCst) + 1)
+        }
+    }
+
+    fn context(tenant: &str) -> ExecutionContext {
+        let tenant: TenantId = tenant.to_string().try_into().unwrap();
+        ExecutionContext::new(
+            Some(tenant),
+            Caller::new("007".to_string(), CallerType::USER),
+            HashSet::from([Permission::TokenCreate]),
+        )
+    }
+
+    fn policy(code: &str) -> Policy {
+        Policy::new(code, TokenFormat::Sequence(SequenceFormat::Raw))
+    }
+
+    #[tokio::test]
+    async fn values_are_handed_out_from_blocks() {
+        let source = Arc::new(CountingSource::default());
+        let blocks = BlockSequenceSource::new(source.clone(), 10);
+        let (context, policy) = (context("idfm"), policy("sales"));
+
+        let mut values = Vec::new();
+        for _ in 0..25 {
+            values.push(blocks.reserve(&context, &policy, 1).await.unwrap());
+        }
+        assert_eq!(values.iter().collect::<HashSet<_>>().len(), 25);
+        // 3 blocks, plus possibly the prefetched 4th one
+        assert!(source.calls.load(Ordering::SeqCst) <= 4);
+    }
+
+    #[tokio::test]
+    async fn next_block_is_prefetched() {
+        let source = Arc::new(CountingSource::default());
+        let blocks = BlockSequenceSource::new(source.clone(), 10);
+        let (context, policy) = (context("idfm"), policy("sales"));
+
+        for _ in 0..9 {
+            blocks.reserve(&context, &policy, 1).await.unwrap();
+        }
+        tokio::task::yield_now().await;
+        assert_eq!(source.calls.load(Ordering::SeqCst), 2);
+
+        // the prefetched block is used once the current one is exhausted
+        assert_eq!(blocks.reserve(&context, &policy, 1).await.unwrap(), 10);
+        assert_eq!(blocks.reserve(&context, &policy, 1).await.unwrap(), 11);
+        assert_eq!(source.calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn blocks_are_kept_per_tenant_and_policy() {
+        let source = Arc::new(CountingSource::default());
+        let blocks = BlockSequenceSource::new(source.clone(), 100);
+
+        let a = blocks
+            .reserve(&context("idfm"), &policy("sales"), 1)
+            .await
+            .unwrap();
+        let b = blocks
+            .reserve(&context("idfm"), &policy("customers"), 1)
+            .await
+            .unwrap();
+        let c = blocks
+            .reserve(&context("sncf"), &policy("sales"), 1)
+            .await
+            .unwrap();
+        assert_eq!(source.calls.load(Ordering::SeqCst), 3);
+        assert_eq!(HashSet::from([a, b, c]).len(), 3);
+    }
+
+    #[tokio::test]
+    async fn least_recently_used_allocators_are_evicted() {
+        let source = Arc::new(CountingSource::default());
+        let blocks = BlockSequenceSource::new(source.clone(), 100).with_max_allocators(2);
+        let reserve = |tenant| {
+            let context = context(tenant);
+            let blocks = &blocks;
+            async move { blocks.reserve(&context, &policy("sales"), 1).await.unwrap() }
+        };
+
+        reserve("idfm").await;
+        reserve("sncf").await;
+        reserve("idfm").await;
+        assert_eq!(blocks.allocators.lock().unwrap().len(), 2);
+        assert_eq!(source.calls.load(Ordering::SeqCst), 2);
+
+        // sncf is evicted, its block is lost
+        reserve("ratp").await;
+        assert_eq!(blocks.allocators.lock().unwrap().len(), 2);
+        reserve("idfm").await;
+        assert_eq!(source.calls.load(Ordering::SeqCst), 3);
+        let value = reserve("sncf").await;
+        assert_eq!(source.calls.load(Ordering::SeqCst), 4);
+        assert_eq!(value, 301);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_reservations_never_duplicate() {
+        let source = Arc::new(CountingSource::default());
+        let blocks = Arc::new(BlockSequenceSource::new(source, 7));
+        let handles: Vec<_> = (0..16)
+            .map(|_| {
+                let blocks = blocks.clone();
+                tokio::spawn(async move {
+                    let (context, policy) = (context("idfm"), policy("sales"));
+                    let mut values = Vec::new();
+                    for _ in 0..100 {
+                        values.push(blocks.reserve(&context, &policy, 1).await.unwrap());
+                    }
+                    values
+                })
+            })
+            .collect();
+        let mut values = HashSet::new();
+        for handle in handles {
+            for value in handle.await.unwrap() {
+                assert!(values.insert(value), "duplicated value {value}");
+            }
+        }
+        assert_eq!(values.len(), 1600);
+    }
+
+    #[tokio::test]
+    async fn large_reservations_bypass_the_blocks() {
+        let source = Arc::new(CountingSource::default());
+        let blocks = BlockSequenceSource::new(source.clone(), 10);
+        let (context, policy) = (context("idfm"), policy("sales"));
+
+        let first = blocks.reserve(&context, &policy, 1).await.unwrap();
+        let range = blocks.reserve(&context, &policy, 50).await.unwrap();
+        assert!(range > first + 9);
+    }
+
+    #[tokio::test]
+    async fn reserve_requires_permission() {
+        let blocks = BlockSequenceSource::new(Arc::new(CountingSource::default()), 10);
+        let tenant: TenantId = "idfm".to_string().try_into().unwrap();
+        let context = ExecutionContext::new(
+            Some(tenant),
+            Caller::new("007".to_string(), CallerType::USER),
+            HashSet::new(),
+        );
+        assert!(blocks.reserve(&context, &policy("sales"), 1).await.is_err());
+    }
+}
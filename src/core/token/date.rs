@@ -0,0 +1,116 @@
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime};
+
+use crate::core::token::TokenError;
+
+/// Date (or date time) shifted by a keyed offset of a few days: the token is a
+/// valid date written with the same pattern, and the intervals between dates
+/// sharing the same offset are kept.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DateShiftFormat {
+    /// strftime pattern of the values, e.g. `%Y-%m-%d`
+    pub pattern: String,
+    /// the offset is picked in `-window_days..=window_days`, never 0
+    pub window_days: u32,
+    /// when set, each subject gets its own offset, otherwise the offset is shared
+    /// by the whole tenant
+    pub per_subject: bool,
+}
+
+impl DateShiftFormat {
+    /// Shifts the value by `days`, failing when it does not match the pattern
+    pub fn shift(&self, value: &str, days: i64) -> Result<String, TokenError> {
+        self.check()?;
+        let delta = Duration::days(days);
+        let out_of_range = || TokenError::InvalidValue("shifted date is out of range".to_string());
+        if let Ok(date) = DateTime::parse_from_str(value, &self.pattern) {
+            let shifted = date.checked_add_signed(delta).ok_or_else(out_of_range)?;
+            return Ok(shifted.format(&self.pattern).to_string());
+        }
+        if let Ok(date) = NaiveDateTime::parse_from_str(value, &self.pattern) {
+            let shifted = date.checked_add_signed(delta).ok_or_else(out_of_range)?;
+            return Ok(shifted.format(&self.pattern).to_string());
+        }
+        if let Ok(date) = NaiveDate::parse_from_str(value, &self.pattern) {
+            let shifted = date.checked_add_signed(delta).ok_or_else(out_of_range)?;
+            return Ok(shifted.format(&self.pattern).to_string());
+        }
+        Err(TokenError::InvalidValue(format!(
+            "value does not match the pattern {}",
+            self.pattern
+        )))
+    }
+
+    fn check(&self) -> Result<(), TokenError> {
+        if StrftimeItems::new(&self.pattern).any(|item| matches!(item, Item::Error)) {
+            return Err(TokenError::InvalidValue(format!(
+                "invalid date pattern '{}'",
+                self.pattern
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(pattern: &str) -> DateShiftFormat {
+        DateShiftFormat {
+            pattern: pattern.to_string(),
+            window_days: 30,
+            per_subject: false,
+        }
+    }
+
+    #[test]
+    fn shift_dates() {
+        let format = format("%d/%m/%Y");
+        assert_eq!(format.shift("28/02/2024", 1).unwrap(), "29/02/2024");
+        assert_eq!(format.shift("28/02/2023", 1).unwrap(), "01/03/2023");
+        assert_eq!(format.shift("05/01/1970", -10).unwrap(), "26/12/1969");
+    }
+
+    #[test]
+    fn shift_date_times_keeping_the_time() {
+        assert_eq!(
+            format("%Y-%m-%dT%H:%M:%S")
+                .shift("2023-05-17T10:30:00", 3)
+                .unwrap(),
+            "2023-05-20T10:30:00"
+        );
+        assert_eq!(
+            format("%Y-%m-%dT%H:%M:%S%:z")
+                .shift("2023-05-17T10:30:00+02:00", -17)
+                .unwrap(),
+            "2023-04-30T10:30:00+02:00"
+        );
+    }
+
+    #[test]
+    fn shift_back_restores_the_value() {
+        let format = format("%Y%m%d");
+        let shifted = format.shift("19800229", 12).unwrap();
+        assert_eq!(format.shift(&shifted, -12).unwrap(), "19800229");
+    }
+
+    #[test]
+    fn shift_rejects_invalid_values_and_patterns() {
+        for (pattern, value) in [
+            ("%Y-%m-%d", "2023-02-30"),
+            ("%Y-%m-%d", "17/05/2023"),
+            ("%Y-%m-%d", "2023-05-17 10:30"),
+            ("%Y", "2023"),
+            ("%Q", "2023"),
+        ] {
+            assert!(
+                matches!(
+                    format(pattern).shift(value, 1),
+                    Err(TokenError::InvalidValue(_))
+                ),
+                "{value} / {pattern} should be rejected"
+            );
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use rand::Rng;
+
+use crate::core::token::{TokenError, TokenFormat};
+
+const MAX_LOCAL_PART_LEN: usize = 64;
+const MAX_DOMAIN_LEN: usize = 253;
+const MAX_LABEL_LEN: usize = 63;
+
+/// Email address whose local part is tokenized with `local`, the domain being
+/// kept (or replaced by a random one when `tokenize_domain` is set).
+///
+/// Only the unquoted ASCII syntax is supported: `local@domain`, the local part
+/// being a dot-atom and the domain a hostname.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "UncheckedEmailFormat")]
+pub struct EmailFormat {
+    /// format of the token of the local part: `Uuid`, `Sequence` or `Random`
+    pub local: Box<TokenFormat>,
+    /// e.g. for internal domains; the top level domain is kept
+    pub tokenize_domain: bool,
+}
+
+impl EmailFormat {
+    pub fn check(&self) -> Result<(), TokenError> {
+        match *self.local {
+            TokenFormat::Uuid | TokenFormat::Sequence(_) | TokenFormat::Random(_) => Ok(()),
+            _ => Err(TokenError::InvalidValue(
+                "local part of an email must be a Uuid, Sequence or Random token".to_string(),
+            )),
+        }
+    }
+}
+
+/// `EmailFormat` as deserialized, before its local format is checked
+#[derive(Deserialize)]
+struct UncheckedEmailFormat {
+    local: Box<TokenFormat>,
+    tokenize_domain: bool,
+}
+
+impl TryFrom<UncheckedEmailFormat> for EmailFormat {
+    type Error = TokenError;
+
+    fn try_from(unchecked: UncheckedEmailFormat) -> Result<Self, Self::Error> {
+        let email = EmailFormat {
+            local: unchecked.local,
+            tokenize_domain: unchecked.tokenize_domain,
+        };
+        email.check()?;
+        Ok(email)
+    }
+}
+
+/// Splits a valid address into its local part and domain
+pub(crate) fn split_address(value: &str) -> Result<(&str, &str), TokenError> {
+    let invalid = || TokenError::InvalidValue("invalid email address".to_string());
+    let (local, domain) = value.rsplit_once('@').ok_or_else(invalid)?;
+    if is_valid_local_part(local) && is_valid_domain(domain) {
+        Ok((local, domain))
+    } else {
+        Err(invalid())
+    }
+}
+
+fn is_valid_local_part(local: &str) -> bool {
+    const ATEXT: &str = "!#$%&'*+-/=?^_`{|}~";
+    !local.is_empty()
+        && local.len() <= MAX_LOCAL_PART_LEN
+        && local.split('.').all(|atom| {
+            !atom.is_empty()
+                && atom
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || ATEXT.contains(c))
+        })
+}
+
+fn is_valid_domain(domain: &str) -> bool {
+    !domain.is_empty()
+        && domain.len() <= MAX_DOMAIN_LEN
+        && domain.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= MAX_LABEL_LEN
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// Rebuilds the address, failing when the tokenized local part is not a valid one
+/// (e.g. a template or charset producing forbidden characters)
+pub(crate) fn join_address(local: &str, domain: &str) -> Result<String, TokenError> {
+    if !is_valid_local_part(local) {
+        return Err(TokenError::GenerationFailure(format!(
+            "'{local}' is not a valid local part of an email"
+        )));
+    }
+    Ok(format!("{local}@{domain}"))
+}
+
+/// Replaces every label but the top level one by random letters and digits,
+/// hyphens being kept in place
+pub(crate) fn random_domain(domain: &str) -> String {
+    let mut rng = rand::thread_rng();
+    let (labels, tld) = domain.rsplit_once('.').unwrap_or(("", domain));
+    let mut random: String = labels
+        .chars()
+        .map(|c| match c {
+            '.' | '-' => c,
+            _ => {
+                let n = rng.gen_range(0..36);
+                std::char::from_digit(n, 36).expect("digit is lower than 36")
+            }
+        })
+        .collect();
+    if !random.is_empty() {
+        random.push('.');
+    }
+    random.push_str(tld);
+    random
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::token::SequenceFormat;
+
+    #[test]
+    fn split_valid_addresses() {
+        assert_eq!(
+            split_address("carmen.mccallum@example.com"),
+            Ok(("carmen.mccallum", "example.com"))
+        );
+        assert_eq!(
+            split_address("j+tag_1@mail.example-corp.fr"),
+            Ok(("j+tag_1", "mail.example-corp.fr"))
+        );
+    }
+
+    #[test]
+    fn split_rejects_invalid_addresses() {
+        for value in [
+            "carmen",
+            "@example.com",
+            "carmen@",
+            "carmen@@example.com",
+            ".carmen@example.com",
+            "car..men@example.com",
+            "car men@example.com",
+            "carmen@-example.com",
+            "carmen@example..com",
+            "carmén@example.com",
+        ] {
+            assert!(split_address(value).is_err(), "{value} should be rejected");
+        }
+        let long = format!("{}@example.com", "a".repeat(65));
+        assert!(split_address(&long).is_err());
+    }
+
+    #[test]
+    fn random_domain_keeps_the_shape_and_tld() {
+        for _ in 0..20 {
+            let domain = random_domain("mail.internal-corp.fr");
+            assert_eq!(domain.len(), "mail.internal-corp.fr".len());
+            assert!(domain.ends_with(".fr"));
+            assert_eq!(domain.chars().nth(4), Some('.'));
+            assert_eq!(domain.chars().nth(13), Some('-'));
+            assert!(is_valid_domain(&domain));
+        }
+        assert_eq!(random_domain("localhost"), "localhost");
+    }
+
+    #[test]
+    fn local_format_must_be_a_raw_one() {
+        let email = |local: TokenFormat| EmailFormat {
+            local: Box::new(local),
+            tokenize_domain: false,
+        };
+        assert!(email(TokenFormat::Uuid).check().is_ok());
+        assert!(email(TokenFormat::Sequence(SequenceFormat::Raw))
+            .check()
+            .is_ok());
+        assert!(email(TokenFormat::ClassPreserving).check().is_err());
+        assert!(email(TokenFormat::Email(email(TokenFormat::Uuid)))
+            .check()
+            .is_err());
+    }
+
+    #[test]
+    fn local_format_is_checked_on_deserialization() {
+        let json = r#"{"local":"Uuid","tokenize_domain":true}"#;
+        let email: EmailFormat = serde_json::from_str(json).unwrap();
+        assert_eq!(serde_json::to_string(&email).unwrap(), json);
+        let json = r#"{"local":"ClassPreserving","tokenize_domain":false}"#;
+        assert!(serde_json::from_str::<EmailFormat>(json).is_err());
+    }
+}
@@ -1,13 +1,26 @@
+use crate::core::context::ExecutionContext;
+use crate::core::crypto::KeyedPermutation;
+use crate::core::token::email::{self, EmailFormat};
 use crate::core::token::{
-    Policy, RawTokenGenerator, Token, TokenError, TokenFormat, TokenGenerator,
+    LuhnCheck, Policy, RandomFormat, RawTokenGenerator, SequenceFormat, SequenceSource, Token,
+    TokenError, TokenFormat, TokenGenerator, TokenVault,
 };
-use crate::error::Error as TError;
+use crate::core::util::is_luhn_valid;
+use crate::error::{Error, ErrorCode};
+use async_trait::async_trait;
+use rand::seq::SliceRandom;
+use rand::Rng;
 use secrecy::{ExposeSecret, Secret, Zeroize};
-use std::cmp::{max, min};
-use std::ops::Deref;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+/// Number of tokens generated before giving up on collisions with stored ones
+const MAX_GENERATION_ATTEMPTS: usize = 10;
+const MAX_PAN_ATTEMPTS: usize = 10;
+const MAX_CLASS_PRESERVING_ATTEMPTS: usize = 10;
 
 #[derive(Clone, Debug)]
-struct DefaultTokenGenerator<G>
+pub struct DefaultTokenGenerator<G>
 where
     G: RawTokenGenerator,
 {
@@ -25,32 +38,371 @@ where
     }
 }
 
+#[async_trait]
 impl<G> TokenGenerator for DefaultTokenGenerator<G>
 where
-    G: RawTokenGenerator,
+    G: RawTokenGenerator + Sync,
 {
-    fn generate(&self, policy: &Policy, value: Secret<String>) -> Result<Token, TokenError> {
-        let raw_token = self.delegate.generate(&policy.format)?;
-        Ok(format(&policy, raw_token, value).into())
+    async fn generate(
+        &self,
+        context: &ExecutionContext,
+        policy: &Policy,
+        value: Secret<String>,
+    ) -> Result<Token, Error> {
+        match &policy.format {
+            TokenFormat::Pan(luhn) => return Ok(pan_token(policy, *luhn, value)?),
+            TokenFormat::ClassPreserving => return Ok(class_preserving_token(policy, value)?),
+            TokenFormat::Email(email) => {
+                return self.email_token(context, policy, email, value).await
+            }
+            _ => {}
+        }
+        let raw_token = self.delegate.generate(context, policy, &value).await?;
+        Ok(format(policy, raw_token, value).into())
+    }
+
+    async fn generate_stored<V>(
+        &self,
+        context: &ExecutionContext,
+        policy: &Policy,
+        subject: Option<&str>,
+        value: Secret<String>,
+        vault: &V,
+    ) -> Result<Token, Error>
+    where
+        V: TokenVault + Sync,
+    {
+        for _ in 0..MAX_GENERATION_ATTEMPTS {
+            let token = self
+                .generate(context, policy, Secret::new(value.expose_secret().clone()))
+                .await?;
+            match vault
+                .store_token(context, policy, subject, &token, &value)
+                .await
+            {
+                Err(e) if e.code() == ErrorCode::UniqueViolation => continue,
+                res => return res,
+            }
+        }
+        Err(TokenError::GenerationFailure(format!(
+            "no free token found after {} attempts",
+            MAX_GENERATION_ATTEMPTS
+        ))
+        .into())
     }
 }
 
-pub fn format<T>(policy: &Policy, raw_token: String, value: Secret<T>) -> String
+impl<G> DefaultTokenGenerator<G>
 where
-    T: ToString + Zeroize,
+    G: RawTokenGenerator + Sync,
 {
-    let unsecure = value.expose_secret().to_string();
-    let idx_left = min(policy.keep_left as i32, unsecure.len() as i32);
-    let left = &unsecure[0..idx_left as usize];
+    /// Tokenizes the local part with the raw format of the email, keep_left,
+    /// keep_right and prefix applying to it
+    async fn email_token(
+        &self,
+        context: &ExecutionContext,
+        policy: &Policy,
+        email: &EmailFormat,
+        value: Secret<String>,
+    ) -> Result<Token, Error> {
+        email.check()?;
+        let (local, domain) = email::split_address(value.expose_secret())?;
+        let local = Secret::new(local.to_string());
+        let local_policy = Policy {
+            format: (*email.local).clone(),
+            ..policy.clone()
+        };
+        let raw_token = self
+            .delegate
+            .generate(context, &local_policy, &local)
+            .await?;
+        let domain = if email.tokenize_domain {
+            email::random_domain(domain)
+        } else {
+            domain.to_string()
+        };
+        Ok(email::join_address(&format(policy, raw_token, local), &domain)?.into())
+    }
+}
+
+/// Ratio of the capacity of a sequence past which a warning is emitted
+const DEFAULT_USAGE_WARNING: f64 = 0.8;
+
+/// Settings of the raw token generators drawing from a `SequenceSource`
+#[derive(Clone, Debug)]
+pub(crate) struct SequenceOptions {
+    pub permutation: Option<Arc<KeyedPermutation>>,
+    pub usage_warning: f64,
+    /// (tenant, policy) sequences already warned about by this process
+    warned: Arc<Mutex<HashSet<(String, String)>>>,
+}
+
+impl Default for SequenceOptions {
+    fn default() -> Self {
+        SequenceOptions {
+            permutation: None,
+            usage_warning: DEFAULT_USAGE_WARNING,
+            warned: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+}
 
-    let mut idx_right = unsecure.len() as i32 - policy.keep_right as i32;
-    idx_right = min(unsecure.len() as i32, max(idx_right, idx_left));
+impl SequenceOptions {
+    /// Whether the usage warning of the sequence is due, i.e. not emitted yet
+    fn first_warning(&self, tenant: String, policy: &str) -> bool {
+        self.warned
+            .lock()
+            .expect("warned sequences lock poisoned")
+            .insert((tenant, policy.to_string()))
+    }
+}
+
+/// Raw tokens whose sequences are drawn from a `SequenceSource`
+#[derive(Clone, Debug)]
+pub struct SequenceRawTokenGenerator<S>
+where
+    S: SequenceSource,
+{
+    sequences: S,
+    options: SequenceOptions,
+}
+
+impl<S> SequenceRawTokenGenerator<S>
+where
+    S: SequenceSource,
+{
+    pub fn new(sequences: S) -> SequenceRawTokenGenerator<S> {
+        SequenceRawTokenGenerator {
+            sequences,
+            options: SequenceOptions::default(),
+        }
+    }
+
+    /// Enables the `Permuted` sequence format
+    pub fn with_permutation(mut self, permutation: Arc<KeyedPermutation>) -> Self {
+        self.options.permutation = Some(permutation);
+        self
+    }
+
+    /// Ratio (0.8 by default) of the capacity of a sequence past which a warning is emitted
+    pub fn with_usage_warning(mut self, ratio: f64) -> Self {
+        self.options.usage_warning = ratio;
+        self
+    }
+}
+
+#[async_trait]
+impl<S> RawTokenGenerator for SequenceRawTokenGenerator<S>
+where
+    S: SequenceSource + Sync,
+{
+    async fn generate(
+        &self,
+        context: &ExecutionContext,
+        policy: &Policy,
+        value: &Secret<String>,
+    ) -> Result<String, Error> {
+        raw_token(&self.sequences, &self.options, context, policy, value).await
+    }
+}
 
-    let right = if idx_right >= 0 {
-        &unsecure[idx_right as usize..]
-    } else {
-        ""
+pub(crate) async fn raw_token<S>(
+    sequences: &S,
+    options: &SequenceOptions,
+    context: &ExecutionContext,
+    policy: &Policy,
+    value: &Secret<String>,
+) -> Result<String, Error>
+where
+    S: SequenceSource + Sync,
+{
+    let raw_token = match &policy.format {
+        TokenFormat::Uuid => uuid::Uuid::new_v4().to_string(),
+        TokenFormat::Sequence(formatter) => {
+            let mut seq = sequences.reserve(context, policy, 1).await?;
+            if let Some(capacity) = formatter.capacity() {
+                check_capacity(options, context, policy, seq, capacity)?;
+            }
+            if let SequenceFormat::Permuted(len) = formatter {
+                seq = permute(options.permutation.as_deref(), context, policy, seq, *len)?;
+            }
+            formatter.apply(seq, policy, value)
+        }
+        TokenFormat::Random(format) => random(format)?,
+        TokenFormat::Fpe(_)
+        | TokenFormat::Pan(_)
+        | TokenFormat::ClassPreserving
+        | TokenFormat::Email(_)
+        | TokenFormat::DateShift(_)
+        | TokenFormat::Pseudonym(_)
+        | TokenFormat::Mask(_) => {
+            return Err(TokenError::GenerationFailure(
+                "token is derived from the value".to_string(),
+            )
+            .into())
+        }
     };
+    Ok(raw_token)
+}
+
+/// Fails once `seq` no longer fits in the format, warning (once per process)
+/// when it reaches the usage threshold: values may have been skipped, e.g.
+/// reserved in bulk or lost with a block, so the threshold itself may never show up
+fn check_capacity(
+    options: &SequenceOptions,
+    context: &ExecutionContext,
+    policy: &Policy,
+    seq: i64,
+    capacity: i64,
+) -> Result<(), TokenError> {
+    if seq > capacity {
+        return Err(TokenError::SequenceExhausted(format!(
+            "policy {} cannot produce more than {} tokens",
+            policy.code, capacity
+        )));
+    }
+    if seq >= usage_threshold(capacity, options.usage_warning)
+        && options.first_warning(context.tenant_label(), &policy.code)
+    {
+        tracing::warn!(
+            tenant = %context.tenant_label(),
+            policy = %policy.code,
+            "sequence has used {} of its {} values",
+            seq,
+            capacity
+        );
+    }
+    Ok(())
+}
+
+/// The sequence value at which `ratio` of the capacity is used
+fn usage_threshold(capacity: i64, ratio: f64) -> i64 {
+    ((capacity as f64 * ratio).ceil() as i64).clamp(1, capacity.max(1))
+}
+
+/// Runs `seq` through the tenant's permutation of `0..10^len`
+fn permute(
+    permutation: Option<&KeyedPermutation>,
+    context: &ExecutionContext,
+    policy: &Policy,
+    seq: i64,
+    len: usize,
+) -> Result<i64, Error> {
+    let permutation =
+        permutation.ok_or_else(|| Error::MissingConfig("sequence permutation".to_string()))?;
+    let domain = u32::try_from(len)
+        .ok()
+        .and_then(|len| 10u64.checked_pow(len))
+        .filter(|domain| *domain <= i64::MAX as u64)
+        .ok_or_else(|| {
+            TokenError::GenerationFailure(format!("permuted length {len} is too large"))
+        })?;
+    let permuted =
+        permutation.permute(&context.tenant_label(), &policy.code, seq as u64, domain)?;
+    Ok(permuted as i64)
+}
+
+fn random(format: &RandomFormat) -> Result<String, TokenError> {
+    let chars = format.charset.chars();
+    if chars.len() < 2
+        || chars
+            .iter()
+            .enumerate()
+            .any(|(i, c)| chars[..i].contains(c))
+    {
+        return Err(TokenError::GenerationFailure(
+            "charset must contain at least 2 distinct characters".to_string(),
+        ));
+    }
+    // thread_rng is a CSPRNG
+    let mut rng = rand::thread_rng();
+    Ok((0..format.length)
+        .map(|_| *chars.choose(&mut rng).expect("charset is not empty"))
+        .collect())
+}
+
+/// Replaces the digits that are not kept by random ones, adjusting the last
+/// of them to honor the Luhn requirement; the token never equals the value.
+fn pan_token(policy: &Policy, luhn: LuhnCheck, value: Secret<String>) -> Result<Token, TokenError> {
+    let pan = value.expose_secret();
+    if !(12..=19).contains(&pan.len()) || !pan.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(TokenError::InvalidValue(
+            "PAN must be made of 12 to 19 digits".to_string(),
+        ));
+    }
+    let middle_len = pan
+        .len()
+        .saturating_sub(policy.keep_left + policy.keep_right);
+    if middle_len == 0 {
+        return Err(TokenError::InvalidValue(
+            "no digit left to replace once keep_left/keep_right applied".to_string(),
+        ));
+    }
+    let prefix_len = policy.prefix.as_ref().map_or(0, |p| p.len());
+
+    let mut rng = rand::thread_rng();
+    for _ in 0..MAX_PAN_ATTEMPTS {
+        let mut middle: Vec<u8> = (0..middle_len)
+            .map(|_| rng.gen_range(b'0'..=b'9'))
+            .collect();
+        let start = rng.gen_range(0..10);
+        // changing a single digit always changes the checksum
+        for shift in 0..10 {
+            middle[middle_len - 1] = b'0' + (start + shift) % 10;
+            let raw_token = String::from_utf8(middle.clone()).expect("ascii digits");
+            let token = format(policy, raw_token, Secret::new(pan.clone()));
+            let digits = &token[prefix_len..];
+            if is_luhn_valid(digits) == (luhn == LuhnCheck::Pass) && digits != pan {
+                return Ok(token.into());
+            }
+        }
+    }
+    Err(TokenError::GenerationFailure(
+        "could not generate a PAN token different from the value".to_string(),
+    ))
+}
+
+/// Replaces each character that is not kept by a random one of the same class,
+/// letters without case being replaced by lowercase ones; the token never equals the value.
+fn class_preserving_token(policy: &Policy, value: Secret<String>) -> Result<Token, TokenError> {
+    let (_, middle, _) = policy.split(value.expose_secret());
+    if !middle.chars().any(char::is_alphanumeric) {
+        return Err(TokenError::InvalidValue(
+            "no letter or digit left to replace once keep_left/keep_right applied".to_string(),
+        ));
+    }
+    let mut rng = rand::thread_rng();
+    for _ in 0..MAX_CLASS_PRESERVING_ATTEMPTS {
+        let raw_token: String = middle
+            .chars()
+            .map(|c| {
+                if c.is_uppercase() {
+                    rng.gen_range('A'..='Z')
+                } else if c.is_alphabetic() {
+                    rng.gen_range('a'..='z')
+                } else if c.is_numeric() {
+                    rng.gen_range('0'..='9')
+                } else {
+                    c
+                }
+            })
+            .collect();
+        if raw_token != middle {
+            let value = Secret::new(value.expose_secret().clone());
+            return Ok(format(policy, raw_token, value).into());
+        }
+    }
+    Err(TokenError::GenerationFailure(
+        "could not generate a token different from the value".to_string(),
+    ))
+}
+
+pub fn format<T>(policy: &Policy, raw_token: String, value: Secret<T>) -> String
+where
+    T: ToString + Zeroize,
+{
+    let unsecure = value.expose_secret().to_string();
+    let (left, _, right) = policy.split(&unsecure);
     std::format!(
         "{}{}{}{}",
         &policy.prefix.clone().unwrap_or("".into()),
@@ -63,73 +415,319 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::core::token::SequenceFormat;
-    use chrono::format::Item::Error;
+    use crate::core::context::{Caller, CallerType};
+    use crate::core::token::{SequenceFormat, TokenFormat};
+    use crate::core::util::LengthUnit;
     use mockall::*;
+    use std::collections::HashSet;
+    use std::ops::Deref;
     mock! {
         RawGen {}
+        #[async_trait]
         impl RawTokenGenerator for RawGen {
-            fn generate(&self, token_format: &TokenFormat) -> Result<String, TokenError>;
+            async fn generate(
+                &self,
+                context: &ExecutionContext,
+                policy: &Policy,
+                value: &Secret<String>,
+            ) -> Result<String, Error>;
         }
     }
+    /// In memory vault refusing the tokens it stores already
+    #[derive(Default)]
+    struct SetVault {
+        tokens: Mutex<HashSet<String>>,
+    }
 
-    #[test]
-    fn default_token_generator_generate_nominal_case() {
+    #[async_trait]
+    impl TokenVault for SetVault {
+        async fn store_token(
+            &self,
+            _context: &ExecutionContext,
+            _policy: &Policy,
+            _subject: Option<&str>,
+            token: &Token,
+            _value: &Secret<String>,
+        ) -> Result<Token, Error> {
+            if self.tokens.lock().unwrap().insert(token.to_string()) {
+                Ok(token.clone())
+            } else {
+                Err(Error::Generic(
+                    ErrorCode::UniqueViolation,
+                    "token already stored".to_string(),
+                    Default::default(),
+                ))
+            }
+        }
+
+        async fn find_token(
+            &self,
+            _context: &ExecutionContext,
+            _policy: &Policy,
+            _subject: Option<&str>,
+            _value: &Secret<String>,
+        ) -> Result<Option<Token>, Error> {
+            Ok(None)
+        }
+
+        async fn resolve_token(
+            &self,
+            _context: &ExecutionContext,
+            _policy: &Policy,
+            _token: &Token,
+        ) -> Result<Option<Secret<String>>, Error> {
+            Ok(None)
+        }
+    }
+
+    fn sample_context() -> ExecutionContext {
+        ExecutionContext::new(
+            None,
+            Caller::new("007".to_string(), CallerType::USER),
+            HashSet::new(),
+        )
+    }
+
+    #[tokio::test]
+    async fn default_token_generator_generate_nominal_case() {
         let mut raw_generator = MockRawGen::new();
         raw_generator
             .expect_generate()
-            .return_once(move |_| Ok("_1_".to_string()));
+            .return_once(move |_, _, _| Ok("_1_".to_string()));
         let generator = DefaultTokenGenerator::new(raw_generator);
         let policy = Policy {
-            code: "sales".to_string(),
-            format: TokenFormat::Sequence(SequenceFormat::Raw),
             prefix: Some("TOK-".to_string()),
             keep_left: 2,
             keep_right: 3,
+            ..Policy::new("sales", TokenFormat::Sequence(SequenceFormat::Raw))
         };
 
         assert_eq!(
             generator
-                .generate(&policy, Secret::new("CARMEN MCCALLUM".to_string()))
+                .generate(
+                    &sample_context(),
+                    &policy,
+                    Secret::new("CARMEN MCCALLUM".to_string())
+                )
+                .await
                 .unwrap()
                 .deref(),
             &"TOK-CA_1_LUM".to_string()
         );
     }
 
-    #[test]
-    fn default_token_generator_generate_propagate_error() {
+    #[tokio::test]
+    async fn default_token_generator_generate_propagate_error() {
         let mut raw_generator = MockRawGen::new();
-        raw_generator.expect_generate().return_once(move |_| {
-            Err(TokenError::GenerationFailure(
-                "db not reachable".to_string(),
-            ))
+        raw_generator.expect_generate().return_once(move |_, _, _| {
+            Err(TokenError::GenerationFailure("db not reachable".to_string()).into())
         });
         let generator = DefaultTokenGenerator::new(raw_generator);
         let policy = Policy {
-            code: "sales".to_string(),
-            format: TokenFormat::Sequence(SequenceFormat::Raw),
             prefix: Some("TOK-".to_string()),
             keep_left: 2,
             keep_right: 3,
+            ..Policy::new("sales", TokenFormat::Sequence(SequenceFormat::Raw))
         };
 
-        assert_eq!(
-            generator
-                .generate(&policy, Secret::new("CARMEN MCCALLUM".to_string()))
-                .expect_err("Expecting error"),
-            TokenError::GenerationFailure("db not reachable".to_string())
+        let res = generator
+            .generate(
+                &sample_context(),
+                &policy,
+                Secret::new("CARMEN MCCALLUM".to_string()),
+            )
+            .await;
+        assert!(matches!(
+            res,
+            Err(Error::TokenError(TokenError::GenerationFailure(msg))) if msg == "db not reachable"
+        ));
+    }
+
+    #[tokio::test]
+    async fn generate_stored_retries_on_collisions() {
+        let mut raw_generator = MockRawGen::new();
+        let mut raw_tokens = ["1", "1", "1"].into_iter();
+        raw_generator
+            .expect_generate()
+            .returning(move |_, _, _| Ok(raw_tokens.next().unwrap_or("2").to_string()));
+        let generator = DefaultTokenGenerator::new(raw_generator);
+        let policy = Policy::new("sales", TokenFormat::Sequence(SequenceFormat::Raw));
+        let vault = SetVault::default();
+        let context = sample_context();
+        let store = |value: &str| {
+            generator.generate_stored(
+                &context,
+                &policy,
+                None,
+                Secret::new(value.to_string()),
+                &vault,
+            )
+        };
+
+        assert_eq!(store("CARMEN").await.unwrap().as_str(), "1");
+        // "1" twice more before "2"
+        assert_eq!(store("SALLY").await.unwrap().as_str(), "2");
+        // only "2" from now on
+        assert!(matches!(
+            store("DIANA").await,
+            Err(Error::TokenError(TokenError::GenerationFailure(_)))
+        ));
+    }
+
+    fn pan_policy(luhn: LuhnCheck) -> Policy {
+        Policy {
+            prefix: Some("TOK-".to_string()),
+            keep_left: 6,
+            keep_right: 4,
+            ..Policy::new("pan", TokenFormat::Pan(luhn))
+        }
+    }
+
+    fn generate_pan(policy: &Policy, pan: &str) -> Result<Token, TokenError> {
+        match policy.format {
+            TokenFormat::Pan(luhn) => pan_token(policy, luhn, Secret::new(pan.to_string())),
+            _ => panic!("PAN policy expected"),
+        }
+    }
+
+    #[test]
+    fn usage_warning_is_due_once_past_the_threshold() {
+        let options = SequenceOptions::default();
+        let policy = Policy::new(
+            "sales",
+            TokenFormat::Sequence(SequenceFormat::PaddedInt(2, '0')),
         );
+        // the threshold (80) itself was skipped
+        check_capacity(&options, &sample_context(), &policy, 79, 99).unwrap();
+        assert!(options.warned.lock().unwrap().is_empty());
+        check_capacity(&options, &sample_context(), &policy, 85, 99).unwrap();
+        assert!(!options.first_warning(String::new(), "sales"));
+        assert!(options.first_warning(String::new(), "other"));
+    }
+
+    #[test]
+    fn usage_threshold_samples() {
+        assert_eq!(usage_threshold(9999, 0.8), 8000);
+        assert_eq!(usage_threshold(10, 0.85), 9);
+        assert_eq!(usage_threshold(10, 0.0), 1);
+        assert_eq!(usage_threshold(10, 1.5), 10);
+    }
+
+    #[test]
+    fn pan_token_keeps_first_and_last_digits() {
+        for luhn in [LuhnCheck::Fail, LuhnCheck::Pass] {
+            for _ in 0..50 {
+                let token = generate_pan(&pan_policy(luhn), "4111111111111111").unwrap();
+                assert_eq!(token.len(), "TOK-4111111111111111".len());
+                assert!(token.starts_with("TOK-411111"));
+                assert!(token.ends_with("1111"));
+                assert!(token[4..].bytes().all(|b| b.is_ascii_digit()));
+                assert_eq!(is_luhn_valid(&token[4..]), luhn == LuhnCheck::Pass);
+                assert_ne!(&token[4..], "4111111111111111");
+            }
+        }
+    }
+
+    #[test]
+    fn pan_token_never_equals_the_value() {
+        // a single digit to replace, among which only one passes the Luhn check
+        let mut policy = pan_policy(LuhnCheck::Pass);
+        policy.keep_left = 15;
+        policy.keep_right = 0;
+        assert!(matches!(
+            generate_pan(&policy, "4111111111111111"),
+            Err(TokenError::GenerationFailure(_))
+        ));
+
+        policy.format = TokenFormat::Pan(LuhnCheck::Fail);
+        let token = generate_pan(&policy, "4111111111111111").unwrap();
+        assert_ne!(&token[4..], "4111111111111111");
+    }
+
+    #[test]
+    fn pan_token_rejects_invalid_values() {
+        let policy = pan_policy(LuhnCheck::Fail);
+        for pan in ["4111-1111-1111-1111", "41111111111", "41111111111111111111"] {
+            assert!(matches!(
+                generate_pan(&policy, pan),
+                Err(TokenError::InvalidValue(_))
+            ));
+        }
+        let mut policy = pan_policy(LuhnCheck::Fail);
+        policy.keep_left = 12;
+        assert!(matches!(
+            generate_pan(&policy, "4111111111111111"),
+            Err(TokenError::InvalidValue(_))
+        ));
+    }
+
+    fn class_preserving_policy(keep_left: usize, keep_right: usize) -> Policy {
+        Policy {
+            keep_left,
+            keep_right,
+            ..Policy::new("postcodes", TokenFormat::ClassPreserving)
+        }
+    }
+
+    #[test]
+    fn class_preserving_token_keeps_the_shape() {
+        let shape = |value: &str| -> String {
+            value
+                .chars()
+                .map(|c| match c {
+                    'A'..='Z' => 'A',
+                    'a'..='z' => 'a',
+                    '0'..='9' => '9',
+                    c => c,
+                })
+                .collect()
+        };
+        for value in ["AB12 3CD", "X-1234-ab", "Zoé Åsa"] {
+            for _ in 0..20 {
+                let token = class_preserving_token(
+                    &class_preserving_policy(0, 0),
+                    Secret::new(value.to_string()),
+                )
+                .unwrap();
+                assert_eq!(
+                    shape(&token),
+                    shape(&value.replace('é', "e").replace('Å', "A"))
+                );
+                assert_ne!(token.as_str(), value);
+            }
+        }
+    }
+
+    #[test]
+    fn class_preserving_token_honors_keep_left_and_right() {
+        let token = class_preserving_token(
+            &class_preserving_policy(2, 3),
+            Secret::new("AB12 3CD".to_string()),
+        )
+        .unwrap();
+        assert!(token.starts_with("AB"));
+        assert!(token.ends_with("3CD"));
+        assert_eq!(token.chars().nth(4), Some(' '));
+    }
+
+    #[test]
+    fn class_preserving_token_rejects_values_without_letters_or_digits() {
+        assert!(matches!(
+            class_preserving_token(
+                &class_preserving_policy(2, 0),
+                Secret::new("AB -/".to_string())
+            ),
+            Err(TokenError::InvalidValue(_))
+        ));
     }
 
     #[test]
     fn format_nominal_case() {
         let policy = Policy {
-            code: "sales".to_string(),
-            format: TokenFormat::Sequence(SequenceFormat::Raw),
             prefix: Some("TOK-".to_string()),
             keep_left: 2,
             keep_right: 3,
+            ..Policy::new("sales", TokenFormat::Sequence(SequenceFormat::Raw))
         };
 
         assert_eq!(
@@ -145,11 +743,9 @@ mod tests {
     #[test]
     fn format_with_value_too_short_for_keep_left() {
         let policy = Policy {
-            code: "sales".to_string(),
-            format: TokenFormat::Sequence(SequenceFormat::Raw),
             prefix: Some("TOK-".to_string()),
             keep_left: 4,
-            keep_right: 0,
+            ..Policy::new("sales", TokenFormat::Sequence(SequenceFormat::Raw))
         };
 
         assert_eq!(
@@ -161,11 +757,9 @@ mod tests {
     #[test]
     fn format_with_value_too_short_for_keep_right() {
         let policy = Policy {
-            code: "sales".to_string(),
-            format: TokenFormat::Sequence(SequenceFormat::Raw),
             prefix: Some("TOK-".to_string()),
-            keep_left: 0,
             keep_right: 4,
+            ..Policy::new("sales", TokenFormat::Sequence(SequenceFormat::Raw))
         };
 
         assert_eq!(
@@ -177,11 +771,10 @@ mod tests {
     #[test]
     fn format_with_value_when_keep_right_overlap_keep_left() {
         let policy = Policy {
-            code: "sales".to_string(),
-            format: TokenFormat::Sequence(SequenceFormat::Raw),
             prefix: Some("TOK-".to_string()),
             keep_left: 4,
             keep_right: 4,
+            ..Policy::new("sales", TokenFormat::Sequence(SequenceFormat::Raw))
         };
 
         assert_eq!(
@@ -193,4 +786,49 @@ mod tests {
             "TOK-CARM_1_EN"
         );
     }
+
+    #[test]
+    fn format_with_non_ascii_value() {
+        let mut policy = Policy {
+            keep_left: 2,
+            keep_right: 1,
+            ..Policy::new("names", TokenFormat::Sequence(SequenceFormat::Raw))
+        };
+        assert_eq!(
+            format(
+                &policy,
+                "_1_".to_string(),
+                Secret::new("Åsa Öberg".to_string())
+            ),
+            "Ås_1_g"
+        );
+        assert_eq!(
+            format(
+                &policy,
+                "_1_".to_string(),
+                Secret::new("東京都庁".to_string())
+            ),
+            "東京_1_庁"
+        );
+
+        // "Zoé" with a combining acute accent
+        policy.keep_left = 0;
+        assert_eq!(
+            format(
+                &policy,
+                "_1_".to_string(),
+                Secret::new("Zoe\u{301}".to_string())
+            ),
+            "_1_\u{301}"
+        );
+        policy.length_unit = LengthUnit::Graphemes;
+        assert_eq!(
+            format(
+                &policy,
+                "_1_".to_string(),
+                Secret::new("Zoe\u{301}".to_string())
+            ),
+            "_1_e\u{301}"
+        );
+    }
 }
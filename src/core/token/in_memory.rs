@@ -1,47 +1,115 @@
 use std::ops::Deref;
 
-use crate::core::token::{RawTokenGenerator, TokenError, TokenFormat};
+use crate::core::context::ExecutionContext;
+use crate::core::crypto::KeyedPermutation;
+use crate::core::token::generator::{raw_token, SequenceOptions};
+use crate::core::token::{Policy, RawTokenGenerator, SequenceSource};
+use crate::error::Error;
+use async_trait::async_trait;
+use secrecy::Secret;
 use std::sync::atomic::{AtomicI64, Ordering};
 use std::sync::Arc;
 
 #[derive(Clone, Debug)]
-struct InMemoryRawTokenGenerator {
+pub struct InMemoryRawTokenGenerator {
     sequence: Arc<AtomicI64>,
+    options: SequenceOptions,
 }
 
 impl InMemoryRawTokenGenerator {
     pub fn new() -> InMemoryRawTokenGenerator {
         InMemoryRawTokenGenerator {
             sequence: Arc::new(AtomicI64::new(1)),
+            options: SequenceOptions::default(),
         }
     }
+
+    /// Enables the `Permuted` sequence format
+    pub fn with_permutation(mut self, permutation: Arc<KeyedPermutation>) -> Self {
+        self.options.permutation = Some(permutation);
+        self
+    }
+
+    /// Ratio (0.8 by default) of the capacity of a sequence past which a warning is emitted
+    pub fn with_usage_warning(mut self, ratio: f64) -> Self {
+        self.options.usage_warning = ratio;
+        self
+    }
 }
 
+impl Default for InMemoryRawTokenGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl SequenceSource for InMemoryRawTokenGenerator {
+    async fn reserve(
+        &self,
+        _context: &ExecutionContext,
+        _policy: &Policy,
+        count: i64,
+    ) -> Result<i64, Error> {
+        Ok(self.sequence.deref().fetch_add(count, Ordering::SeqCst))
+    }
+
+    async fn next_value(
+        &self,
+        _context: &ExecutionContext,
+        _policy: &Policy,
+    ) -> Result<i64, Error> {
+        Ok(self.sequence.load(Ordering::SeqCst))
+    }
+}
+
+#[async_trait]
 impl RawTokenGenerator for InMemoryRawTokenGenerator {
-    fn generate(&self, token_format: &TokenFormat) -> Result<String, TokenError> {
-        let raw_token = match &token_format {
-            TokenFormat::Uuid => uuid::Uuid::new_v4().to_string(),
-            TokenFormat::Sequence(formatter) => {
-                let seq = self.sequence.deref().fetch_add(1, Ordering::SeqCst);
-                formatter.apply(seq)
-            }
-        };
-        Ok(raw_token)
+    async fn generate(
+        &self,
+        context: &ExecutionContext,
+        policy: &Policy,
+        value: &Secret<String>,
+    ) -> Result<String, Error> {
+        raw_token(self, &self.options, context, policy, value).await
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::core::token::SequenceFormat;
+    use crate::core::context::{Caller, CallerType};
+    use crate::core::token::{
+        Charset, RandomFormat, SequenceFormat, Template, TokenError, TokenFormat,
+    };
+    use std::collections::HashSet;
+
+    async fn generate(
+        generator: &InMemoryRawTokenGenerator,
+        token_format: &TokenFormat,
+    ) -> Result<String, Error> {
+        let policy = Policy::new("sales", token_format.clone());
+        let context = ExecutionContext::new(
+            None,
+            Caller::new("007".to_string(), CallerType::USER),
+            HashSet::new(),
+        );
+        generator
+            .generate(
+                &context,
+                &policy,
+                &Secret::new("CARMEN MCCALLUM".to_string()),
+            )
+            .await
+    }
 
-    #[test]
-    fn in_memory_raw_token_generator_samples() {
+    #[tokio::test]
+    async fn in_memory_raw_token_generator_samples() {
         let generator = InMemoryRawTokenGenerator::new();
         let token_format = TokenFormat::Sequence(SequenceFormat::Raw);
 
-        let seq1 = &generator.generate(&token_format);
-        let seq2 = &generator.generate(&token_format);
+        let seq1 = &generate(&generator, &token_format).await;
+        let seq2 = &generate(&generator, &token_format).await;
 
         let x1 = seq1.as_ref().unwrap().deref();
         let x2 = seq2.as_ref().unwrap().deref();
@@ -51,13 +119,13 @@ mod tests {
         assert_eq!(x2.parse::<i64>().unwrap(), 2);
     }
 
-    #[test]
-    fn in_memory_raw_token_generator_uuid() {
+    #[tokio::test]
+    async fn in_memory_raw_token_generator_uuid() {
         let generator = InMemoryRawTokenGenerator::new();
         let token_format = TokenFormat::Uuid;
 
-        let seq1 = &generator.generate(&token_format);
-        let seq2 = &generator.generate(&token_format);
+        let seq1 = &generate(&generator, &token_format).await;
+        let seq2 = &generate(&generator, &token_format).await;
 
         let x1 = seq1.as_ref().unwrap().deref();
         let x2 = seq2.as_ref().unwrap().deref();
@@ -69,4 +137,115 @@ mod tests {
         assert!(regex.is_match(x1));
         assert!(regex.is_match(x2));
     }
+
+    #[tokio::test]
+    async fn in_memory_raw_token_generator_random() {
+        let generator = InMemoryRawTokenGenerator::new();
+        for charset in [
+            Charset::Digits,
+            Charset::Alphanumeric,
+            Charset::CrockfordBase32,
+            Charset::Custom("éàè".to_string()),
+        ] {
+            let chars = charset.chars();
+            let token_format = TokenFormat::Random(RandomFormat {
+                length: 12,
+                charset,
+            });
+            let token = generate(&generator, &token_format).await.unwrap();
+            assert_eq!(token.chars().count(), 12);
+            assert!(token.chars().all(|c| chars.contains(&c)));
+        }
+    }
+
+    #[tokio::test]
+    async fn in_memory_raw_token_generator_random_invalid_charset() {
+        let generator = InMemoryRawTokenGenerator::new();
+        for charset in ["a", "aba"] {
+            let token_format = TokenFormat::Random(RandomFormat {
+                length: 12,
+                charset: Charset::Custom(charset.to_string()),
+            });
+            assert!(matches!(
+                generate(&generator, &token_format).await,
+                Err(Error::TokenError(TokenError::GenerationFailure(_)))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn in_memory_raw_token_generator_permuted() {
+        let generator = InMemoryRawTokenGenerator::new()
+            .with_permutation(Arc::new(KeyedPermutation::new(&[4u8; 32]).unwrap()));
+        let token_format = TokenFormat::Sequence(SequenceFormat::Permuted(6));
+
+        let mut tokens = Vec::new();
+        for _ in 0..100 {
+            tokens.push(generate(&generator, &token_format).await.unwrap());
+        }
+        assert!(tokens
+            .iter()
+            .all(|t| t.len() == 6 && t.bytes().all(|b| b.is_ascii_digit())));
+        assert_eq!(tokens.iter().collect::<HashSet<_>>().len(), 100);
+        assert_ne!(tokens[0], "000001");
+
+        // same counter, same key: same token
+        let replay = InMemoryRawTokenGenerator::new()
+            .with_permutation(Arc::new(KeyedPermutation::new(&[4u8; 32]).unwrap()));
+        assert_eq!(generate(&replay, &token_format).await.unwrap(), tokens[0]);
+    }
+
+    #[tokio::test]
+    async fn in_memory_raw_token_generator_permuted_requires_a_key() {
+        let generator = InMemoryRawTokenGenerator::new();
+        let token_format = TokenFormat::Sequence(SequenceFormat::Permuted(6));
+        assert!(matches!(
+            generate(&generator, &token_format).await,
+            Err(Error::MissingConfig(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn in_memory_raw_token_generator_permuted_overflow() {
+        let generator = InMemoryRawTokenGenerator::new()
+            .with_permutation(Arc::new(KeyedPermutation::new(&[4u8; 32]).unwrap()));
+        let token_format = TokenFormat::Sequence(SequenceFormat::Permuted(1));
+        for _ in 1..10 {
+            generate(&generator, &token_format).await.unwrap();
+        }
+        assert!(matches!(
+            generate(&generator, &token_format).await,
+            Err(Error::TokenError(TokenError::SequenceExhausted(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn in_memory_raw_token_generator_padded_int_never_outgrows_its_width() {
+        let generator = InMemoryRawTokenGenerator::new();
+        let token_format = TokenFormat::Sequence(SequenceFormat::PaddedInt(2, '0'));
+        for _ in 1..100 {
+            assert_eq!(generate(&generator, &token_format).await.unwrap().len(), 2);
+        }
+        assert!(matches!(
+            generate(&generator, &token_format).await,
+            Err(Error::TokenError(TokenError::SequenceExhausted(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn in_memory_raw_token_generator_template() {
+        let generator = InMemoryRawTokenGenerator::new();
+        let token_format = TokenFormat::Sequence(SequenceFormat::Template(
+            Template::parse("{policy}-{value:0:3}-{seq:4}").unwrap(),
+        ));
+
+        assert_eq!(
+            generate(&generator, &token_format).await.unwrap(),
+            "sales-CAR-0001"
+        );
+        assert_eq!(
+            generate(&generator, &token_format).await.unwrap(),
+            "sales-CAR-0002"
+        );
+    }
 }
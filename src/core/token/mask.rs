@@ -0,0 +1,158 @@
+use crate::core::token::{email, Policy, TokenError};
+
+/// Irreversible masking of the part of the value that is not kept,
+/// e.g. `****-****-****-1234`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MaskFormat {
+    /// character replacing the masked ones
+    pub mask_char: char,
+    /// characters left as is in a length preserving mask, e.g. `"- "`
+    pub separators: Option<String>,
+    pub length: MaskLength,
+    /// masks only the local part of an email address, e.g. `j***@example.com`
+    #[serde(default)]
+    pub keep_domain: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MaskLength {
+    /// one mask character per masked one (counted in the policy length unit)
+    Preserve,
+    /// always the given number of mask characters, hiding the length of the value
+    /// (separators are masked too)
+    Fixed(usize),
+}
+
+impl MaskFormat {
+    /// Masks the value, keeping the prefix, keep_left and keep_right of the policy
+    /// (applying to the local part of the address when the domain is kept)
+    pub fn mask(&self, policy: &Policy, value: &str) -> Result<String, TokenError> {
+        let mut masked = String::new();
+        masked.push_str(policy.prefix.as_deref().unwrap_or_default());
+        if self.keep_domain {
+            let (local, domain) = email::split_address(value)?;
+            self.mask_part(policy, local, &mut masked);
+            masked.push('@');
+            masked.push_str(domain);
+        } else {
+            self.mask_part(policy, value, &mut masked);
+        }
+        Ok(masked)
+    }
+
+    /// Masks the whole value when it is too short to keep keep_left and keep_right
+    /// and still mask something: it is never shown as is
+    fn mask_part(&self, policy: &Policy, value: &str, masked: &mut String) {
+        let (left, middle, right) = match policy.split(value) {
+            (_, "", _) => ("", value, ""),
+            parts => parts,
+        };
+        masked.push_str(left);
+        match self.length {
+            MaskLength::Preserve => {
+                let separators = self.separators.as_deref().unwrap_or_default();
+                for unit in policy.length_unit.units(middle) {
+                    let mut chars = unit.chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(c), None) if separators.contains(c) => masked.push(c),
+                        _ => masked.push(self.mask_char),
+                    }
+                }
+            }
+            MaskLength::Fixed(len) => masked.extend(std::iter::repeat_n(self.mask_char, len)),
+        }
+        masked.push_str(right);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::token::TokenFormat;
+    use crate::core::util::LengthUnit;
+
+    fn sample_policy(keep_left: usize, keep_right: usize, format: &MaskFormat) -> Policy {
+        Policy {
+            keep_left,
+            keep_right,
+            ..Policy::new("display", TokenFormat::Mask(format.clone()))
+        }
+    }
+
+    fn mask(format: MaskFormat, keep_left: usize, keep_right: usize, value: &str) -> String {
+        format
+            .mask(&sample_policy(keep_left, keep_right, &format), value)
+            .expect("Failed to mask")
+    }
+
+    #[test]
+    fn mask_preserving_separators() {
+        let format = MaskFormat {
+            mask_char: '*',
+            separators: Some("-".to_string()),
+            length: MaskLength::Preserve,
+            keep_domain: false,
+        };
+        assert_eq!(
+            mask(format, 0, 4, "4111-1111-1111-1234"),
+            "****-****-****-1234"
+        );
+    }
+
+    #[test]
+    fn mask_preserving_length() {
+        let format = MaskFormat {
+            mask_char: '#',
+            separators: None,
+            length: MaskLength::Preserve,
+            keep_domain: false,
+        };
+        assert_eq!(mask(format.clone(), 1, 0, "Zoé Ösel"), "Z#######");
+        assert_eq!(mask(format.clone(), 2, 2, "abc"), "###");
+        assert_eq!(mask(format, 2, 2, "abcd"), "####");
+    }
+
+    #[test]
+    fn mask_with_fixed_length() {
+        let format = MaskFormat {
+            mask_char: '*',
+            separators: Some("-".to_string()),
+            length: MaskLength::Fixed(3),
+            keep_domain: false,
+        };
+        assert_eq!(mask(format.clone(), 1, 0, "john"), "j***");
+        assert_eq!(mask(format.clone(), 0, 4, "4111-1111-1111-1234"), "***1234");
+        assert_eq!(mask(format, 0, 4, "1234"), "***");
+    }
+
+    #[test]
+    fn mask_keeps_prefix_and_counts_graphemes() {
+        let format = MaskFormat {
+            mask_char: '*',
+            separators: None,
+            length: MaskLength::Preserve,
+            keep_domain: false,
+        };
+        let mut policy = sample_policy(1, 0, &format);
+        policy.prefix = Some("M-".to_string());
+        policy.length_unit = LengthUnit::Graphemes;
+        // "Zoé" with a combining acute accent
+        assert_eq!(format.mask(&policy, "Zoe\u{301}").unwrap(), "M-Z**");
+    }
+
+    #[test]
+    fn mask_keeping_the_email_domain() {
+        let format = MaskFormat {
+            mask_char: '*',
+            separators: None,
+            length: MaskLength::Fixed(3),
+            keep_domain: true,
+        };
+        assert_eq!(
+            mask(format.clone(), 1, 0, "john.doe@example.com"),
+            "j***@example.com"
+        );
+        let policy = sample_policy(1, 0, &format);
+        assert!(format.mask(&policy, "john.doe").is_err());
+    }
+}
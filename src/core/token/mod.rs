@@ -1,5 +1,24 @@
+mod blocks;
+mod date;
+mod email;
 mod generator;
 mod in_memory;
+mod mask;
+mod rotation;
+mod template;
+#[allow(clippy::module_inception)]
 mod token;
+mod tokenizer;
+mod vault;
 
+pub use blocks::BlockSequenceSource;
+pub use date::DateShiftFormat;
+pub use email::EmailFormat;
+pub use generator::{format, DefaultTokenGenerator, SequenceRawTokenGenerator};
+pub use in_memory::InMemoryRawTokenGenerator;
+pub use mask::{MaskFormat, MaskLength};
+pub use rotation::{RotationJob, RotationProgress, VaultKeys};
+pub use template::Template;
 pub use token::*;
+pub use tokenizer::Tokenizer;
+pub use vault::TokenVault;
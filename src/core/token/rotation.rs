@@ -0,0 +1,157 @@
+use crate::core::context::{ExecutionContext, Permission};
+use crate::core::util;
+use async_trait::async_trait;
+
+use crate::error::Error;
+
+/// Versioned keys encrypting the values of the vault.
+///
+/// Every version of the data keys is kept: the values encrypted with a
+/// previous one still decrypt until they are re-encrypted.
+#[async_trait]
+pub trait VaultKeys {
+    /// Switches the tenant to a new data key, returning its version; requires
+    /// `Permission::KeyRotate`.
+    async fn rotate_data_key(&self, context: &ExecutionContext) -> Result<i32, Error>;
+
+    /// Wraps again every data key of the tenant with the latest version of the
+    /// master key, returning how many were; requires `Permission::KeyRotate`.
+    async fn rewrap_data_keys(&self, context: &ExecutionContext) -> Result<usize, Error>;
+
+    /// Wraps again, with the latest data key, the next batch of subject keys
+    /// wrapped with a previous one; returns the ids of the rewrapped keys.
+    /// The subject keys themselves are kept, and so are the values they encrypt.
+    /// Requires `Permission::KeyRotate`.
+    async fn rewrap_subject_keys(
+        &self,
+        context: &ExecutionContext,
+        paging: util::Paging,
+    ) -> Result<util::Page<i64>, Error>;
+
+    /// Re-encrypts, with the latest data key, the next batch of values encrypted
+    /// with a previous one; returns the ids of the re-encrypted tokens.
+    /// The values of subjects are left out: see `rewrap_subject_keys`.
+    /// Values that no longer decrypt are logged and skipped, the cursor moving past them.
+    /// Requires `Permission::KeyRotate`.
+    async fn reencrypt_tokens(
+        &self,
+        context: &ExecutionContext,
+        paging: util::Paging,
+    ) -> Result<util::Page<i64>, Error>;
+
+    /// Destroys the data key of `subject`: its values can no longer be
+    /// decrypted, and fail with `ErrorCode::Erased`. Requires `Permission::KeyShred`.
+    ///
+    /// Fails with `ErrorCode::NotFound` when the subject has no key.
+    ///
+    /// Only the stored key is destroyed: a database backup taken beforehand
+    /// still holds it, wrapped by a data key of the tenant, and restores the
+    /// values along with it. Backups must expire for the erasure to be complete.
+    async fn shred_subject_key(
+        &self,
+        context: &ExecutionContext,
+        subject: &str,
+    ) -> Result<(), Error>;
+
+    /// Destroys every data key of the tenant, erasing all of its vault values;
+    /// no new value can be stored afterwards. Requires `Permission::KeyShred`.
+    ///
+    /// As for `shred_subject_key`, database backups taken beforehand still hold
+    /// the keys, which the master key unwraps: the erasure is complete once those
+    /// backups expire, or once the master key versions wrapping them are destroyed.
+    async fn shred_data_keys(&self, context: &ExecutionContext) -> Result<(), Error>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RotationProgress {
+    /// Batches processed so far
+    pub batches: usize,
+    /// Values re-encrypted so far
+    pub reencrypted: usize,
+    /// Cursor to resume the job from when interrupted, `None` once done
+    pub after: Option<String>,
+}
+
+/// Brings the whole vault of a tenant to the latest keys: wraps again the
+/// data keys with the latest master key and the subject keys with the latest
+/// data key, then re-encrypts the values batch after batch.
+///
+/// The job may be interrupted at any time: running it again, from the last
+/// reported cursor or from scratch, only processes what is left.
+pub struct RotationJob<K>
+where
+    K: VaultKeys,
+{
+    keys: K,
+    batch_size: i64,
+}
+
+impl<K> RotationJob<K>
+where
+    K: VaultKeys + Sync,
+{
+    pub fn new(keys: K, batch_size: i64) -> RotationJob<K> {
+        RotationJob { keys, batch_size }
+    }
+
+    /// Runs the job from the `after` cursor (from the start when `None`);
+    /// `report` is called after each batch.
+    pub async fn run<F>(
+        &self,
+        context: &ExecutionContext,
+        after: Option<String>,
+        mut report: F,
+    ) -> Result<RotationProgress, Error>
+    where
+        F: FnMut(&RotationProgress) + Send,
+    {
+        context.ensure_permission(&Permission::KeyRotate)?;
+        self.keys.rewrap_data_keys(context).await?;
+        self.rewrap_subject_keys(context).await?;
+
+        let mut progress = RotationProgress {
+            batches: 0,
+            reencrypted: 0,
+            after,
+        };
+        loop {
+            let page = self
+                .keys
+                .reencrypt_tokens(
+                    context,
+                    util::Paging::new(self.batch_size, progress.after.clone()),
+                )
+                .await?;
+            progress.batches += 1;
+            progress.reencrypted += page.items.len();
+            progress.after = page.page_infos.after();
+            tracing::info!(
+                batches = progress.batches,
+                reencrypted = progress.reencrypted,
+                "Vault values re-encrypted"
+            );
+            report(&progress);
+            if !page.page_infos.has_next_page() {
+                return Ok(progress);
+            }
+        }
+    }
+
+    /// Always from the start: the subject keys already rewrapped are skipped anyway
+    async fn rewrap_subject_keys(&self, context: &ExecutionContext) -> Result<(), Error> {
+        let mut rewrapped = 0;
+        let mut after = None;
+        loop {
+            let page = self
+                .keys
+                .rewrap_subject_keys(context, util::Paging::new(self.batch_size, after))
+                .await?;
+            rewrapped += page.items.len();
+            after = page.page_infos.after();
+            tracing::info!(rewrapped, "Subject keys rewrapped");
+            if !page.page_infos.has_next_page() {
+                return Ok(());
+            }
+        }
+    }
+}
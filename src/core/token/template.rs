@@ -0,0 +1,358 @@
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Utc};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::core::token::token::max_value;
+use crate::core::token::TokenError;
+use crate::core::util::LengthUnit;
+
+/// Pattern of a sequence token, e.g. `CUST-{date:%Y}-{seq:8:0}`.
+///
+/// Placeholders:
+/// * `{seq}`, `{seq:WIDTH}`, `{seq:WIDTH:PAD}` or `{seq:WIDTH:PAD:RADIX}`: the sequence,
+///   left padded up to `WIDTH` with `PAD` (default `0`), written in `RADIX` (2 to 36, default 10)
+/// * `{date:FORMAT}`: the current (UTC) date, `FORMAT` being a strftime pattern
+/// * `{policy}`: the policy code
+/// * `{value:START:END}`: characters `START` (included) to `END` (excluded) of the value;
+///   negative indexes count from the end, and empty ones default to the bounds of the value
+///
+/// Anything else is literal text; `{{` and `}}` stand for `{` and `}`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Template {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Seq { width: usize, pad: char, radix: u32 },
+    Date(String),
+    Policy,
+    Value(Option<isize>, Option<isize>),
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Template, TokenError> {
+        let invalid = |reason: String| TokenError::InvalidTemplate(format!("{source}: {reason}"));
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = source.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| invalid("unclosed placeholder".to_string()))?;
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::parse(&rest[..end]).map_err(invalid)?);
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => return Err(invalid("unexpected '}'".to_string())),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        if !segments.iter().any(|s| matches!(s, Segment::Seq { .. })) {
+            return Err(invalid("a {seq} placeholder is required".to_string()));
+        }
+        Ok(Template {
+            source: source.to_string(),
+            segments,
+        })
+    }
+
+    /// Renders the template, the value ranges being counted in `unit`
+    pub fn render(
+        &self,
+        seq: i64,
+        policy: &str,
+        value: &str,
+        unit: LengthUnit,
+        now: DateTime<Utc>,
+    ) -> String {
+        let mut rendered = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) => rendered.push_str(text),
+                Segment::Seq { width, pad, radix } => {
+                    let digits = to_radix(seq, *radix);
+                    let len = digits.chars().count();
+                    rendered.extend(std::iter::repeat_n(*pad, width.saturating_sub(len)));
+                    rendered.push_str(&digits);
+                }
+                Segment::Date(format) => rendered.push_str(&now.format(format).to_string()),
+                Segment::Policy => rendered.push_str(policy),
+                Segment::Value(start, end) => {
+                    let len = unit.len(value);
+                    let start = bound(*start, 0, len);
+                    let end = bound(*end, len, len);
+                    rendered.push_str(unit.slice(value, start, end));
+                }
+            }
+        }
+        rendered
+    }
+
+    /// Highest sequence value fitting in the width of every padded `{seq}`,
+    /// `None` when none is padded
+    pub fn capacity(&self) -> Option<i64> {
+        self.segments
+            .iter()
+            .filter_map(|segment| match segment {
+                Segment::Seq { width, radix, .. } if *width > 0 => {
+                    Some(max_value(*radix, *width).unwrap_or(i64::MAX))
+                }
+                _ => None,
+            })
+            .min()
+    }
+}
+
+impl Segment {
+    fn parse(placeholder: &str) -> Result<Segment, String> {
+        let (name, args) = placeholder
+            .split_once(':')
+            .map_or((placeholder, None), |(name, args)| (name, Some(args)));
+        match (name, args) {
+            ("seq", None) => Ok(Segment::Seq {
+                width: 0,
+                pad: '0',
+                radix: 10,
+            }),
+            ("seq", Some(args)) => {
+                let args: Vec<&str> = args.split(':').collect();
+                if args.len() > 3 {
+                    return Err(format!("too many arguments for {{{placeholder}}}"));
+                }
+                let width = args[0]
+                    .parse::<usize>()
+                    .map_err(|_| format!("invalid width '{}'", args[0]))?;
+                let pad = match args.get(1) {
+                    None => '0',
+                    Some(pad) => {
+                        let mut chars = pad.chars();
+                        match (chars.next(), chars.next()) {
+                            (Some(c), None) => c,
+                            _ => return Err(format!("invalid padding '{pad}'")),
+                        }
+                    }
+                };
+                let radix = match args.get(2) {
+                    None => 10,
+                    Some(radix) => radix
+                        .parse::<u32>()
+                        .ok()
+                        .filter(|r| (2..=36).contains(r))
+                        .ok_or_else(|| format!("invalid radix '{radix}'"))?,
+                };
+                Ok(Segment::Seq { width, pad, radix })
+            }
+            ("date", Some(format)) if !format.is_empty() => {
+                if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+                    return Err(format!("invalid date format '{format}'"));
+                }
+                Ok(Segment::Date(format.to_string()))
+            }
+            ("policy", None) => Ok(Segment::Policy),
+            ("value", Some(range)) => {
+                let (start, end) = range.split_once(':').ok_or_else(|| {
+                    format!("expected {{value:START:END}}, got {{{placeholder}}}")
+                })?;
+                let index = |index: &str| {
+                    if index.is_empty() {
+                        Ok(None)
+                    } else {
+                        index
+                            .parse::<isize>()
+                            .map(Some)
+                            .map_err(|_| format!("invalid index '{index}'"))
+                    }
+                };
+                Ok(Segment::Value(index(start)?, index(end)?))
+            }
+            _ => Err(format!("unknown placeholder {{{placeholder}}}")),
+        }
+    }
+}
+
+/// Resolves a possibly negative (i.e. from the end) index into `0..=len`
+fn bound(index: Option<isize>, default: usize, len: usize) -> usize {
+    match index {
+        None => default,
+        Some(i) if i < 0 => len.saturating_sub(i.unsigned_abs()),
+        Some(i) => (i as usize).min(len),
+    }
+}
+
+fn to_radix(value: i64, radix: u32) -> String {
+    let mut n = value.unsigned_abs();
+    let mut digits = Vec::new();
+    loop {
+        digits.push(
+            std::char::from_digit((n % radix as u64) as u32, radix)
+                .expect("digit is lower than radix")
+                .to_ascii_uppercase(),
+        );
+        n /= radix as u64;
+        if n == 0 {
+            break;
+        }
+    }
+    if value < 0 {
+        digits.push('-');
+    }
+    digits.iter().rev().collect()
+}
+
+impl FromStr for Template {
+    type Err = TokenError;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        Template::parse(source)
+    }
+}
+
+impl TryFrom<String> for Template {
+    type Error = TokenError;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        Template::parse(&source)
+    }
+}
+
+impl From<Template> for String {
+    fn from(template: Template) -> Self {
+        template.source
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn render(template: &str, seq: i64, value: &str) -> String {
+        let now = Utc.with_ymd_and_hms(2023, 5, 17, 10, 30, 0).unwrap();
+        Template::parse(template)
+            .unwrap()
+            .render(seq, "customers", value, LengthUnit::Chars, now)
+    }
+
+    #[test]
+    fn render_nominal_case() {
+        assert_eq!(
+            render("CUST-{date:%Y}-{seq:8:0}", 37, "CARMEN"),
+            "CUST-2023-00000037"
+        );
+    }
+
+    #[test]
+    fn render_sequence_variants() {
+        assert_eq!(render("{seq}", 37, ""), "37");
+        assert_eq!(render("{seq:4}", 37, ""), "0037");
+        assert_eq!(render("{seq:4:_}", 37, ""), "__37");
+        assert_eq!(render("{seq:4:0:16}", 255, ""), "00FF");
+        assert_eq!(render("{seq:0:0:36}", 35, ""), "Z");
+        assert_eq!(render("{seq:2}", 12345, ""), "12345");
+    }
+
+    #[test]
+    fn render_policy_value_and_literals() {
+        assert_eq!(
+            render(
+                "{policy}/{value:0:2}{value:-3:}-{seq}",
+                1,
+                "CARMEN MCCALLUM"
+            ),
+            "customers/CALUM-1"
+        );
+        assert_eq!(render("{value:1:3}{seq}", 1, "Zoé"), "oé1");
+        assert_eq!(render("{value:2:10}{seq}", 1, "ZO"), "1");
+        assert_eq!(render("{value:-10:1}{seq}", 1, "ZO"), "Z1");
+        assert_eq!(render("{{{seq}}}", 1, ""), "{1}");
+    }
+
+    #[test]
+    fn render_value_in_graphemes() {
+        let template = Template::parse("{value:0:3}{seq}").unwrap();
+        let now = Utc.with_ymd_and_hms(2023, 5, 17, 10, 30, 0).unwrap();
+        // "Zoé" with a combining acute accent
+        let value = "Zoe\u{301}la";
+        assert_eq!(
+            template.render(1, "customers", value, LengthUnit::Chars, now),
+            "Zoe1"
+        );
+        assert_eq!(
+            template.render(1, "customers", value, LengthUnit::Graphemes, now),
+            "Zoe\u{301}1"
+        );
+    }
+
+    #[test]
+    fn capacity_is_bound_by_padded_sequences() {
+        let capacity = |template: &str| Template::parse(template).unwrap().capacity();
+        assert_eq!(capacity("CUST-{seq}"), None);
+        assert_eq!(capacity("CUST-{seq:4}"), Some(9999));
+        assert_eq!(capacity("{seq:2:0:16}-{seq:3}"), Some(255));
+        assert_eq!(capacity("{seq:30}"), Some(i64::MAX));
+    }
+
+    #[test]
+    fn parse_rejects_invalid_templates() {
+        for template in [
+            "CUST-",
+            "{date:%Y}",
+            "{seq",
+            "seq}",
+            "{seq:x}",
+            "{seq:8:00}",
+            "{seq:8:0:37}",
+            "{seq:8:0:10:1}",
+            "{seq}{date:}",
+            "{seq}{date:%Q}",
+            "{seq}{value}",
+            "{seq}{value:a:}",
+            "{seq}{unknown}",
+            "{seq}{policy:x}",
+        ] {
+            assert!(
+                matches!(
+                    Template::parse(template),
+                    Err(TokenError::InvalidTemplate(_))
+                ),
+                "{template} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        let template = Template::parse("CUST-{date:%Y}-{seq:8:0}").unwrap();
+        let json = serde_json::to_string(&template).unwrap();
+        assert_eq!(json, "\"CUST-{date:%Y}-{seq:8:0}\"");
+        assert_eq!(serde_json::from_str::<Template>(&json).unwrap(), template);
+        assert!(serde_json::from_str::<Template>("\"CUST-\"").is_err());
+    }
+}
@@ -1,5 +1,12 @@
+use crate::core::context::ExecutionContext;
+use crate::core::crypto::FpeAlgorithm;
+use crate::core::token::{DateShiftFormat, EmailFormat, MaskFormat, Template, TokenVault};
 use crate::core::util;
-use secrecy::Secret;
+use crate::core::util::LengthUnit;
+use crate::error::Error;
+use async_trait::async_trait;
+use chrono::Utc;
+use secrecy::{ExposeSecret, Secret};
 use std::ops::Deref;
 use thiserror::Error;
 
@@ -7,6 +14,12 @@ use thiserror::Error;
 pub enum TokenError {
     #[error("Token Generation Failed {0}")]
     GenerationFailure(String),
+    #[error("Value cannot be tokenized: {0}")]
+    InvalidValue(String),
+    #[error("Invalid template {0}")]
+    InvalidTemplate(String),
+    #[error("Sequence exhausted: {0}")]
+    SequenceExhausted(String),
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -25,34 +38,198 @@ impl From<String> for Token {
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum SequenceFormat {
     Raw,
     PaddedInt(usize, char),
+    Template(Template),
+    /// Zero padded to the given length, the sequence going through a keyed
+    /// permutation of `0..10^len` so that tokens do not look sequential
+    Permuted(usize),
 }
 
 impl SequenceFormat {
-    pub fn apply(&self, seq: i64) -> String {
+    /// Formats `seq`, which has already gone through the permutation for `Permuted`
+    pub fn apply(&self, seq: i64, policy: &Policy, value: &Secret<String>) -> String {
         match self {
             SequenceFormat::Raw => seq.to_string(),
             SequenceFormat::PaddedInt(len, chr) => util::left_pad(seq, *len, *chr),
+            SequenceFormat::Permuted(len) => util::left_pad(seq, *len, '0'),
+            SequenceFormat::Template(template) => template.render(
+                seq,
+                &policy.code,
+                value.expose_secret(),
+                policy.length_unit,
+                Utc::now(),
+            ),
         }
     }
+
+    /// Highest sequence value that fits in the format, `None` when it is unbounded
+    pub fn capacity(&self) -> Option<i64> {
+        match self {
+            SequenceFormat::Raw | SequenceFormat::PaddedInt(0, _) => None,
+            SequenceFormat::PaddedInt(len, _) => max_value(10, *len),
+            // the permutation domain is 0..10^len
+            SequenceFormat::Permuted(len) => max_value(10, *len).or(Some(i64::MAX)),
+            SequenceFormat::Template(template) => template.capacity(),
+        }
+    }
+}
+
+/// Highest value written with `len` digits in `radix`, `None` when it does not fit in an i64
+pub(crate) fn max_value(radix: u32, len: usize) -> Option<i64> {
+    (radix as i64)
+        .checked_pow(u32::try_from(len).ok()?)
+        .map(|max| max - 1)
+}
+
+/// Usage of the sequence of a policy
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SequenceCapacity {
+    /// Highest value the format can render
+    pub capacity: i64,
+    /// Values already handed out (or reserved)
+    pub used: i64,
+}
+
+impl SequenceCapacity {
+    pub fn remaining(&self) -> i64 {
+        (self.capacity - self.used).max(0)
+    }
+
+    /// Ratio of the capacity already used
+    pub fn usage(&self) -> f64 {
+        self.used as f64 / self.capacity.max(1) as f64
+    }
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TokenFormat {
     /// UUID
     Uuid,
 
-    /// Sequence based token; pattern is provided for formatting
-    /// {1} corresponds to the current sequence
-    /// {2} corresponds to the original value
+    /// Sequence based token
     ///
     /// e.g.
     /// * (37, Raw) will produce "37"
     /// * (37, PaddedInt(4,"0")) will produce "0037"
+    /// * (37, Template("CUST-{date:%Y}-{seq:8:0}")) will produce "CUST-2023-00000037"
+    /// * (37, Permuted(6)) will produce e.g. "481922"
     Sequence(SequenceFormat),
+
+    /// Format preserving encryption of the value: the token has the same length
+    /// and alphabet, and is decrypted back without any vault lookup
+    Fpe(FpeFormat),
+
+    /// Card number: the digits that are not kept are replaced by random ones,
+    /// the token failing (or passing) the Luhn check
+    Pan(LuhnCheck),
+
+    /// Random characters drawn from a CSPRNG
+    Random(RandomFormat),
+
+    /// Each character replaced by a random one of the same class (uppercase,
+    /// lowercase, digit), punctuation and whitespace being kept in place:
+    /// e.g. "AB12 3CD" may give "QX70 8KE"
+    ClassPreserving,
+
+    /// Email address whose local part is tokenized, e.g. "4711@example.com"
+    Email(EmailFormat),
+
+    /// Date shifted by a keyed offset, reverted without any vault lookup
+    DateShift(DateShiftFormat),
+
+    /// Stable pseudonym (truncated keyed hash of the value): nothing is stored,
+    /// and the value cannot be recovered
+    Pseudonym(PseudonymFormat),
+
+    /// Irreversible masked value meant for display, e.g. `****-****-****-1234`:
+    /// nothing is stored, see `Tokenizer::mask`
+    Mask(MaskFormat),
 }
 
+impl TokenFormat {
+    /// The sequence the tokens are drawn from, if any
+    pub fn sequence(&self) -> Option<&SequenceFormat> {
+        match self {
+            TokenFormat::Sequence(format) => Some(format),
+            TokenFormat::Email(email) => email.local.sequence(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Charset {
+    /// 0-9
+    Digits,
+    /// 0-9, A-Z and a-z
+    Alphanumeric,
+    /// 0-9 and A-Z without I, L, O and U
+    CrockfordBase32,
+    /// any set of distinct characters
+    Custom(String),
+}
+
+impl Charset {
+    pub fn chars(&self) -> Vec<char> {
+        match self {
+            Charset::Digits => "0123456789".chars().collect(),
+            Charset::Alphanumeric => ('0'..='9').chain('A'..='Z').chain('a'..='z').collect(),
+            Charset::CrockfordBase32 => "0123456789ABCDEFGHJKMNPQRSTVWXYZ".chars().collect(),
+            Charset::Custom(chars) => chars.chars().collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PseudonymFormat {
+    pub length: usize,
+    pub charset: Charset,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RandomFormat {
+    pub length: usize,
+    pub charset: Charset,
+}
+
+impl RandomFormat {
+    /// Entropy of the generated tokens, in bits
+    pub fn entropy(&self) -> f64 {
+        self.length as f64 * (self.charset.chars().len() as f64).log2()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LuhnCheck {
+    /// tokens cannot be confused with real card numbers
+    Fail,
+    /// tokens look like valid card numbers
+    Pass,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FpeFormat {
+    pub algorithm: FpeAlgorithm,
+
+    /// characters allowed in the encrypted segment; its size is the radix
+    pub alphabet: String,
+}
+
+impl FpeFormat {
+    pub const DIGITS: &'static str = "0123456789";
+
+    pub fn numeric(algorithm: FpeAlgorithm) -> FpeFormat {
+        FpeFormat {
+            algorithm,
+            alphabet: FpeFormat::DIGITS.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Policy {
     /// Unique identifier of the policy
     pub code: String,
@@ -68,12 +245,208 @@ pub struct Policy {
 
     /// number of characters to retain from the right
     pub keep_right: usize,
+
+    /// when set, tokenizing the same value twice yields the same token
+    pub deterministic: bool,
+
+    /// unit in which keep_left, keep_right and the value ranges of the templates are counted
+    pub length_unit: LengthUnit,
 }
 
+impl Policy {
+    /// Policy keeping nothing of the value, without prefix, not deterministic
+    pub fn new(code: &str, format: TokenFormat) -> Policy {
+        Policy {
+            code: code.to_string(),
+            format,
+            prefix: None,
+            keep_left: 0,
+            keep_right: 0,
+            deterministic: false,
+            length_unit: LengthUnit::Chars,
+        }
+    }
+
+    /// Splits the value into the kept left part, the part to protect and the kept right part
+    pub fn split<'a>(&self, value: &'a str) -> (&'a str, &'a str, &'a str) {
+        let unit = self.length_unit;
+        let len = unit.len(value);
+        let left = self.keep_left.min(len);
+        let right = len - self.keep_right.min(len - left);
+        let (start, end) = (unit.offset(value, left), unit.offset(value, right));
+        (&value[..start], &value[start..end], &value[end..])
+    }
+}
+
+#[async_trait]
 pub trait TokenGenerator {
-    fn generate(&self, policy: &Policy, value: Secret<String>) -> Result<Token, TokenError>;
+    async fn generate(
+        &self,
+        context: &ExecutionContext,
+        policy: &Policy,
+        value: Secret<String>,
+    ) -> Result<Token, Error>;
+
+    /// Generates a token and binds `value` to it in the `vault`, generating
+    /// another one as long as it collides with a token already stored.
+    ///
+    /// Fails with `TokenError::GenerationFailure` once every attempt collided.
+    async fn generate_stored<V>(
+        &self,
+        context: &ExecutionContext,
+        policy: &Policy,
+        subject: Option<&str>,
+        value: Secret<String>,
+        vault: &V,
+    ) -> Result<Token, Error>
+    where
+        V: TokenVault + Sync;
 }
 
+#[async_trait]
 pub trait RawTokenGenerator {
-    fn generate(&self, token_format: &TokenFormat) -> Result<String, TokenError>;
+    async fn generate(
+        &self,
+        context: &ExecutionContext,
+        policy: &Policy,
+        value: &Secret<String>,
+    ) -> Result<String, Error>;
+}
+
+/// Counters backing the sequence tokens, one per (tenant, policy)
+#[async_trait]
+pub trait SequenceSource {
+    /// Reserves `count` consecutive values and returns the first one;
+    /// requires `Permission::TokenCreate`.
+    async fn reserve(
+        &self,
+        context: &ExecutionContext,
+        policy: &Policy,
+        count: i64,
+    ) -> Result<i64, Error>;
+
+    /// The value the next reservation will start at;
+    /// requires `Permission::PolicyRead`.
+    async fn next_value(&self, context: &ExecutionContext, policy: &Policy) -> Result<i64, Error>;
+
+    /// Capacity of the sequence of the policy, `None` when its format is
+    /// unbounded (or not a sequence)
+    async fn capacity(
+        &self,
+        context: &ExecutionContext,
+        policy: &Policy,
+    ) -> Result<Option<SequenceCapacity>, Error>
+    where
+        Self: Sync,
+    {
+        let capacity = policy.format.sequence().and_then(SequenceFormat::capacity);
+        match capacity {
+            None => Ok(None),
+            Some(capacity) => {
+                let next = self.next_value(context, policy).await?;
+                Ok(Some(SequenceCapacity {
+                    capacity,
+                    used: next - 1,
+                }))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_policy(keep_left: usize, keep_right: usize, length_unit: LengthUnit) -> Policy {
+        Policy {
+            keep_left,
+            keep_right,
+            length_unit,
+            ..Policy::new("names", TokenFormat::Uuid)
+        }
+    }
+
+    #[test]
+    fn split_keeps_left_and_right() {
+        let policy = sample_policy(6, 4, LengthUnit::Chars);
+        assert_eq!(
+            policy.split("4111111111111111"),
+            ("411111", "111111", "1111")
+        );
+        assert_eq!(
+            sample_policy(0, 0, LengthUnit::Chars).split("4111"),
+            ("", "4111", "")
+        );
+    }
+
+    #[test]
+    fn split_when_keep_right_overlap_keep_left() {
+        assert_eq!(
+            sample_policy(4, 4, LengthUnit::Chars).split("CARMEN"),
+            ("CARM", "", "EN")
+        );
+        assert_eq!(
+            sample_policy(8, 0, LengthUnit::Chars).split("CARMEN"),
+            ("CARMEN", "", "")
+        );
+    }
+
+    #[test]
+    fn split_counts_chars_or_graphemes_never_bytes() {
+        assert_eq!(
+            sample_policy(1, 1, LengthUnit::Chars).split("éàèù"),
+            ("é", "àè", "ù")
+        );
+        assert_eq!(
+            sample_policy(1, 1, LengthUnit::Chars).split("東京都庁"),
+            ("東", "京都", "庁")
+        );
+        // "Zoé" with a combining acute accent
+        assert_eq!(
+            sample_policy(0, 1, LengthUnit::Chars).split("Zoe\u{301}"),
+            ("", "Zoe", "\u{301}")
+        );
+        assert_eq!(
+            sample_policy(0, 1, LengthUnit::Graphemes).split("Zoe\u{301}"),
+            ("", "Zo", "e\u{301}")
+        );
+    }
+
+    #[test]
+    fn sequence_capacity() {
+        assert_eq!(SequenceFormat::Raw.capacity(), None);
+        assert_eq!(SequenceFormat::PaddedInt(0, '0').capacity(), None);
+        assert_eq!(SequenceFormat::PaddedInt(4, '0').capacity(), Some(9999));
+        assert_eq!(SequenceFormat::PaddedInt(19, '0').capacity(), None);
+        assert_eq!(SequenceFormat::Permuted(6).capacity(), Some(999_999));
+
+        let capacity = SequenceCapacity {
+            capacity: 9999,
+            used: 8999,
+        };
+        assert_eq!(capacity.remaining(), 1000);
+        assert!((capacity.usage() - 0.9).abs() < 0.001);
+    }
+
+    #[test]
+    fn charset_sizes() {
+        assert_eq!(Charset::Digits.chars().len(), 10);
+        assert_eq!(Charset::Alphanumeric.chars().len(), 62);
+        assert_eq!(Charset::CrockfordBase32.chars().len(), 32);
+        assert_eq!(Charset::Custom("ab".to_string()).chars().len(), 2);
+    }
+
+    #[test]
+    fn random_format_entropy() {
+        let format = RandomFormat {
+            length: 10,
+            charset: Charset::CrockfordBase32,
+        };
+        assert_eq!(format.entropy(), 50.0);
+        let format = RandomFormat {
+            length: 6,
+            charset: Charset::Digits,
+        };
+        assert!((format.entropy() - 19.93).abs() < 0.01);
+    }
 }
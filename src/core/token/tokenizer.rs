@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::core::context::{ExecutionContext, Permission};
+use crate::core::crypto::{CryptoError, FpeCipher, KeyedOffset, Pseudonymizer};
+use crate::core::token::{
+    DateShiftFormat, FpeFormat, Policy, PseudonymFormat, Token, TokenFormat, TokenGenerator,
+    TokenVault,
+};
+use crate::error::{Error, ErrorCode};
+use secrecy::{ExposeSecret, Secret};
+
+/// Generates tokens according to the policy and keeps track of them in the vault.
+///
+/// FPE and date shift policies are vaultless: the token is the encrypted
+/// (or shifted) value itself.
+/// Mask policies only mask values, and pseudonyms are never stored either:
+/// neither can be reverted.
+pub struct Tokenizer<G, V>
+where
+    G: TokenGenerator,
+    V: TokenVault,
+{
+    generator: G,
+    vault: V,
+    fpe: Option<Arc<FpeCipher>>,
+    date_shift: Option<Arc<KeyedOffset>>,
+    pseudonymizer: Option<Arc<Pseudonymizer>>,
+}
+
+impl<G, V> Tokenizer<G, V>
+where
+    G: TokenGenerator + Sync,
+    V: TokenVault + Sync,
+{
+    pub fn new(generator: G, vault: V) -> Tokenizer<G, V> {
+        Tokenizer {
+            generator,
+            vault,
+            fpe: None,
+            date_shift: None,
+            pseudonymizer: None,
+        }
+    }
+
+    pub fn with_fpe(mut self, fpe: Arc<FpeCipher>) -> Tokenizer<G, V> {
+        self.fpe = Some(fpe);
+        self
+    }
+
+    pub fn with_date_shift(mut self, date_shift: Arc<KeyedOffset>) -> Tokenizer<G, V> {
+        self.date_shift = Some(date_shift);
+        self
+    }
+
+    pub fn with_pseudonymizer(mut self, pseudonymizer: Arc<Pseudonymizer>) -> Tokenizer<G, V> {
+        self.pseudonymizer = Some(pseudonymizer);
+        self
+    }
+
+    pub async fn tokenize(
+        &self,
+        context: &ExecutionContext,
+        policy: &Policy,
+        value: Secret<String>,
+    ) -> Result<Token, Error> {
+        self.tokenize_with_subject(context, policy, None, value)
+            .await
+    }
+
+    /// Tokenizes a value of the data `subject`: vault values are encrypted with
+    /// a key of the subject, which can be shredded to erase them all, and per
+    /// subject date shifts use its offset. Vaultless formats ignore the subject.
+    pub async fn tokenize_with_subject(
+        &self,
+        context: &ExecutionContext,
+        policy: &Policy,
+        subject: Option<&str>,
+        value: Secret<String>,
+    ) -> Result<Token, Error> {
+        match &policy.format {
+            TokenFormat::Fpe(format) => return self.fpe_tokenize(context, policy, format, &value),
+            TokenFormat::DateShift(_) => return self.shift_date(context, policy, subject, &value),
+            TokenFormat::Pseudonym(format) => {
+                return self.pseudonym(context, policy, format, &value)
+            }
+            TokenFormat::Mask(_) => return Err(masking_policy(policy)),
+            _ => {}
+        }
+        if policy.deterministic {
+            if let Some(token) = self
+                .vault
+                .find_token(context, policy, subject, &value)
+                .await?
+            {
+                return Ok(token);
+            }
+        }
+        self.generator
+            .generate_stored(context, policy, subject, value, &self.vault)
+            .await
+    }
+
+    pub async fn detokenize(
+        &self,
+        context: &ExecutionContext,
+        policy: &Policy,
+        token: &Token,
+    ) -> Result<Secret<String>, Error> {
+        self.detokenize_with_subject(context, policy, None, token)
+            .await
+    }
+
+    /// Detokenizes a token of the data `subject`: per subject date shifts are
+    /// reverted with its offset. Vault tokens know their subject already.
+    pub async fn detokenize_with_subject(
+        &self,
+        context: &ExecutionContext,
+        policy: &Policy,
+        subject: Option<&str>,
+        token: &Token,
+    ) -> Result<Secret<String>, Error> {
+        match &policy.format {
+            TokenFormat::Fpe(format) => return self.fpe_detokenize(context, policy, format, token),
+            TokenFormat::DateShift(_) => return self.unshift_date(context, policy, subject, token),
+            TokenFormat::Pseudonym(_) => {
+                return Err(Error::Generic(
+                    ErrorCode::Forbidden,
+                    "Pseudonyms cannot be detokenized".to_string(),
+                    HashMap::from([("policy".to_string(), policy.code.clone())]),
+                ))
+            }
+            TokenFormat::Mask(_) => return Err(masking_policy(policy)),
+            _ => {}
+        }
+        self.vault
+            .resolve_token(context, policy, token)
+            .await?
+            .ok_or_else(|| token_not_found(policy))
+    }
+
+    fn pseudonym(
+        &self,
+        context: &ExecutionContext,
+        policy: &Policy,
+        format: &PseudonymFormat,
+        value: &Secret<String>,
+    ) -> Result<Token, Error> {
+        context.ensure_permission(&Permission::TokenCreate)?;
+        let pseudonym = self
+            .pseudonymizer
+            .as_deref()
+            .ok_or_else(|| Error::MissingConfig("pseudonymizer".to_string()))?
+            .pseudonym(
+                &context.tenant_label(),
+                &policy.code,
+                &format.charset.chars(),
+                format.length,
+                value,
+            )?;
+        Ok(Token::from(format!(
+            "{}{}",
+            policy.prefix.as_deref().unwrap_or_default(),
+            pseudonym
+        )))
+    }
+
+    /// Shifts the date according to a `TokenFormat::DateShift` policy; `subject`
+    /// is required when the offset is per subject, and ignored otherwise.
+    pub fn shift_date(
+        &self,
+        context: &ExecutionContext,
+        policy: &Policy,
+        subject: Option<&str>,
+        value: &Secret<String>,
+    ) -> Result<Token, Error> {
+        context.ensure_permission(&Permission::TokenCreate)?;
+        let (format, days) = self.date_offset(context, policy, subject)?;
+        Ok(Token::from(format.shift(value.expose_secret(), days)?))
+    }
+
+    /// Reverts `shift_date`; requires `Permission::TokenRead`.
+    pub fn unshift_date(
+        &self,
+        context: &ExecutionContext,
+        policy: &Policy,
+        subject: Option<&str>,
+        token: &Token,
+    ) -> Result<Secret<String>, Error> {
+        context.ensure_permission(&Permission::TokenRead)?;
+        let (format, days) = self.date_offset(context, policy, subject)?;
+        let value = format
+            .shift(token, -days)
+            .map_err(|_| token_not_found(policy))?;
+        Ok(Secret::new(value))
+    }
+
+    fn date_offset<'a>(
+        &self,
+        context: &ExecutionContext,
+        policy: &'a Policy,
+        subject: Option<&str>,
+    ) -> Result<(&'a DateShiftFormat, i64), Error> {
+        let TokenFormat::DateShift(format) = &policy.format else {
+            return Err(Error::Generic(
+                ErrorCode::BadRequest,
+                "Policy does not shift dates".to_string(),
+                HashMap::from([("policy".to_string(), policy.code.clone())]),
+            ));
+        };
+        let mut scope = vec![policy.code.as_str()];
+        if format.per_subject {
+            scope.push(subject.ok_or_else(|| {
+                Error::Generic(
+                    ErrorCode::BadRequest,
+                    "A subject is required to shift dates".to_string(),
+                    HashMap::from([("policy".to_string(), policy.code.clone())]),
+                )
+            })?);
+        }
+        let days = self
+            .date_shift
+            .as_deref()
+            .ok_or_else(|| Error::MissingConfig("date shift key".to_string()))?
+            .offset(&context.tenant_label(), &scope, format.window_days)?;
+        Ok((format, days))
+    }
+
+    /// Masks the value according to a `TokenFormat::Mask` policy;
+    /// requires `Permission::TokenMask` only, the vault being left untouched.
+    pub fn mask(
+        &self,
+        context: &ExecutionContext,
+        policy: &Policy,
+        value: &Secret<String>,
+    ) -> Result<String, Error> {
+        context.ensure_permission(&Permission::TokenMask)?;
+        match &policy.format {
+            TokenFormat::Mask(format) => Ok(format.mask(policy, value.expose_secret())?),
+            _ => Err(Error::Generic(
+                ErrorCode::BadRequest,
+                "Policy does not mask values".to_string(),
+                HashMap::from([("policy".to_string(), policy.code.clone())]),
+            )),
+        }
+    }
+
+    fn fpe_cipher(&self) -> Result<&FpeCipher, Error> {
+        self.fpe
+            .as_deref()
+            .ok_or_else(|| Error::MissingConfig("fpe cipher".to_string()))
+    }
+
+    fn fpe_tokenize(
+        &self,
+        context: &ExecutionContext,
+        policy: &Policy,
+        format: &FpeFormat,
+        value: &Secret<String>,
+    ) -> Result<Token, Error> {
+        context.ensure_permission(&Permission::TokenCreate)?;
+        let (left, middle, right) = policy.split(value.expose_secret());
+        let encrypted = self.fpe_cipher()?.encrypt(
+            &context.tenant_label(),
+            &policy.code,
+            format.algorithm,
+            &format.alphabet,
+            middle,
+        )?;
+        Ok(Token::from(format!(
+            "{}{}{}{}",
+            policy.prefix.as_deref().unwrap_or_default(),
+            left,
+            encrypted,
+            right
+        )))
+    }
+
+    fn fpe_detokenize(
+        &self,
+        context: &ExecutionContext,
+        policy: &Policy,
+        format: &FpeFormat,
+        token: &Token,
+    ) -> Result<Secret<String>, Error> {
+        context.ensure_permission(&Permission::TokenRead)?;
+        let body = token
+            .strip_prefix(policy.prefix.as_deref().unwrap_or_default())
+            .ok_or_else(|| token_not_found(policy))?;
+        // the encrypted segment has the length of the original one
+        let (left, middle, right) = policy.split(body);
+        let decrypted = self
+            .fpe_cipher()?
+            .decrypt(
+                &context.tenant_label(),
+                &policy.code,
+                format.algorithm,
+                &format.alphabet,
+                middle,
+            )
+            .map_err(|e| match e {
+                CryptoError::InvalidInput(_) => token_not_found(policy),
+                e => e.into(),
+            })?;
+        Ok(Secret::new(format!("{}{}{}", left, decrypted, right)))
+    }
+}
+
+fn token_not_found(policy: &Policy) -> Error {
+    Error::Generic(
+        ErrorCode::NotFound,
+        "Token not found".to_string(),
+        HashMap::from([("policy".to_string(), policy.code.clone())]),
+    )
+}
+
+fn masking_policy(policy: &Policy) -> Error {
+    Error::Generic(
+        ErrorCode::BadRequest,
+        "Policy only masks values".to_string(),
+        HashMap::from([("policy".to_string(), policy.code.clone())]),
+    )
+}
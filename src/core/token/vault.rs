@@ -0,0 +1,49 @@
+use crate::core::context::ExecutionContext;
+use crate::core::token::{Policy, Token};
+use async_trait::async_trait;
+use secrecy::Secret;
+
+use crate::error::Error;
+
+/// Keeps the mapping between the tokens and their (encrypted) original value.
+#[async_trait]
+pub trait TokenVault {
+    /// Stores the `value` the `token` stands for; requires `Permission::TokenCreate`.
+    ///
+    /// Returns the token the value is bound to: for deterministic policies, it is the
+    /// one stored first when the same value is tokenized concurrently.
+    ///
+    /// The value of a `subject` is encrypted with a data key of its own, which
+    /// can be shredded to erase it; `None` uses the data key of the tenant.
+    ///
+    /// Fails with `ErrorCode::UniqueViolation` when the token is already bound to another value,
+    /// and with `ErrorCode::Erased` when the key of the subject (or tenant) has been shredded.
+    async fn store_token(
+        &self,
+        context: &ExecutionContext,
+        policy: &Policy,
+        subject: Option<&str>,
+        token: &Token,
+        value: &Secret<String>,
+    ) -> Result<Token, Error>;
+
+    /// Looks up the token already bound to `value`; only relevant for deterministic
+    /// policies. Requires `Permission::TokenCreate`.
+    async fn find_token(
+        &self,
+        context: &ExecutionContext,
+        policy: &Policy,
+        subject: Option<&str>,
+        value: &Secret<String>,
+    ) -> Result<Option<Token>, Error>;
+
+    /// Resolves a token back into its original value; requires `Permission::TokenRead`.
+    ///
+    /// Fails with `ErrorCode::Erased` when the key of the value has been shredded.
+    async fn resolve_token(
+        &self,
+        context: &ExecutionContext,
+        policy: &Policy,
+        token: &Token,
+    ) -> Result<Option<Secret<String>>, Error>;
+}
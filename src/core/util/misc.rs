@@ -12,6 +12,24 @@ where
     format!("{}{}", pad_str, value_str)
 }
 
+/// Luhn (mod 10) checksum, as used by card numbers; `false` if any char is not a digit
+pub fn is_luhn_valid(digits: &str) -> bool {
+    let mut sum = 0;
+    for (i, c) in digits.chars().rev().enumerate() {
+        let Some(mut d) = c.to_digit(10) else {
+            return false;
+        };
+        if i % 2 == 1 {
+            d *= 2;
+            if d > 9 {
+                d -= 9;
+            }
+        }
+        sum += d;
+    }
+    !digits.is_empty() && sum % 10 == 0
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -30,4 +48,14 @@ mod tests {
     fn left_pad_value_already_too_long() {
         assert_eq!(left_pad("HOGWARD", 4, '_'), "HOGWARD".to_string());
     }
+
+    #[test]
+    fn is_luhn_valid_samples() {
+        assert!(is_luhn_valid("4111111111111111"));
+        assert!(is_luhn_valid("79927398713"));
+        assert!(!is_luhn_valid("4111111111111112"));
+        assert!(!is_luhn_valid("79927398710"));
+        assert!(!is_luhn_valid("4111-1111"));
+        assert!(!is_luhn_valid(""));
+    }
 }
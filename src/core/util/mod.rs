@@ -1,5 +1,7 @@
 mod misc;
 pub mod paging;
+mod text;
 
 pub use misc::*;
 pub use paging::{Page, PageInfos, Paging};
+pub use text::LengthUnit;
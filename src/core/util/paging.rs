@@ -1,6 +1,7 @@
 use base64::{engine::general_purpose, Engine as _};
 use std::ops::Deref;
 
+#[derive(Serialize)]
 pub struct PageInfos {
     pub after: Option<String>,
     has_next_page: bool,
@@ -30,6 +31,7 @@ impl PageInfos {
     }
 }
 
+#[derive(Serialize)]
 pub struct Page<T> {
     pub items: Vec<T>,
     pub page_infos: PageInfos,
@@ -63,9 +65,9 @@ impl From<Paging> for IntCursor {
                 .decode(after)
                 .map(|v| String::from_utf8(v).unwrap_or("".to_string()))
                 .unwrap_or("".to_string());
-            let raw: Vec<&str> = decoded.split(";").collect();
+            let raw: Vec<&str> = decoded.split(';').collect();
             let cursor = raw
-                .get(0)
+                .first()
                 .map(|s| s.to_string().parse::<i64>().unwrap_or(0))
                 .unwrap_or(0);
             IntCursor(cursor)
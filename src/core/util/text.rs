@@ -0,0 +1,114 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Unit in which the lengths of a value are counted
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LengthUnit {
+    /// unicode scalar values (`char`)
+    #[default]
+    Chars,
+    /// extended grapheme clusters, i.e. what a reader sees as a single character
+    /// (e.g. `e` followed by a combining acute accent)
+    Graphemes,
+}
+
+impl LengthUnit {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LengthUnit::Chars => "chars",
+            LengthUnit::Graphemes => "graphemes",
+        }
+    }
+
+    /// Length of `value` in this unit
+    pub fn len(&self, value: &str) -> usize {
+        match self {
+            LengthUnit::Chars => value.chars().count(),
+            LengthUnit::Graphemes => value.graphemes(true).count(),
+        }
+    }
+
+    /// Byte offset of the `n`th unit of `value`, its byte length when out of bounds
+    pub fn offset(&self, value: &str, n: usize) -> usize {
+        let offset = match self {
+            LengthUnit::Chars => value.char_indices().nth(n).map(|(i, _)| i),
+            LengthUnit::Graphemes => value.grapheme_indices(true).nth(n).map(|(i, _)| i),
+        };
+        offset.unwrap_or(value.len())
+    }
+
+    /// The units of `value`
+    pub fn units<'a>(&self, value: &'a str) -> Vec<&'a str> {
+        match self {
+            LengthUnit::Chars => value
+                .char_indices()
+                .map(|(i, c)| &value[i..i + c.len_utf8()])
+                .collect(),
+            LengthUnit::Graphemes => value.graphemes(true).collect(),
+        }
+    }
+
+    /// Units `start` (included) to `end` (excluded) of `value`, both clamped to its length
+    pub fn slice<'a>(&self, value: &'a str, start: usize, end: usize) -> &'a str {
+        let start = self.offset(value, start);
+        let end = self.offset(value, end).max(start);
+        &value[start..end]
+    }
+}
+
+impl TryFrom<&str> for LengthUnit {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "chars" => Ok(LengthUnit::Chars),
+            "graphemes" => Ok(LengthUnit::Graphemes),
+            other => Err(format!("unknown length unit {other}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "Zoé" with a combining acute accent: 4 chars, 3 graphemes
+    const COMBINING: &str = "Zoe\u{301}";
+
+    #[test]
+    fn len_per_unit() {
+        assert_eq!(LengthUnit::Chars.len("Zoé"), 3);
+        assert_eq!(LengthUnit::Chars.len(COMBINING), 4);
+        assert_eq!(LengthUnit::Graphemes.len(COMBINING), 3);
+        assert_eq!(LengthUnit::Graphemes.len("東京都"), 3);
+    }
+
+    #[test]
+    fn slice_never_cuts_a_unit() {
+        assert_eq!(LengthUnit::Chars.slice("東京都", 1, 2), "京");
+        assert_eq!(LengthUnit::Chars.slice(COMBINING, 0, 3), "Zoe");
+        assert_eq!(LengthUnit::Graphemes.slice(COMBINING, 2, 3), "e\u{301}");
+        assert_eq!(LengthUnit::Graphemes.slice(COMBINING, 2, 10), "e\u{301}");
+        assert_eq!(LengthUnit::Graphemes.slice(COMBINING, 3, 1), "");
+    }
+
+    #[test]
+    fn units_per_unit() {
+        assert_eq!(
+            LengthUnit::Chars.units(COMBINING),
+            ["Z", "o", "e", "\u{301}"]
+        );
+        assert_eq!(
+            LengthUnit::Graphemes.units(COMBINING),
+            ["Z", "o", "e\u{301}"]
+        );
+    }
+
+    #[test]
+    fn string_round_trip() {
+        for unit in [LengthUnit::Chars, LengthUnit::Graphemes] {
+            assert_eq!(LengthUnit::try_from(unit.as_str()), Ok(unit));
+        }
+        assert!(LengthUnit::try_from("bytes").is_err());
+    }
+}
@@ -1,9 +1,11 @@
 //! Module for the error management
+use crate::core::crypto::CryptoError;
+use crate::core::token::TokenError;
 use std::collections::HashMap;
 use std::fmt;
 use thiserror::Error;
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum ErrorCode {
     /// Generic error
     ServerError,
@@ -21,6 +23,12 @@ pub enum ErrorCode {
     ReferenceViolation,
     /// when the actual data state does not permit the attempted action
     Forbidden,
+    /// when the entity has been modified since it was read
+    ConcurrentModification,
+    /// when the data has been erased by destroying its key
+    Erased,
+    /// when a sequence has handed out all the values of its format
+    SequenceExhausted,
 }
 
 impl fmt::Display for ErrorCode {
@@ -29,6 +37,25 @@ impl fmt::Display for ErrorCode {
     }
 }
 
+impl ErrorCode {
+    /// Stable, machine-readable representation of the code
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::ServerError => "SERVER_ERROR",
+            ErrorCode::UniqueViolation => "UNIQUE_VIOLATION",
+            ErrorCode::NotFound => "NOT_FOUND",
+            ErrorCode::InvalidReference => "INVALID_REFERENCE",
+            ErrorCode::BadRequest => "BAD_REQUEST",
+            ErrorCode::Unauthorized => "UNAUTHORIZED",
+            ErrorCode::ReferenceViolation => "REFERENCE_VIOLATION",
+            ErrorCode::Forbidden => "FORBIDDEN",
+            ErrorCode::ConcurrentModification => "CONCURRENT_MODIFICATION",
+            ErrorCode::Erased => "ERASED",
+            ErrorCode::SequenceExhausted => "SEQUENCE_EXHAUSTED",
+        }
+    }
+}
+
 /// An error that can occur when processing GTFS data.
 #[derive(Error, Debug)]
 pub enum Error {
@@ -66,6 +93,42 @@ pub enum Error {
     /// Propagate a ConfigError
     #[error("Unknown CallerType: {0}")]
     ConfigError(config::ConfigError),
+
+    /// Propagate a TokenError
+    #[error("Token error: {0}")]
+    TokenError(TokenError),
+
+    /// Propagate a CryptoError
+    #[error("Crypto error: {0}")]
+    CryptoError(CryptoError),
+}
+
+impl Error {
+    /// The `ErrorCode` the error falls into
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Error::Generic(code, _, _) => *code,
+            Error::ReferenceError(_) => ErrorCode::InvalidReference,
+            Error::InvalidJWT(_) | Error::UnknownRole(_) | Error::UnknownCallerType(_) => {
+                ErrorCode::Unauthorized
+            }
+            Error::InvalidTenantId(_) => ErrorCode::BadRequest,
+            Error::TokenError(err) => match err {
+                TokenError::GenerationFailure(_) => ErrorCode::ServerError,
+                TokenError::SequenceExhausted(_) => ErrorCode::SequenceExhausted,
+                TokenError::InvalidValue(_) | TokenError::InvalidTemplate(_) => {
+                    ErrorCode::BadRequest
+                }
+            },
+            Error::CryptoError(err) => match err {
+                CryptoError::InvalidInput(_) => ErrorCode::BadRequest,
+                _ => ErrorCode::ServerError,
+            },
+            Error::RepositoryError(_) | Error::MissingConfig(_) | Error::ConfigError(_) => {
+                ErrorCode::ServerError
+            }
+        }
+    }
 }
 
 impl From<config::ConfigError> for Error {
@@ -74,6 +137,18 @@ impl From<config::ConfigError> for Error {
     }
 }
 
+impl From<TokenError> for Error {
+    fn from(err: TokenError) -> Self {
+        Self::TokenError(err)
+    }
+}
+
+impl From<CryptoError> for Error {
+    fn from(err: CryptoError) -> Self {
+        Self::CryptoError(err)
+    }
+}
+
 impl From<sqlx::Error> for Error {
     fn from(err: sqlx::Error) -> Self {
         Self::RepositoryError(err)
@@ -1,12 +1,10 @@
 use std::collections::HashMap;
 use std::fmt::Formatter;
 
-use config::ConfigError;
 use secrecy::{ExposeSecret, Secret};
 use serde::{Deserialize, Deserializer};
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
 use sqlx::ConnectOptions;
-use tracing::metadata::LevelFilter;
 
 use crate::error::Error as TError;
 
@@ -14,6 +12,11 @@ use crate::error::Error as TError;
 pub struct Settings {
     pub database: DatabaseSettings,
     pub web: WebSettings,
+    pub jwt: JwtSettings,
+    pub vault: VaultSettings,
+    pub keys: KeyProviderSettings,
+    #[serde(default)]
+    pub tokenization: TokenizationSettings,
 }
 
 #[derive(serde::Deserialize, Debug, Clone)]
@@ -21,6 +24,57 @@ pub struct WebSettings {
     pub port: u16,
 }
 
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct JwtSettings {
+    pub secret: Secret<String>,
+    pub issuer: String,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct VaultSettings {
+    /// base64 encoded (at least) 256 bits key used to index the values of deterministic policies
+    pub index_key: Secret<String>,
+}
+
+/// Keys of the vaultless formats, base64 encoded (at least) 256 bits keys from
+/// which the per-tenant keys are derived; a policy whose key is missing fails
+/// with `MissingConfig`.
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+pub struct TokenizationSettings {
+    /// format preserving encryption of `Fpe` policies
+    pub fpe_key: Option<Secret<String>>,
+    /// offsets of `DateShift` policies
+    pub date_shift_key: Option<Secret<String>>,
+    /// `Pseudonym` policies
+    pub pseudonym_key: Option<Secret<String>>,
+    /// `Permuted` sequences
+    pub permutation_key: Option<Secret<String>>,
+    /// ratio of the capacity of a sequence past which a warning is emitted (0.8 by default)
+    pub sequence_usage_warning: Option<f64>,
+}
+
+/// Where the master key, wrapping the per-tenant keys that encrypt the vault values, lives
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(tag = "provider", rename_all = "lowercase")]
+pub enum KeyProviderSettings {
+    /// local file of base64 encoded keys, one per line, the latest last (insecure, development only)
+    File { path: String },
+    /// environment variable of base64 encoded keys, comma separated, the latest last
+    Env { variable: String },
+    /// HashiCorp Vault Transit secrets engine
+    Transit {
+        address: String,
+        token: Secret<String>,
+        #[serde(default = "default_transit_mount")]
+        mount: String,
+        key_name: String,
+    },
+}
+
+fn default_transit_mount() -> String {
+    "transit".to_string()
+}
+
 #[derive(serde::Deserialize, Debug, Clone)]
 pub struct DatabaseCredentials {
     pub username: String,
@@ -81,7 +135,7 @@ impl DatabaseSettings {
         let creds = self
             .roles
             .get(role)
-            .expect(format!("Missing role credentials {role}").as_str());
+            .unwrap_or_else(|| panic!("Missing role credentials {role}"));
         PgConnectOptions::new()
             .host(&self.host)
             .username(creds.username.as_str())
@@ -98,10 +152,15 @@ impl DatabaseSettings {
 }
 
 fn check_settings(environment: &Environment, settings: &Settings) {
-    if environment == &Environment::Prod {
-        if settings.database.roles.contains_key(&DatabaseRole::Root) {
-            panic!("Root role should not appear in production environment")
-        }
+    if environment == &Environment::Prod
+        && settings.database.roles.contains_key(&DatabaseRole::Root)
+    {
+        panic!("Root role should not appear in production environment")
+    }
+    if environment == &Environment::Prod
+        && matches!(settings.keys, KeyProviderSettings::File { .. })
+    {
+        panic!("Local key file should not be used in production environment")
     }
 }
 
@@ -170,3 +229,79 @@ impl TryFrom<String> for Environment {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_settings(keys: &str) -> Settings {
+        let yaml = format!(
+            r#"
+database:
+  database_name: tokend
+  host: localhost
+  port: 5432
+  roles:
+    application:
+        username: tokend_app
+        password: p
+web:
+  port: 5001
+jwt:
+  secret: s
+  issuer: i
+vault:
+  index_key: fi8bZTzqHba4BqYyi+UE4QA+PiO2TwXi76d+wIukqCA=
+keys:
+{keys}
+tokenization:
+  fpe_key: 2p7ZQ9jVweYXV4Gk0A+5JNJ7dS9CHR3CE3Yxspjcx9U=
+  sequence_usage_warning: 0.9
+"#
+        );
+        config::Config::builder()
+            .add_source(config::File::from_str(&yaml, config::FileFormat::Yaml))
+            .build()
+            .and_then(|config| config.try_deserialize())
+            .expect("Invalid settings")
+    }
+
+    #[test]
+    fn key_providers_settings() {
+        let settings = sample_settings("  provider: env\n  variable: TOKEND_KEYS");
+        assert!(matches!(
+            settings.keys,
+            KeyProviderSettings::Env { variable } if variable == "TOKEND_KEYS"
+        ));
+        let settings = sample_settings(
+            "  provider: transit\n  address: http://vault:8200\n  token: t\n  key_name: tokend",
+        );
+        assert!(matches!(
+            settings.keys,
+            KeyProviderSettings::Transit { mount, .. } if mount == "transit"
+        ));
+    }
+
+    #[test]
+    fn tokenization_keys_are_optional() {
+        let settings = sample_settings("  provider: env\n  variable: TOKEND_KEYS");
+        assert!(settings.tokenization.fpe_key.is_some());
+        assert!(settings.tokenization.pseudonym_key.is_none());
+        assert!(TokenizationSettings::default().fpe_key.is_none());
+        assert_eq!(settings.tokenization.sequence_usage_warning, Some(0.9));
+    }
+
+    #[test]
+    #[should_panic(expected = "Local key file")]
+    fn local_key_file_is_refused_in_production() {
+        let settings = sample_settings("  provider: file\n  path: ./conf/local.keys");
+        check_settings(&Environment::Local, &settings);
+        check_settings(&Environment::Prod, &settings);
+    }
+
+    #[test]
+    fn environment_keys_are_allowed_in_production() {
+        let settings = sample_settings("  provider: env\n  variable: TOKEND_KEYS");
+        check_settings(&Environment::Prod, &settings);
+    }
+}
@@ -1,18 +1,81 @@
 use crate::core::context::ExecutionContext;
+use crate::core::crypto::{BlindIndex, KeyProvider};
 use crate::error::Error as TError;
 use sqlx::pool::PoolConnection;
-use sqlx::{Acquire, Error, PgConnection, Pool, Postgres, Transaction};
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::{PgConnection, Pool, Postgres, Transaction};
 use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
 
+#[derive(Clone)]
 pub struct ContextualizedPool {
     pool: Pool<Postgres>,
+    key_provider: Option<Arc<dyn KeyProvider>>,
+    blind_index: Option<Arc<BlindIndex>>,
 }
 
+/// Connection carrying the context for the whole session, until released to the pool
 pub struct ContextualizedConnection(PoolConnection<Postgres>);
 
+/// Transaction carrying the context until it ends (`SET LOCAL`); rolled back
+/// when dropped without being committed
+pub struct ContextualizedTransaction(Transaction<'static, Postgres>);
+
 impl ContextualizedPool {
-    pub fn new(pool: Pool<Postgres>) -> ContextualizedPool {
-        ContextualizedPool { pool }
+    /// Contextualized pool, connecting lazily with `options`; the context of a
+    /// connection is reset when it is released, so that it never leaks to the next one
+    pub fn connect_lazy_with(
+        options: PgPoolOptions,
+        connect_options: PgConnectOptions,
+    ) -> ContextualizedPool {
+        ContextualizedPool::from_pool(reset_on_release(options).connect_lazy_with(connect_options))
+    }
+
+    /// As `connect_lazy_with`, connecting right away
+    pub async fn connect_with(
+        options: PgPoolOptions,
+        connect_options: PgConnectOptions,
+    ) -> Result<ContextualizedPool, TError> {
+        let pool = reset_on_release(options)
+            .connect_with(connect_options)
+            .await?;
+        Ok(ContextualizedPool::from_pool(pool))
+    }
+
+    fn from_pool(pool: Pool<Postgres>) -> ContextualizedPool {
+        ContextualizedPool {
+            pool,
+            key_provider: None,
+            blind_index: None,
+        }
+    }
+
+    /// Provider wrapping the per-tenant keys used to encrypt the values stored in the vault
+    pub fn with_key_provider(self, key_provider: Arc<dyn KeyProvider>) -> ContextualizedPool {
+        ContextualizedPool {
+            key_provider: Some(key_provider),
+            ..self
+        }
+    }
+
+    /// Keyed index used to find the tokens of deterministic policies
+    pub fn with_blind_index(self, blind_index: Arc<BlindIndex>) -> ContextualizedPool {
+        ContextualizedPool {
+            blind_index: Some(blind_index),
+            ..self
+        }
+    }
+
+    pub(crate) fn blind_index(&self) -> Result<&BlindIndex, TError> {
+        self.blind_index
+            .as_deref()
+            .ok_or_else(|| TError::MissingConfig("vault blind index".to_string()))
+    }
+
+    pub(crate) fn key_provider(&self) -> Result<&dyn KeyProvider, TError> {
+        self.key_provider
+            .as_deref()
+            .ok_or_else(|| TError::MissingConfig("vault key provider".to_string()))
     }
 
     pub async fn acquire(
@@ -25,11 +88,37 @@ impl ContextualizedPool {
         Ok(contextualized)
     }
 
+    /// Starts a transaction within the context, which is committed or rolled back as a whole
+    pub async fn begin(
+        &self,
+        context: &ExecutionContext,
+    ) -> Result<ContextualizedTransaction, TError> {
+        let mut tx = self.pool.begin().await?;
+        let (caller_type, caller_id, tenant) = context_values(context);
+        apply_context(&mut tx, &caller_type, &caller_id, &tenant, true).await?;
+        Ok(ContextualizedTransaction(tx))
+    }
+
+    /// Underlying pool, whose connections carry no context
+    pub fn pool(&self) -> &Pool<Postgres> {
+        &self.pool
+    }
+
     pub async fn close(&self) {
         self.pool.close().await
     }
 }
 
+fn reset_on_release(options: PgPoolOptions) -> PgPoolOptions {
+    options.after_release(|conn, _| {
+        Box::pin(async move {
+            // an error closes the connection rather than returning it to the pool
+            apply_context(conn, "", "", "", false).await?;
+            Ok(true)
+        })
+    })
+}
+
 impl Deref for ContextualizedConnection {
     type Target = PoolConnection<Postgres>;
     fn deref(&self) -> &Self::Target {
@@ -43,40 +132,64 @@ impl DerefMut for ContextualizedConnection {
     }
 }
 
+impl Deref for ContextualizedTransaction {
+    type Target = Transaction<'static, Postgres>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for ContextualizedTransaction {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl ContextualizedTransaction {
+    pub async fn commit(self) -> Result<(), TError> {
+        Ok(self.0.commit().await?)
+    }
+
+    pub async fn rollback(self) -> Result<(), TError> {
+        Ok(self.0.rollback().await?)
+    }
+}
+
 impl ContextualizedConnection {
     async fn contextualize(&mut self, context: &ExecutionContext) -> Result<(), TError> {
-        set_config(
-            self.deref_mut(),
-            "var.caller_type".to_string(),
-            context.caller.caller_type.to_string(),
-        )
-        .await?;
-
-        set_config(
-            self.deref_mut(),
-            "var.caller_id".to_string(),
-            context.caller.caller_id.to_string(),
-        )
-        .await?;
-
-        let tid = match context.tenant.as_ref() {
-            None => "".to_string(),
-            Some(s) => s.to_string(),
-        };
-
-        set_config(self.deref_mut(), "var.tenant_id".to_string(), tid);
-
+        let (caller_type, caller_id, tenant) = context_values(context);
+        apply_context(&mut self.0, &caller_type, &caller_id, &tenant, false).await?;
         Ok(())
     }
 }
 
-async fn set_config(
-    conn: &mut PoolConnection<Postgres>,
-    key: String,
-    value: String,
-) -> Result<(), TError> {
-    sqlx::query!("select set_config($1, $2, 'f')", key, value)
-        .fetch_one(conn)
-        .await?;
+fn context_values(context: &ExecutionContext) -> (String, String, String) {
+    (
+        context.caller.caller_type.to_string(),
+        context.caller.caller_id.to_string(),
+        context.tenant_label(),
+    )
+}
+
+/// Sets the variables read by the audit triggers and the row level security;
+/// `local` limits them to the current transaction.
+async fn apply_context(
+    conn: &mut PgConnection,
+    caller_type: &str,
+    caller_id: &str,
+    tenant: &str,
+    local: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "select set_config('var.caller_type', $1, $4) as caller_type,
+         set_config('var.caller_id', $2, $4) as caller_id,
+         set_config('var.tenant_id', $3, $4) as tenant_id",
+        caller_type,
+        caller_id,
+        tenant,
+        local
+    )
+    .fetch_one(conn)
+    .await?;
     Ok(())
 }
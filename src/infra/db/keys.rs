@@ -0,0 +1,463 @@
+use async_trait::async_trait;
+use sqlx::PgConnection;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::ops::Deref;
+
+use crate::core::context::{ExecutionContext, Permission};
+use crate::core::crypto::{Cipher, DataKey};
+use crate::core::token::VaultKeys;
+use crate::core::util;
+use crate::error::{Error as CError, ErrorCode};
+use crate::infra::db::tokens::associated_data;
+use crate::infra::{db, db::ContextualizedPool};
+
+/// Values whose key has been shredded
+fn erased(detail: &str, value: String) -> CError {
+    CError::Generic(
+        ErrorCode::Erased,
+        "Data erased".to_string(),
+        HashMap::from([(detail.to_string(), value)]),
+    )
+}
+
+impl ContextualizedPool {
+    /// Latest data key of the current tenant along with its version, generated on first use
+    pub(crate) async fn current_data_key(
+        &self,
+        conn: &mut PgConnection,
+        tenant: &str,
+    ) -> Result<(i32, DataKey), CError> {
+        let provider = self.key_provider()?;
+        if let Some(record) = sqlx::query!(
+            "select version, wrapped_key from tenant_keys order by version desc limit 1"
+        )
+        .fetch_optional(&mut *conn)
+        .await?
+        {
+            let wrapped = record
+                .wrapped_key
+                .ok_or_else(|| erased("tenant", tenant.to_string()))?;
+            let key = DataKey::unwrap(provider, tenant, &wrapped).await?;
+            return Ok((record.version, key));
+        }
+
+        let (key, wrapped) = DataKey::generate(provider, tenant).await?;
+        let inserted = sqlx::query!(
+            "insert into tenant_keys (wrapped_key, version) values ($1, 1) on conflict (tenant_id, version) do nothing",
+            wrapped
+        )
+        .execute(&mut *conn)
+        .await?;
+        if inserted.rows_affected() == 1 {
+            return Ok((1, key));
+        }
+
+        // generated concurrently: the first one wins
+        Ok((1, self.data_key(conn, tenant, 1).await?))
+    }
+
+    /// Data key of the current tenant in the given version
+    pub(crate) async fn data_key(
+        &self,
+        conn: &mut PgConnection,
+        tenant: &str,
+        version: i32,
+    ) -> Result<DataKey, CError> {
+        let record = sqlx::query!(
+            "select wrapped_key from tenant_keys where version = $1",
+            version
+        )
+        .fetch_optional(&mut *conn)
+        .await?
+        .ok_or_else(|| {
+            CError::Generic(
+                ErrorCode::ServerError,
+                "Unknown data key version".to_string(),
+                HashMap::from([("version".to_string(), version.to_string())]),
+            )
+        })?;
+        let wrapped = record
+            .wrapped_key
+            .ok_or_else(|| erased("tenant", tenant.to_string()))?;
+        Ok(DataKey::unwrap(self.key_provider()?, tenant, &wrapped).await?)
+    }
+
+    /// Data key of `subject` along with its id and the version of the tenant
+    /// key wrapping it, generated on first use
+    pub(crate) async fn subject_key(
+        &self,
+        conn: &mut PgConnection,
+        tenant: &str,
+        subject: &str,
+    ) -> Result<(i64, i32, DataKey), CError> {
+        if let Some(key) = self.find_subject_key(conn, tenant, subject).await? {
+            return Ok(key);
+        }
+
+        let (version, tenant_key) = self.current_data_key(conn, tenant).await?;
+        let (key, wrapped) = tenant_key.generate_subject_key(tenant, subject)?;
+        let inserted = sqlx::query!(
+            "insert into subject_keys (subject, wrapped_key, tenant_key_version) values ($1, $2, $3)
+             on conflict (tenant_id, subject) do nothing returning id",
+            subject,
+            wrapped,
+            version
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+        if let Some(record) = inserted {
+            return Ok((record.id, version, key));
+        }
+
+        // generated concurrently: the first one wins
+        self.find_subject_key(conn, tenant, subject)
+            .await?
+            .ok_or_else(|| {
+                CError::Generic(
+                    ErrorCode::ServerError,
+                    "Subject key not found".to_string(),
+                    HashMap::new(),
+                )
+            })
+    }
+
+    async fn find_subject_key(
+        &self,
+        conn: &mut PgConnection,
+        tenant: &str,
+        subject: &str,
+    ) -> Result<Option<(i64, i32, DataKey)>, CError> {
+        let Some(record) = sqlx::query!(
+            "select id, wrapped_key, tenant_key_version from subject_keys where subject = $1",
+            subject
+        )
+        .fetch_optional(&mut *conn)
+        .await?
+        else {
+            return Ok(None);
+        };
+        let key = self
+            .open_subject_key(
+                conn,
+                tenant,
+                subject,
+                record.tenant_key_version,
+                record.wrapped_key,
+            )
+            .await?;
+        Ok(Some((record.id, record.tenant_key_version, key)))
+    }
+
+    /// Data key of a subject, from its id
+    pub(crate) async fn subject_key_by_id(
+        &self,
+        conn: &mut PgConnection,
+        tenant: &str,
+        id: i64,
+    ) -> Result<DataKey, CError> {
+        let record = sqlx::query!(
+            "select subject, wrapped_key, tenant_key_version from subject_keys where id = $1",
+            id
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+        self.open_subject_key(
+            conn,
+            tenant,
+            &record.subject,
+            record.tenant_key_version,
+            record.wrapped_key,
+        )
+        .await
+    }
+
+    async fn open_subject_key(
+        &self,
+        conn: &mut PgConnection,
+        tenant: &str,
+        subject: &str,
+        tenant_key_version: i32,
+        wrapped: Option<Vec<u8>>,
+    ) -> Result<DataKey, CError> {
+        let wrapped = wrapped.ok_or_else(|| erased("subject", subject.to_string()))?;
+        let tenant_key = self.data_key(conn, tenant, tenant_key_version).await?;
+        Ok(tenant_key.unwrap_subject_key(tenant, subject, &wrapped)?)
+    }
+}
+
+#[async_trait]
+impl VaultKeys for ContextualizedPool {
+    async fn rotate_data_key(&self, context: &ExecutionContext) -> Result<i32, CError> {
+        context.ensure_permission(&Permission::KeyRotate)?;
+        let tenant = context.tenant_label();
+        let (_, wrapped) = DataKey::generate(self.key_provider()?, &tenant).await?;
+
+        let mut tx = self.begin(context).await?;
+        let shredded = sqlx::query!(
+            r#"select exists(select 1 from tenant_keys where shredded_at is not null) as "shredded!""#
+        )
+        .fetch_one(&mut *tx)
+        .await?
+        .shredded;
+        if shredded {
+            return Err(erased("tenant", tenant));
+        }
+        let res = sqlx::query!(
+            "insert into tenant_keys (wrapped_key, version)
+             select $1, coalesce(max(version), 0) + 1 from tenant_keys
+             returning version",
+            wrapped
+        )
+        .fetch_one(&mut *tx)
+        .await;
+        match res {
+            Ok(record) => {
+                tx.commit().await?;
+                Ok(record.version)
+            }
+            Err(e)
+                if db::is_unique_constraint_error(&e, Some("tenant_keys_version_uniqueness")) =>
+            {
+                Err(CError::Generic(
+                    ErrorCode::ConcurrentModification,
+                    "Data key rotated concurrently".to_string(),
+                    HashMap::from([("tenant".to_string(), tenant)]),
+                ))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn rewrap_data_keys(&self, context: &ExecutionContext) -> Result<usize, CError> {
+        context.ensure_permission(&Permission::KeyRotate)?;
+        let tenant = context.tenant_label();
+        let provider = self.key_provider()?;
+        let mut tx = self.begin(context).await?;
+        let records = sqlx::query!(
+            "select id, wrapped_key from tenant_keys where wrapped_key is not null order by id"
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        for record in &records {
+            let Some(wrapped_key) = &record.wrapped_key else {
+                continue;
+            };
+            let wrapped = DataKey::rewrap(provider, &tenant, wrapped_key).await?;
+            sqlx::query!(
+                "update tenant_keys set wrapped_key = $2 where id = $1",
+                record.id,
+                wrapped
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(records.len())
+    }
+
+    async fn rewrap_subject_keys(
+        &self,
+        context: &ExecutionContext,
+        paging: util::Paging,
+    ) -> Result<util::Page<i64>, CError> {
+        context.ensure_permission(&Permission::KeyRotate)?;
+        let tenant = context.tenant_label();
+        let mut tx = self.begin(context).await?;
+        let (version, current) = self.current_data_key(&mut tx, &tenant).await?;
+        let limit: i64 = paging.first + 1;
+        let cursor: util::paging::IntCursor = paging.clone().into();
+        let mut records = sqlx::query!(
+            r#"select id, subject, wrapped_key as "wrapped_key!", tenant_key_version from subject_keys
+             where id > $1 and tenant_key_version <> $2 and wrapped_key is not null order by id limit $3"#,
+            cursor.deref(),
+            version,
+            limit
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        let has_next_page = records.len() > paging.first as usize;
+        records.truncate(paging.first as usize);
+
+        let mut previous: HashMap<i32, DataKey> = HashMap::new();
+        let mut rewrapped = vec![];
+        for record in &records {
+            let key = match previous.entry(record.tenant_key_version) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(
+                    self.data_key(&mut tx, &tenant, record.tenant_key_version)
+                        .await?,
+                ),
+            };
+            let wrapped =
+                current.rewrap_subject_key(key, &tenant, &record.subject, &record.wrapped_key)?;
+            // skipped if rewrapped or shredded concurrently
+            let updated = sqlx::query!(
+                "update subject_keys set wrapped_key = $2, tenant_key_version = $3
+                 where id = $1 and tenant_key_version = $4 and wrapped_key is not null",
+                record.id,
+                wrapped,
+                version,
+                record.tenant_key_version
+            )
+            .execute(&mut *tx)
+            .await?;
+            if updated.rows_affected() == 1 {
+                rewrapped.push(record.id);
+            }
+        }
+
+        tx.commit().await?;
+
+        let page_infos = match records.last() {
+            Some(last) if has_next_page => util::PageInfos::page_after(last.id, true),
+            _ => util::PageInfos::no_page_after(),
+        };
+        Ok(util::Page {
+            items: rewrapped,
+            page_infos,
+        })
+    }
+
+    async fn reencrypt_tokens(
+        &self,
+        context: &ExecutionContext,
+        paging: util::Paging,
+    ) -> Result<util::Page<i64>, CError> {
+        context.ensure_permission(&Permission::KeyRotate)?;
+        let tenant = context.tenant_label();
+        let mut tx = self.begin(context).await?;
+        let (version, current) = self.current_data_key(&mut tx, &tenant).await?;
+        let limit: i64 = paging.first + 1;
+        let cursor: util::paging::IntCursor = paging.clone().into();
+        let mut records = sqlx::query!(
+            "select id, policy_code, token, value, key_version from tokens
+             where id > $1 and key_version <> $2 and subject_key_id is null order by id limit $3",
+            cursor.deref(),
+            version,
+            limit
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        let has_next_page = records.len() > paging.first as usize;
+        records.truncate(paging.first as usize);
+
+        let mut previous: HashMap<i32, DataKey> = HashMap::new();
+        let mut reencrypted = vec![];
+        for record in &records {
+            let key = match previous.entry(record.key_version) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    entry.insert(self.data_key(&mut tx, &tenant, record.key_version).await?)
+                }
+            };
+            let aad = associated_data(context, &record.policy_code, &record.token);
+            // left as is, not to hold the whole vault back
+            let value = match key.decrypt(&record.value, &aad) {
+                Ok(value) => value,
+                Err(err) => {
+                    tracing::warn!(
+                        tenant = %tenant,
+                        token_id = record.id,
+                        error = %err,
+                        "value cannot be decrypted, not re-encrypted"
+                    );
+                    continue;
+                }
+            };
+            let encrypted = current.encrypt(&value, &aad)?;
+            // skipped if re-encrypted concurrently
+            let updated = sqlx::query!(
+                "update tokens set value = $2, key_version = $3 where id = $1 and key_version = $4",
+                record.id,
+                encrypted,
+                version,
+                record.key_version
+            )
+            .execute(&mut *tx)
+            .await?;
+            if updated.rows_affected() == 1 {
+                reencrypted.push(record.id);
+            }
+        }
+
+        tx.commit().await?;
+
+        let page_infos = match records.last() {
+            Some(last) if has_next_page => util::PageInfos::page_after(last.id, true),
+            _ => util::PageInfos::no_page_after(),
+        };
+        Ok(util::Page {
+            items: reencrypted,
+            page_infos,
+        })
+    }
+
+    async fn shred_subject_key(
+        &self,
+        context: &ExecutionContext,
+        subject: &str,
+    ) -> Result<(), CError> {
+        context.ensure_permission(&Permission::KeyShred)?;
+        let mut tx = self.begin(context).await?;
+        let id = sqlx::query!(
+            "update subject_keys set wrapped_key = null, shredded_at = now()
+             where subject = $1 and wrapped_key is not null returning id",
+            subject
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .map(|record| record.id);
+        let Some(id) = id else {
+            // already shredded
+            return sqlx::query!("select id from subject_keys where subject = $1", subject)
+                .fetch_optional(&mut *tx)
+                .await?
+                .map(|_| ())
+                .ok_or_else(|| {
+                    CError::Generic(
+                        ErrorCode::NotFound,
+                        "Subject key not found".to_string(),
+                        HashMap::from([("subject".to_string(), subject.to_string())]),
+                    )
+                });
+        };
+        // the blind indexes would still tell which values were tokenized for the subject
+        sqlx::query!(
+            "update tokens set value_index = null where subject_key_id = $1",
+            id
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn shred_data_keys(&self, context: &ExecutionContext) -> Result<(), CError> {
+        context.ensure_permission(&Permission::KeyShred)?;
+        let mut tx = self.begin(context).await?;
+        // a tenant without data key yet gets a shredded one, so that nothing is stored afterwards
+        sqlx::query!(
+            "insert into tenant_keys (wrapped_key, version, shredded_at)
+             select null, 1, now() where not exists (select 1 from tenant_keys)
+             on conflict (tenant_id, version) do nothing"
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            "update tenant_keys set wrapped_key = null, shredded_at = now() where wrapped_key is not null"
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            "update subject_keys set wrapped_key = null, shredded_at = now() where wrapped_key is not null"
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!("update tokens set value_index = null where value_index is not null")
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+}
@@ -1,5 +1,10 @@
+#[allow(clippy::module_inception)]
 mod db;
+mod keys;
+mod policies;
+mod sequences;
 mod tenants;
+mod tokens;
 mod util;
 
 pub use db::ContextualizedPool;
@@ -0,0 +1,185 @@
+use crate::core::context::{ExecutionContext, Permission};
+use crate::core::util;
+use crate::core::util::LengthUnit;
+use async_trait::async_trait;
+use sqlx::types::Json;
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+
+use crate::core::policy::{Policies, StoredPolicy};
+use crate::core::token::{Policy, TokenFormat};
+use crate::error::{Error as CError, ErrorCode};
+use crate::infra::{db, db::ContextualizedPool};
+
+struct PolicyRecord {
+    id: i64,
+    row_version: i32,
+    code: String,
+    format: Json<TokenFormat>,
+    prefix: Option<String>,
+    keep_left: i32,
+    keep_right: i32,
+    deterministic: bool,
+    length_unit: String,
+}
+
+impl From<PolicyRecord> for StoredPolicy {
+    fn from(record: PolicyRecord) -> Self {
+        StoredPolicy {
+            id: record.id,
+            row_version: record.row_version,
+            policy: Policy {
+                code: record.code,
+                format: record.format.0,
+                prefix: record.prefix,
+                keep_left: record.keep_left as usize,
+                keep_right: record.keep_right as usize,
+                deterministic: record.deterministic,
+                // the values are constrained by policies_length_unit_check
+                length_unit: LengthUnit::try_from(record.length_unit.as_str()).unwrap_or_default(),
+            },
+        }
+    }
+}
+
+fn map_write_error(e: sqlx::Error, code: &str) -> CError {
+    if db::is_unique_constraint_error(&e, Some("policies_code_uniqueness")) {
+        CError::Generic(
+            ErrorCode::UniqueViolation,
+            "Duplicate policy".to_string(),
+            HashMap::from([("code".to_string(), code.to_string())]),
+        )
+    } else if db::is_concurrent_update_error(&e) {
+        CError::Generic(
+            ErrorCode::ConcurrentModification,
+            "Policy has been modified concurrently".to_string(),
+            HashMap::from([("code".to_string(), code.to_string())]),
+        )
+    } else {
+        e.into()
+    }
+}
+
+#[async_trait]
+impl Policies for ContextualizedPool {
+    async fn create_policy(
+        &self,
+        context: &ExecutionContext,
+        policy: Policy,
+    ) -> Result<StoredPolicy, CError> {
+        context.ensure_permission(&Permission::PolicyCreate)?;
+        let mut conn = self.acquire(context).await?;
+
+        let res = sqlx::query_as!(
+            PolicyRecord,
+            r#"insert into policies (code, format, prefix, keep_left, keep_right, deterministic, length_unit)
+               values ($1, $2, $3, $4, $5, $6, $7)
+               returning id, row_version, code, format as "format: Json<TokenFormat>", prefix, keep_left, keep_right, deterministic, length_unit"#,
+            policy.code,
+            Json(&policy.format) as _,
+            policy.prefix,
+            policy.keep_left as i32,
+            policy.keep_right as i32,
+            policy.deterministic,
+            policy.length_unit.as_str()
+        )
+        .fetch_one(conn.deref_mut())
+        .await
+        .map(StoredPolicy::from)
+        .map_err(|e| map_write_error(e, &policy.code))?;
+        Ok(res)
+    }
+
+    async fn find_policy_by_code(
+        &self,
+        context: &ExecutionContext,
+        code: String,
+    ) -> Result<Option<StoredPolicy>, CError> {
+        context.ensure_permission(&Permission::PolicyRead)?;
+        let mut conn = self.acquire(context).await?;
+        let res = sqlx::query_as!(
+            PolicyRecord,
+            r#"select id, row_version, code, format as "format: Json<TokenFormat>", prefix, keep_left, keep_right, deterministic, length_unit
+               from policies where code = $1"#,
+            code
+        )
+        .fetch_optional(conn.deref_mut())
+        .await?
+        .map(StoredPolicy::from);
+        Ok(res)
+    }
+
+    async fn find_policies(
+        &self,
+        context: &ExecutionContext,
+        paging: util::Paging,
+    ) -> Result<util::Page<StoredPolicy>, CError> {
+        context.ensure_permission(&Permission::PolicyRead)?;
+        let mut conn = self.acquire(context).await?;
+        let limit: i64 = paging.first + 1;
+        let cursor: util::paging::IntCursor = paging.clone().into();
+        let mut policies: Vec<StoredPolicy> = sqlx::query_as!(
+            PolicyRecord,
+            r#"select id, row_version, code, format as "format: Json<TokenFormat>", prefix, keep_left, keep_right, deterministic, length_unit
+               from policies where id > $1 order by id limit $2"#,
+            cursor.deref(),
+            limit
+        )
+        .fetch_all(conn.deref_mut())
+        .await?
+        .into_iter()
+        .map(StoredPolicy::from)
+        .collect();
+
+        if policies.len() > paging.first as usize {
+            policies.truncate(paging.first as usize);
+            let last = policies.last().map(|p| p.id).unwrap_or(*cursor);
+            Ok(util::Page {
+                items: policies,
+                page_infos: util::PageInfos::page_after(last, true),
+            })
+        } else {
+            Ok(util::Page {
+                items: policies,
+                page_infos: util::PageInfos::no_page_after(),
+            })
+        }
+    }
+
+    async fn update_policy(
+        &self,
+        context: &ExecutionContext,
+        policy: Policy,
+        row_version: i32,
+    ) -> Result<StoredPolicy, CError> {
+        context.ensure_permission(&Permission::PolicyUpdate)?;
+        let mut conn = self.acquire(context).await?;
+
+        let res = sqlx::query_as!(
+            PolicyRecord,
+            r#"update policies
+               set format = $2, prefix = $3, keep_left = $4, keep_right = $5, deterministic = $6, length_unit = $7, row_version = $8 + 1
+               where code = $1
+               returning id, row_version, code, format as "format: Json<TokenFormat>", prefix, keep_left, keep_right, deterministic, length_unit"#,
+            policy.code,
+            Json(&policy.format) as _,
+            policy.prefix,
+            policy.keep_left as i32,
+            policy.keep_right as i32,
+            policy.deterministic,
+            policy.length_unit.as_str(),
+            row_version
+        )
+        .fetch_optional(conn.deref_mut())
+        .await
+        .map_err(|e| map_write_error(e, &policy.code))?;
+
+        res.map(StoredPolicy::from).ok_or_else(|| {
+            CError::Generic(
+                ErrorCode::NotFound,
+                "Policy not found".to_string(),
+                HashMap::from([("code".to_string(), policy.code)]),
+            )
+        })
+    }
+}
@@ -0,0 +1,58 @@
+use crate::core::context::{ExecutionContext, Permission};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::ops::DerefMut;
+
+use crate::core::token::{Policy, SequenceSource};
+use crate::error::{Error as CError, ErrorCode};
+use crate::infra::db::ContextualizedPool;
+
+#[async_trait]
+impl SequenceSource for ContextualizedPool {
+    async fn reserve(
+        &self,
+        context: &ExecutionContext,
+        policy: &Policy,
+        count: i64,
+    ) -> Result<i64, CError> {
+        context.ensure_permission(&Permission::TokenCreate)?;
+        let mut conn = self.acquire(context).await?;
+        // the row lock serializes concurrent reservations, across instances too
+        let record = sqlx::query!(
+            r#"update token_sequences set next_value = next_value + $2
+               where policy_code = $1
+               returning next_value - $2 as "first!""#,
+            policy.code,
+            count
+        )
+        .fetch_optional(conn.deref_mut())
+        .await?;
+
+        record
+            .map(|r| r.first)
+            .ok_or_else(|| unknown_policy(policy))
+    }
+
+    async fn next_value(&self, context: &ExecutionContext, policy: &Policy) -> Result<i64, CError> {
+        context.ensure_permission(&Permission::PolicyRead)?;
+        let mut conn = self.acquire(context).await?;
+        let record = sqlx::query!(
+            "select next_value from token_sequences where policy_code = $1",
+            policy.code
+        )
+        .fetch_optional(conn.deref_mut())
+        .await?;
+
+        record
+            .map(|r| r.next_value)
+            .ok_or_else(|| unknown_policy(policy))
+    }
+}
+
+fn unknown_policy(policy: &Policy) -> CError {
+    CError::Generic(
+        ErrorCode::InvalidReference,
+        "Unknown policy".to_string(),
+        HashMap::from([("policy".to_string(), policy.code.clone())]),
+    )
+}
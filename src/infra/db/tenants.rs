@@ -15,7 +15,7 @@ impl Tenants for ContextualizedPool {
         context: &ExecutionContext,
         tenant: NewTenant,
     ) -> Result<Tenant, CError> {
-        let mut conn = self.acquire(&context).await?;
+        let mut conn = self.acquire(context).await?;
 
         let res = sqlx::query!(
             "insert into tenants (code) values ($1) returning id,code",
@@ -46,7 +46,7 @@ impl Tenants for ContextualizedPool {
         context: &ExecutionContext,
         code: String,
     ) -> Result<Option<Tenant>, CError> {
-        let mut conn = self.acquire(&context).await?;
+        let mut conn = self.acquire(context).await?;
         let res = sqlx::query!("select id, code from tenants where code = $1", code)
             .fetch_optional(conn.deref_mut())
             .await
@@ -64,37 +64,35 @@ impl Tenants for ContextualizedPool {
         context: &ExecutionContext,
         paging: util::Paging,
     ) -> Result<util::Page<Tenant>, CError> {
-        let mut conn = self.acquire(&context).await?;
+        let mut conn = self.acquire(context).await?;
         let limit: i64 = paging.first + 1;
         let cursor: util::paging::IntCursor = paging.clone().into();
-        let res = sqlx::query!(
+        let mut tenants: Vec<Tenant> = sqlx::query!(
             "select id, code from tenants where id > $1 order by id limit $2",
             cursor.deref(),
             limit
         )
         .fetch_all(conn.deref_mut())
-        .await
-        .map(|records| {
-            let tenants: Vec<Tenant> = records
-                .into_iter()
-                .map(|record| Tenant {
-                    id: record.id,
-                    code: record.code,
-                })
-                .collect();
+        .await?
+        .into_iter()
+        .map(|record| Tenant {
+            id: record.id,
+            code: record.code,
+        })
+        .collect();
 
-            if tenants.len() > paging.first as usize {
-                util::Page {
-                    items: tenants[..tenants.len()].to_vec(),
-                    page_infos: util::PageInfos::page_after(tenants.last().unwrap().id, true),
-                }
-            } else {
-                util::Page {
-                    items: tenants,
-                    page_infos: util::PageInfos::no_page_after(),
-                }
-            }
-        })?;
-        Ok(res)
+        if tenants.len() > paging.first as usize {
+            tenants.truncate(paging.first as usize);
+            let last = tenants.last().map(|t| t.id).unwrap_or(*cursor);
+            Ok(util::Page {
+                items: tenants,
+                page_infos: util::PageInfos::page_after(last, true),
+            })
+        } else {
+            Ok(util::Page {
+                items: tenants,
+                page_infos: util::PageInfos::no_page_after(),
+            })
+        }
     }
 }
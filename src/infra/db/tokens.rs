@@ -0,0 +1,172 @@
+use crate::core::context::{ExecutionContext, Permission};
+use crate::core::crypto::Cipher;
+use async_trait::async_trait;
+use secrecy::Secret;
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+
+use crate::core::token::{Policy, Token, TokenVault};
+use crate::error::{Error as CError, ErrorCode};
+use crate::infra::{db, db::ContextualizedPool};
+
+/// Binds the ciphertext to its row: it cannot be swapped with another
+/// token, policy or tenant without failing the decryption.
+pub(super) fn associated_data(
+    context: &ExecutionContext,
+    policy_code: &str,
+    token: &str,
+) -> Vec<u8> {
+    format!("{}:{}:{}", context.tenant_label(), policy_code, token).into_bytes()
+}
+
+impl ContextualizedPool {
+    /// Scoped to the subject, whose values are encrypted with a key of its own
+    fn value_index(
+        &self,
+        context: &ExecutionContext,
+        policy: &Policy,
+        subject: Option<&str>,
+        value: &Secret<String>,
+    ) -> Result<Vec<u8>, CError> {
+        let tenant = context.tenant_label();
+        let mut scope = vec![tenant.as_str(), policy.code.as_str()];
+        scope.extend(subject);
+        Ok(self.blind_index()?.compute(&scope, value))
+    }
+
+    async fn find_token_by_index(
+        &self,
+        context: &ExecutionContext,
+        policy: &Policy,
+        value_index: &[u8],
+    ) -> Result<Option<Token>, CError> {
+        let mut conn = self.acquire(context).await?;
+        let res = sqlx::query!(
+            "select token from tokens where policy_code = $1 and value_index = $2",
+            policy.code,
+            value_index
+        )
+        .fetch_optional(conn.deref_mut())
+        .await?
+        .map(|record| Token::from(record.token));
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl TokenVault for ContextualizedPool {
+    async fn store_token(
+        &self,
+        context: &ExecutionContext,
+        policy: &Policy,
+        subject: Option<&str>,
+        token: &Token,
+        value: &Secret<String>,
+    ) -> Result<Token, CError> {
+        context.ensure_permission(&Permission::TokenCreate)?;
+        let value_index = if policy.deterministic {
+            Some(self.value_index(context, policy, subject, value)?)
+        } else {
+            None
+        };
+
+        let tenant = context.tenant_label();
+        let mut conn = self.acquire(context).await?;
+        let (subject_key_id, key_version, key) = match subject {
+            Some(subject) => {
+                let (id, version, key) = self.subject_key(&mut conn, &tenant, subject).await?;
+                (Some(id), version, key)
+            }
+            None => {
+                let (version, key) = self.current_data_key(&mut conn, &tenant).await?;
+                (None, version, key)
+            }
+        };
+        let encrypted = key.encrypt(value, &associated_data(context, &policy.code, token))?;
+        let res = sqlx::query!(
+            "insert into tokens (policy_code, token, value, value_index, key_version, subject_key_id)
+             values ($1, $2, $3, $4, $5, $6)",
+            policy.code,
+            token.deref(),
+            encrypted,
+            value_index,
+            key_version,
+            subject_key_id
+        )
+        .execute(conn.deref_mut())
+        .await;
+        drop(conn);
+
+        match (res, value_index) {
+            (Ok(_), _) => Ok(token.clone()),
+            // the same value has been tokenized concurrently: the first one wins
+            (Err(e), Some(value_index))
+                if db::is_unique_constraint_error(&e, Some("tokens_value_index_uniqueness")) =>
+            {
+                self.find_token_by_index(context, policy, &value_index)
+                    .await?
+                    .ok_or_else(|| e.into())
+            }
+            (Err(e), _) => {
+                if db::is_unique_constraint_error(&e, Some("tokens_token_uniqueness")) {
+                    Err(CError::Generic(
+                        ErrorCode::UniqueViolation,
+                        "Duplicate token".to_string(),
+                        HashMap::from([("policy".to_string(), policy.code.clone())]),
+                    ))
+                } else {
+                    Err(e.into())
+                }
+            }
+        }
+    }
+
+    async fn find_token(
+        &self,
+        context: &ExecutionContext,
+        policy: &Policy,
+        subject: Option<&str>,
+        value: &Secret<String>,
+    ) -> Result<Option<Token>, CError> {
+        context.ensure_permission(&Permission::TokenCreate)?;
+        let value_index = self.value_index(context, policy, subject, value)?;
+        self.find_token_by_index(context, policy, &value_index)
+            .await
+    }
+
+    async fn resolve_token(
+        &self,
+        context: &ExecutionContext,
+        policy: &Policy,
+        token: &Token,
+    ) -> Result<Option<Secret<String>>, CError> {
+        context.ensure_permission(&Permission::TokenRead)?;
+        let mut conn = self.acquire(context).await?;
+        let record = sqlx::query!(
+            "select value, key_version, subject_key_id from tokens where policy_code = $1 and token = $2",
+            policy.code,
+            token.deref()
+        )
+        .fetch_optional(conn.deref_mut())
+        .await?;
+
+        match record {
+            None => Ok(None),
+            Some(record) => {
+                let tenant = context.tenant_label();
+                let key = match record.subject_key_id {
+                    Some(id) => self.subject_key_by_id(&mut conn, &tenant, id).await?,
+                    None => {
+                        self.data_key(&mut conn, &tenant, record.key_version)
+                            .await?
+                    }
+                };
+                let value = key.decrypt(
+                    &record.value,
+                    &associated_data(context, &policy.code, token),
+                )?;
+                Ok(Some(value))
+            }
+        }
+    }
+}
@@ -20,3 +20,11 @@ pub fn is_unique_constraint_error(e: &sqlx::Error, contraint_name: Option<&str>)
         })
         .unwrap_or(false)
 }
+
+/// Error raised by the `prevent_from_concurrent_update` trigger
+pub fn is_concurrent_update_error(e: &sqlx::Error) -> bool {
+    e.as_database_error()
+        .and_then(|err| err.code())
+        .map(|code| code.eq_ignore_ascii_case("23V01"))
+        .unwrap_or(false)
+}
@@ -0,0 +1,62 @@
+use async_trait::async_trait;
+use base64::{engine::general_purpose, Engine as _};
+use secrecy::zeroize::Zeroizing;
+use secrecy::Secret;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use crate::core::crypto::{random_key, CryptoError, KeyProvider, Keyring};
+
+/// Local key file holding one base64 encoded key per line, the latest last.
+///
+/// The keys lie in clear on the disk: development only.
+#[derive(Debug)]
+pub struct FileKeyProvider {
+    path: PathBuf,
+    keyring: RwLock<Keyring>,
+}
+
+impl FileKeyProvider {
+    pub fn open(path: impl Into<PathBuf>) -> Result<FileKeyProvider, CryptoError> {
+        let path = path.into();
+        let keys = std::fs::read_to_string(&path)
+            .map(Secret::new)
+            .map_err(|e| CryptoError::ProviderFailure(format!("{}: {e}", path.display())))?;
+        Ok(FileKeyProvider {
+            path,
+            keyring: RwLock::new(Keyring::from_base64(&keys)?),
+        })
+    }
+}
+
+#[async_trait]
+impl KeyProvider for FileKeyProvider {
+    async fn wrap(&self, key: &[u8], aad: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        self.keyring
+            .read()
+            .expect("keyring lock poisoned")
+            .wrap(key, aad)
+    }
+
+    async fn unwrap(&self, wrapped: &[u8], aad: &[u8]) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+        self.keyring
+            .read()
+            .expect("keyring lock poisoned")
+            .unwrap(wrapped, aad)
+    }
+
+    /// Appends a new random key to the file
+    async fn rotate(&self) -> Result<(), CryptoError> {
+        let mut keyring = self.keyring.write().expect("keyring lock poisoned");
+        let key = random_key();
+        let encoded = Zeroizing::new(general_purpose::STANDARD.encode(key.as_ref()));
+        // persisted first: a key must never wrap anything before it is saved
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| writeln!(file, "\n{}", encoded.as_str()))
+            .map_err(|e| CryptoError::ProviderFailure(format!("{}: {e}", self.path.display())))?;
+        keyring.add_version(key.as_ref())
+    }
+}
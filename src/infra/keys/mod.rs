@@ -0,0 +1,35 @@
+mod file;
+mod transit;
+
+pub use file::FileKeyProvider;
+pub use transit::TransitKeyProvider;
+
+use secrecy::Secret;
+use std::sync::Arc;
+
+use crate::core::crypto::{KeyProvider, Keyring};
+use crate::error::Error as TError;
+use crate::infra::config::KeyProviderSettings;
+
+/// The `KeyProvider` selected in the settings
+pub fn key_provider(settings: &KeyProviderSettings) -> Result<Arc<dyn KeyProvider>, TError> {
+    Ok(match settings {
+        KeyProviderSettings::File { path } => Arc::new(FileKeyProvider::open(path)?),
+        KeyProviderSettings::Env { variable } => {
+            let keys = std::env::var(variable)
+                .map_err(|_| TError::MissingConfig(format!("environment variable {variable}")))?;
+            Arc::new(Keyring::from_base64(&Secret::new(keys))?)
+        }
+        KeyProviderSettings::Transit {
+            address,
+            token,
+            mount,
+            key_name,
+        } => Arc::new(TransitKeyProvider::new(
+            address,
+            token.clone(),
+            mount,
+            key_name,
+        )),
+    })
+}
@@ -0,0 +1,143 @@
+use async_trait::async_trait;
+use base64::{engine::general_purpose, Engine as _};
+use reqwest::StatusCode;
+use secrecy::zeroize::Zeroizing;
+use secrecy::{ExposeSecret, Secret};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::core::crypto::{CryptoError, KeyProvider};
+
+/// HashiCorp Vault Transit secrets engine (or any compatible server): the KEK
+/// never leaves the server, which wraps and unwraps the keys.
+///
+/// Wrapped keys are the ciphertexts returned by the server, e.g. `vault:v1:...`.
+#[derive(Debug)]
+pub struct TransitKeyProvider {
+    client: reqwest::Client,
+    address: String,
+    token: Secret<String>,
+    mount: String,
+    key_name: String,
+}
+
+#[derive(Serialize)]
+struct EncryptRequest<'a> {
+    plaintext: &'a str,
+    associated_data: String,
+}
+
+#[derive(Serialize)]
+struct DecryptRequest<'a> {
+    ciphertext: &'a str,
+    associated_data: String,
+}
+
+#[derive(Deserialize)]
+struct Response<T> {
+    data: T,
+}
+
+#[derive(Deserialize)]
+struct Encrypted {
+    ciphertext: String,
+}
+
+#[derive(Deserialize)]
+struct Decrypted {
+    plaintext: Secret<String>,
+}
+
+impl TransitKeyProvider {
+    pub fn new(
+        address: &str,
+        token: Secret<String>,
+        mount: &str,
+        key_name: &str,
+    ) -> TransitKeyProvider {
+        TransitKeyProvider {
+            client: reqwest::Client::new(),
+            address: address.trim_end_matches('/').to_string(),
+            token,
+            mount: mount.to_string(),
+            key_name: key_name.to_string(),
+        }
+    }
+
+    async fn post<T: DeserializeOwned>(
+        &self,
+        action: &str,
+        body: &impl Serialize,
+    ) -> Result<T, CryptoError> {
+        let url = format!("{}/v1/{}/{}", self.address, self.mount, action);
+        let failure = |e: reqwest::Error| CryptoError::ProviderFailure(format!("{url}: {e}"));
+        let response = self
+            .client
+            .post(&url)
+            .header("X-Vault-Token", self.token.expose_secret())
+            .json(body)
+            .send()
+            .await
+            .map_err(failure)?;
+        match response.status() {
+            // e.g. the associated data does not match
+            StatusCode::BAD_REQUEST if action.starts_with("decrypt/") => {
+                Err(CryptoError::DecryptionFailure)
+            }
+            status if !status.is_success() => {
+                Err(CryptoError::ProviderFailure(format!("{url}: {status}")))
+            }
+            _ => response.json().await.map_err(failure),
+        }
+    }
+}
+
+#[async_trait]
+impl KeyProvider for TransitKeyProvider {
+    async fn wrap(&self, key: &[u8], aad: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let plaintext = Zeroizing::new(general_purpose::STANDARD.encode(key));
+        let request = EncryptRequest {
+            plaintext: &plaintext,
+            associated_data: general_purpose::STANDARD.encode(aad),
+        };
+        let response: Response<Encrypted> = self
+            .post(&format!("encrypt/{}", self.key_name), &request)
+            .await?;
+        Ok(response.data.ciphertext.into_bytes())
+    }
+
+    async fn unwrap(&self, wrapped: &[u8], aad: &[u8]) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+        let request = DecryptRequest {
+            ciphertext: std::str::from_utf8(wrapped).map_err(|_| CryptoError::DecryptionFailure)?,
+            associated_data: general_purpose::STANDARD.encode(aad),
+        };
+        let response: Response<Decrypted> = self
+            .post(&format!("decrypt/{}", self.key_name), &request)
+            .await?;
+        general_purpose::STANDARD
+            .decode(response.data.plaintext.expose_secret())
+            .map(Zeroizing::new)
+            .map_err(|_| CryptoError::DecryptionFailure)
+    }
+
+    async fn rotate(&self) -> Result<(), CryptoError> {
+        let url = format!(
+            "{}/v1/{}/keys/{}/rotate",
+            self.address, self.mount, self.key_name
+        );
+        let response = self
+            .client
+            .post(&url)
+            .header("X-Vault-Token", self.token.expose_secret())
+            .send()
+            .await
+            .map_err(|e| CryptoError::ProviderFailure(format!("{url}: {e}")))?;
+        if !response.status().is_success() {
+            return Err(CryptoError::ProviderFailure(format!(
+                "{url}: {}",
+                response.status()
+            )));
+        }
+        Ok(())
+    }
+}
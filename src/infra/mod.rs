@@ -1,3 +1,6 @@
 pub mod config;
 pub mod db;
+pub mod keys;
 pub mod telemetry;
+pub mod tokenization;
+pub mod web;
@@ -0,0 +1,50 @@
+use std::sync::Arc;
+
+use crate::core::crypto::{FpeCipher, KeyedOffset, KeyedPermutation, Pseudonymizer};
+use crate::core::token::{
+    SequenceRawTokenGenerator, SequenceSource, TokenGenerator, TokenVault, Tokenizer,
+};
+use crate::error::Error as TError;
+use crate::infra::config::TokenizationSettings;
+
+/// `Tokenizer` holding the keys of the vaultless formats found in the settings
+pub fn tokenizer<G, V>(
+    settings: &TokenizationSettings,
+    generator: G,
+    vault: V,
+) -> Result<Tokenizer<G, V>, TError>
+where
+    G: TokenGenerator + Sync,
+    V: TokenVault + Sync,
+{
+    let mut tokenizer = Tokenizer::new(generator, vault);
+    if let Some(key) = &settings.fpe_key {
+        tokenizer = tokenizer.with_fpe(Arc::new(FpeCipher::from_base64(key)?));
+    }
+    if let Some(key) = &settings.date_shift_key {
+        tokenizer = tokenizer.with_date_shift(Arc::new(KeyedOffset::from_base64(key)?));
+    }
+    if let Some(key) = &settings.pseudonym_key {
+        tokenizer = tokenizer.with_pseudonymizer(Arc::new(Pseudonymizer::from_base64(key)?));
+    }
+    Ok(tokenizer)
+}
+
+/// `SequenceRawTokenGenerator` drawing from `sequences`, with the permutation key
+/// and usage warning found in the settings
+pub fn sequence_generator<S>(
+    settings: &TokenizationSettings,
+    sequences: S,
+) -> Result<SequenceRawTokenGenerator<S>, TError>
+where
+    S: SequenceSource,
+{
+    let mut generator = SequenceRawTokenGenerator::new(sequences);
+    if let Some(key) = &settings.permutation_key {
+        generator = generator.with_permutation(Arc::new(KeyedPermutation::from_base64(key)?));
+    }
+    if let Some(ratio) = settings.sequence_usage_warning {
+        generator = generator.with_usage_warning(ratio);
+    }
+    Ok(generator)
+}
@@ -0,0 +1,231 @@
+use std::collections::HashSet;
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header;
+use actix_web::HttpMessage;
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use secrecy::ExposeSecret;
+
+use crate::core::context::{Caller, CallerType, ExecutionContext, Permission, Role, TenantId};
+use crate::error::Error as CError;
+use crate::infra::config::JwtSettings;
+
+/// Claims expected in the bearer JWT of every authenticated request.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Claims {
+    /// Caller identifier
+    pub sub: String,
+    pub caller_type: String,
+    pub tenant: Option<String>,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    pub iss: String,
+    pub exp: usize,
+}
+
+impl TryFrom<Claims> for ExecutionContext {
+    type Error = CError;
+
+    fn try_from(claims: Claims) -> Result<Self, Self::Error> {
+        let caller_type: CallerType = claims.caller_type.try_into()?;
+        let tenant: Option<TenantId> = claims.tenant.map(TenantId::try_from).transpose()?;
+        let mut permissions: HashSet<Permission> = HashSet::new();
+        for role in claims.roles {
+            let role: Role = role.try_into()?;
+            permissions.extend(role.permissions());
+        }
+        Ok(ExecutionContext::new(
+            tenant,
+            Caller::new(claims.sub, caller_type),
+            permissions,
+        ))
+    }
+}
+
+pub fn decode_context(token: &str, settings: &JwtSettings) -> Result<ExecutionContext, CError> {
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.set_issuer(&[settings.issuer.as_str()]);
+    let key = DecodingKey::from_secret(settings.secret.expose_secret().as_bytes());
+    let data = decode::<Claims>(token, &key, &validation)
+        .map_err(|e| CError::InvalidJWT(e.to_string()))?;
+    data.claims.try_into()
+}
+
+fn bearer_token(req: &ServiceRequest) -> Result<&str, CError> {
+    let value = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .ok_or_else(|| CError::InvalidJWT("Missing Authorization header".to_string()))?
+        .to_str()
+        .map_err(|_| CError::InvalidJWT("Invalid Authorization header".to_string()))?;
+    value
+        .strip_prefix("Bearer ")
+        .ok_or_else(|| CError::InvalidJWT("Bearer token expected".to_string()))
+}
+
+/// Middleware checking the bearer JWT and attaching the resulting
+/// `ExecutionContext` to the request.
+pub struct JwtAuthentication {
+    settings: Rc<JwtSettings>,
+}
+
+impl JwtAuthentication {
+    pub fn new(settings: JwtSettings) -> JwtAuthentication {
+        JwtAuthentication {
+            settings: Rc::new(settings),
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for JwtAuthentication
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = JwtAuthenticationMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(JwtAuthenticationMiddleware {
+            service: Rc::new(service),
+            settings: self.settings.clone(),
+        }))
+    }
+}
+
+pub struct JwtAuthenticationMiddleware<S> {
+    service: Rc<S>,
+    settings: Rc<JwtSettings>,
+}
+
+impl<S, B> Service<ServiceRequest> for JwtAuthenticationMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    actix_web::dev::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let context = bearer_token(&req).and_then(|token| decode_context(token, &self.settings));
+        match context {
+            Ok(context) => {
+                req.extensions_mut().insert(context);
+                let service = self.service.clone();
+                Box::pin(async move { service.call(req).await })
+            }
+            Err(err) => {
+                tracing::info!("Authentication rejected: {}", err);
+                Box::pin(ready(Err(err.into())))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use secrecy::Secret;
+
+    fn settings() -> JwtSettings {
+        JwtSettings {
+            secret: Secret::new("GRUMPFFFFF".to_string()),
+            issuer: "local".to_string(),
+        }
+    }
+
+    fn sample_claims() -> Claims {
+        Claims {
+            sub: "007".to_string(),
+            caller_type: "user".to_string(),
+            tenant: Some("12".to_string()),
+            roles: vec!["agent".to_string()],
+            iss: "local".to_string(),
+            exp: (chrono::Utc::now().timestamp() + 60) as usize,
+        }
+    }
+
+    fn encode_claims(claims: &Claims, secret: &str) -> String {
+        encode(
+            &Header::default(),
+            claims,
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn claims_to_execution_context() {
+        let context: ExecutionContext = sample_claims().try_into().unwrap();
+        assert_eq!(
+            context.caller,
+            Caller::new("007".to_string(), CallerType::USER)
+        );
+        assert_eq!(context.tenant.as_ref().unwrap().as_str(), "12");
+        for permission in Role::Agent.permissions() {
+            assert_eq!(
+                context.has_permission(&permission),
+                crate::core::context::PermissionControlState::Authorized
+            );
+        }
+    }
+
+    #[test]
+    fn claims_with_unknown_role() {
+        let mut claims = sample_claims();
+        claims.roles = vec!["grumpf".to_string()];
+        let res: Result<ExecutionContext, CError> = claims.try_into();
+        assert!(matches!(res, Err(CError::UnknownRole(_))));
+    }
+
+    #[test]
+    fn claims_with_unknown_caller_type() {
+        let mut claims = sample_claims();
+        claims.caller_type = "robot".to_string();
+        let res: Result<ExecutionContext, CError> = claims.try_into();
+        assert!(matches!(res, Err(CError::UnknownCallerType(_))));
+    }
+
+    #[test]
+    fn decode_context_nominal_case() {
+        let token = encode_claims(&sample_claims(), "GRUMPFFFFF");
+        let context = decode_context(&token, &settings()).unwrap();
+        assert_eq!(context.caller.caller_id, "007");
+    }
+
+    #[test]
+    fn decode_context_with_invalid_signature() {
+        let token = encode_claims(&sample_claims(), "not-the-secret");
+        let res = decode_context(&token, &settings());
+        assert!(matches!(res, Err(CError::InvalidJWT(_))));
+    }
+
+    #[test]
+    fn decode_context_with_invalid_issuer() {
+        let mut claims = sample_claims();
+        claims.iss = "someone-else".to_string();
+        let token = encode_claims(&claims, "GRUMPFFFFF");
+        let res = decode_context(&token, &settings());
+        assert!(matches!(res, Err(CError::InvalidJWT(_))));
+    }
+
+    #[test]
+    fn decode_context_with_expired_token() {
+        let mut claims = sample_claims();
+        claims.exp = (chrono::Utc::now().timestamp() - 3600) as usize;
+        let token = encode_claims(&claims, "GRUMPFFFFF");
+        let res = decode_context(&token, &settings());
+        assert!(matches!(res, Err(CError::InvalidJWT(_))));
+    }
+}
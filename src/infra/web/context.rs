@@ -0,0 +1,30 @@
+use std::collections::HashMap;
+use std::future::{ready, Ready};
+
+use actix_web::dev::Payload;
+use actix_web::{FromRequest, HttpMessage, HttpRequest};
+
+use crate::core::context::ExecutionContext;
+use crate::error::{Error as CError, ErrorCode};
+
+/// The `ExecutionContext` is expected to be attached to the request
+/// by an authentication layer; requests without it are rejected.
+impl FromRequest for ExecutionContext {
+    type Error = CError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let res = req
+            .extensions()
+            .get::<ExecutionContext>()
+            .cloned()
+            .ok_or_else(|| {
+                CError::Generic(
+                    ErrorCode::Unauthorized,
+                    "Missing execution context".to_string(),
+                    HashMap::new(),
+                )
+            });
+        ready(res)
+    }
+}
@@ -0,0 +1,206 @@
+//! Rendering of the crate errors as RFC 7807 `application/problem+json` responses
+use std::collections::HashMap;
+
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+
+use crate::error::{Error, ErrorCode};
+
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// RFC 7807 problem details
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    /// stable machine-readable code, see `ErrorCode::as_str`
+    pub code: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub details: HashMap<String, String>,
+}
+
+impl Problem {
+    pub fn new(
+        code: ErrorCode,
+        detail: Option<String>,
+        details: HashMap<String, String>,
+    ) -> Problem {
+        let status = code.status_code();
+        Problem {
+            problem_type: format!(
+                "urn:tokend:problem:{}",
+                code.as_str().to_lowercase().replace('_', "-")
+            ),
+            title: status
+                .canonical_reason()
+                .unwrap_or("Unknown error")
+                .to_string(),
+            status: status.as_u16(),
+            code: code.as_str().to_string(),
+            detail,
+            details,
+        }
+    }
+
+    pub fn to_response(&self) -> HttpResponse {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        HttpResponse::build(status)
+            .content_type(PROBLEM_JSON)
+            .json(self)
+    }
+}
+
+impl ErrorCode {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            ErrorCode::ServerError => StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorCode::UniqueViolation => StatusCode::CONFLICT,
+            ErrorCode::NotFound => StatusCode::NOT_FOUND,
+            ErrorCode::InvalidReference => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::BadRequest => StatusCode::BAD_REQUEST,
+            ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorCode::ReferenceViolation => StatusCode::CONFLICT,
+            ErrorCode::Forbidden => StatusCode::FORBIDDEN,
+            ErrorCode::ConcurrentModification => StatusCode::CONFLICT,
+            ErrorCode::Erased => StatusCode::GONE,
+            ErrorCode::SequenceExhausted => StatusCode::CONFLICT,
+        }
+    }
+}
+
+impl ResponseError for ErrorCode {
+    fn status_code(&self) -> StatusCode {
+        ErrorCode::status_code(self)
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        Problem::new(*self, None, HashMap::new()).to_response()
+    }
+}
+
+impl From<&Error> for Problem {
+    fn from(err: &Error) -> Self {
+        let code = err.code();
+        match err {
+            Error::Generic(_, message, details) if code != ErrorCode::ServerError => {
+                Problem::new(code, Some(message.clone()), details.clone())
+            }
+            // never leak internal details (e.g. sql) to the client
+            _ if code == ErrorCode::ServerError => Problem::new(code, None, HashMap::new()),
+            _ => Problem::new(code, Some(err.to_string()), HashMap::new()),
+        }
+    }
+}
+
+impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        self.code().status_code()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        if self.code() == ErrorCode::ServerError {
+            tracing::error!("{}", self);
+        }
+        Problem::from(self).to_response()
+    }
+}
+
+/// Turns actix extraction failures (invalid json body, query or path)
+/// into problem responses.
+pub fn bad_request<E: std::fmt::Display>(err: E) -> actix_web::Error {
+    Error::Generic(ErrorCode::BadRequest, err.to_string(), HashMap::new()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::token::TokenError;
+    use actix_web::body::to_bytes;
+
+    async fn problem_of(err: Error) -> (StatusCode, String, Problem) {
+        let response = err.error_response();
+        let status = response.status();
+        let content_type = response
+            .headers()
+            .get(actix_web::http::header::CONTENT_TYPE)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body = to_bytes(response.into_body()).await.unwrap();
+        (status, content_type, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[actix_web::test]
+    async fn generic_error_is_rendered_as_problem() {
+        let err = Error::Generic(
+            ErrorCode::UniqueViolation,
+            "Duplicate tenant".to_string(),
+            HashMap::from([("code".to_string(), "idfm".to_string())]),
+        );
+        let (status, content_type, problem) = problem_of(err).await;
+
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(content_type, PROBLEM_JSON);
+        assert_eq!(problem.status, 409);
+        assert_eq!(problem.code, "UNIQUE_VIOLATION");
+        assert_eq!(problem.problem_type, "urn:tokend:problem:unique-violation");
+        assert_eq!(problem.detail, Some("Duplicate tenant".to_string()));
+        assert_eq!(problem.details.get("code"), Some(&"idfm".to_string()));
+    }
+
+    #[actix_web::test]
+    async fn repository_error_does_not_leak_details() {
+        let err = Error::RepositoryError(sqlx::Error::Protocol(
+            "relation \"tenants\" does not exist".to_string(),
+        ));
+        let (status, _, problem) = problem_of(err).await;
+
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(problem.code, "SERVER_ERROR");
+        assert_eq!(problem.detail, None);
+        assert!(problem.details.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn jwt_errors_are_unauthorized() {
+        for err in [
+            Error::InvalidJWT("ExpiredSignature".to_string()),
+            Error::UnknownRole("grumpf".to_string()),
+            Error::UnknownCallerType("robot".to_string()),
+        ] {
+            let (status, _, problem) = problem_of(err).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+            assert_eq!(problem.code, "UNAUTHORIZED");
+        }
+    }
+
+    #[actix_web::test]
+    async fn exhausted_sequence_has_its_own_problem_type() {
+        let err: Error = TokenError::SequenceExhausted("sales".to_string()).into();
+        let (status, _, problem) = problem_of(err).await;
+
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(problem.code, "SEQUENCE_EXHAUSTED");
+        assert_eq!(
+            problem.problem_type,
+            "urn:tokend:problem:sequence-exhausted"
+        );
+    }
+
+    #[test]
+    fn error_code_status_codes() {
+        assert_eq!(ErrorCode::NotFound.status_code(), StatusCode::NOT_FOUND);
+        assert_eq!(ErrorCode::Forbidden.status_code(), StatusCode::FORBIDDEN);
+        assert_eq!(ErrorCode::BadRequest.status_code(), StatusCode::BAD_REQUEST);
+        assert_eq!(ErrorCode::Erased.status_code(), StatusCode::GONE);
+        assert_eq!(
+            ErrorCode::ServerError.status_code(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+}
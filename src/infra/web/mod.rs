@@ -0,0 +1,9 @@
+mod auth;
+mod context;
+mod error;
+mod startup;
+mod tenants;
+
+pub use auth::{decode_context, Claims, JwtAuthentication};
+pub use error::{Problem, PROBLEM_JSON};
+pub use startup::{run, Application};
@@ -0,0 +1,80 @@
+use std::net::TcpListener;
+use std::sync::Arc;
+
+use actix_web::dev::Server;
+use actix_web::middleware::Logger;
+use actix_web::{web, App, HttpResponse, HttpServer};
+use sqlx::postgres::PgPoolOptions;
+
+use crate::core::crypto::BlindIndex;
+use crate::infra::config::{DatabaseRole, JwtSettings, Settings};
+use crate::infra::db::ContextualizedPool;
+use crate::infra::keys::key_provider;
+use crate::infra::web::error::bad_request;
+use crate::infra::web::{tenants, JwtAuthentication};
+
+pub struct Application {
+    port: u16,
+    server: Server,
+}
+
+impl Application {
+    pub async fn build(settings: Settings) -> Result<Application, std::io::Error> {
+        let key_provider = key_provider(&settings.keys)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        let blind_index = BlindIndex::from_base64(&settings.vault.index_key)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        let pool = ContextualizedPool::connect_lazy_with(
+            PgPoolOptions::new(),
+            settings.database.with_db(&DatabaseRole::Application),
+        )
+        .with_key_provider(key_provider)
+        .with_blind_index(Arc::new(blind_index));
+
+        let listener = TcpListener::bind(("0.0.0.0", settings.web.port))?;
+        // port may have been picked by the OS when configured to `0`
+        let port = listener.local_addr()?.port();
+        let server = run(listener, pool, settings.jwt)?;
+
+        Ok(Application { port, server })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
+        self.server.await
+    }
+}
+
+pub fn run(
+    listener: TcpListener,
+    pool: ContextualizedPool,
+    jwt_settings: JwtSettings,
+) -> Result<Server, std::io::Error> {
+    let pool = web::Data::new(pool);
+    let server = HttpServer::new(move || {
+        App::new()
+            .wrap(Logger::default())
+            .app_data(web::JsonConfig::default().error_handler(|err, _| bad_request(err)))
+            .app_data(web::QueryConfig::default().error_handler(|err, _| bad_request(err)))
+            .app_data(web::PathConfig::default().error_handler(|err, _| bad_request(err)))
+            .route("/health_check", web::get().to(health_check))
+            .service(
+                web::scope("/tenants")
+                    .wrap(JwtAuthentication::new(jwt_settings.clone()))
+                    .route("", web::post().to(tenants::declare_tenant))
+                    .route("", web::get().to(tenants::find_tenants))
+                    .route("/{code}", web::get().to(tenants::find_tenant_by_code)),
+            )
+            .app_data(pool.clone())
+    })
+    .listen(listener)?
+    .run();
+    Ok(server)
+}
+
+async fn health_check() -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+
+use actix_web::{web, HttpResponse};
+
+use crate::core::context::ExecutionContext;
+use crate::core::tenant::{NewTenant, Tenants};
+use crate::core::util::Paging;
+use crate::error::{Error as CError, ErrorCode};
+use crate::infra::db::ContextualizedPool;
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+
+#[derive(Deserialize)]
+pub struct NewTenantRequest {
+    code: String,
+}
+
+#[derive(Deserialize)]
+pub struct PagingRequest {
+    first: Option<i64>,
+    after: Option<String>,
+}
+
+impl From<PagingRequest> for Paging {
+    fn from(value: PagingRequest) -> Self {
+        Paging::new(value.first.unwrap_or(DEFAULT_PAGE_SIZE), value.after)
+    }
+}
+
+pub async fn declare_tenant(
+    context: ExecutionContext,
+    repo: web::Data<ContextualizedPool>,
+    body: web::Json<NewTenantRequest>,
+) -> Result<HttpResponse, CError> {
+    let body = body.into_inner();
+    let tenant = repo
+        .declare_tenant(&context, NewTenant::new(body.code))
+        .await?;
+    Ok(HttpResponse::Created().json(tenant))
+}
+
+pub async fn find_tenant_by_code(
+    context: ExecutionContext,
+    repo: web::Data<ContextualizedPool>,
+    code: web::Path<String>,
+) -> Result<HttpResponse, CError> {
+    let code = code.into_inner();
+    match repo.find_tenant_by_code(&context, code.clone()).await? {
+        Some(tenant) => Ok(HttpResponse::Ok().json(tenant)),
+        None => Err(CError::Generic(
+            ErrorCode::NotFound,
+            "Tenant not found".to_string(),
+            HashMap::from([("code".to_string(), code)]),
+        )),
+    }
+}
+
+pub async fn find_tenants(
+    context: ExecutionContext,
+    repo: web::Data<ContextualizedPool>,
+    paging: web::Query<PagingRequest>,
+) -> Result<HttpResponse, CError> {
+    let page = repo
+        .find_tenants(&context, paging.into_inner().into())
+        .await?;
+    Ok(HttpResponse::Ok().json(page))
+}
@@ -1,6 +1,4 @@
 #[macro_use]
-extern crate derivative;
-#[macro_use]
 extern crate serde_derive;
 
 pub mod core;
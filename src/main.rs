@@ -1,4 +1,18 @@
-fn main() {
+use tokend::infra::config::get_configuration;
+use tokend::infra::telemetry::{get_subscriber, init_subscriber};
+use tokend::infra::web::Application;
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    dotenv::dotenv().ok();
+    let subscriber = get_subscriber("tokend".into(), "info".into(), std::io::stdout);
+    init_subscriber(subscriber);
+
     let version = env!("CARGO_PKG_VERSION");
-    println!("Hello, world! {}", version);
+    tracing::info!("Starting tokend {}", version);
+
+    let settings = get_configuration().expect("Failed to read configuration");
+    let application = Application::build(settings).await?;
+    tracing::info!("Listening on port {}", application.port());
+    application.run_until_stopped().await
 }
@@ -0,0 +1,66 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use sqlx::postgres::PgPoolOptions;
+use tokend::core::context::Permission::{TenantCreate, TenantRead};
+use tokend::core::context::{Caller, CallerType, ExecutionContext, Permission, TenantId};
+use tokend::core::crypto::BlindIndex;
+use tokend::core::tenant::{NewTenant, Tenants};
+use tokend::infra::config::{DatabaseRole, Settings};
+use tokend::infra::db::ContextualizedPool;
+use tokend::infra::keys::key_provider;
+
+use crate::helpers::startup;
+
+pub fn sample_caller() -> Caller {
+    Caller::new("007".to_string(), CallerType::USER)
+}
+
+pub async fn declare_tenant(repo: &ContextualizedPool, code: &str) -> TenantId {
+    let context = ExecutionContext::new(
+        None,
+        sample_caller(),
+        HashSet::from([TenantRead, TenantCreate]),
+    );
+    let tenant = repo
+        .declare_tenant(&context, NewTenant::new(code.to_string()))
+        .await
+        .expect("Failed to create tenant");
+    tenant.id.to_string().try_into().unwrap()
+}
+
+pub fn tenant_context(tenant: &TenantId, permissions: &[Permission]) -> ExecutionContext {
+    ExecutionContext::new(
+        Some(tenant.clone()),
+        sample_caller(),
+        permissions.iter().cloned().collect(),
+    )
+}
+
+/// Creates a fresh migrated database and a pool on it using the application role
+pub async fn set_up() -> (Settings, ContextualizedPool) {
+    std::env::set_var("APP_ENVIRONMENT", "local");
+    std::env::set_var("APP_CONFIG_DIR", "./conf");
+    let settings = startup::random_configuration().await;
+    startup::spawn_db(&settings.database).await;
+    startup::migrate_db(&settings.database).await;
+
+    let connect_options = settings.database.with_db(&DatabaseRole::Application);
+    let repo =
+        ContextualizedPool::connect_with(PgPoolOptions::new().max_connections(2), connect_options)
+            .await
+            .expect("Failed to create connection pool");
+
+    let key_provider = key_provider(&settings.keys).expect("Invalid key provider");
+    let blind_index =
+        BlindIndex::from_base64(&settings.vault.index_key).expect("Invalid vault index key");
+    let repo = repo
+        .with_key_provider(key_provider)
+        .with_blind_index(Arc::new(blind_index));
+    (settings, repo)
+}
+
+pub async fn tear_down(settings: &Settings, repo: ContextualizedPool) {
+    repo.close().await;
+    startup::drop_db(&settings.database).await;
+}
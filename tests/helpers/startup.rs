@@ -1,15 +1,14 @@
-use rand::{thread_rng, Rng};
-use rand::distributions::Alphanumeric;
+use jsonwebtoken::{encode, EncodingKey, Header};
 use once_cell::sync::Lazy;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use secrecy::ExposeSecret;
 
-use sqlx::postgres::{PgConnectOptions, PgPoolOptions, PgSslMode};
-use sqlx::{ConnectOptions, Connection, PgConnection, Pool, Postgres, Row};
-use std::convert::{TryFrom, TryInto};
-use tracing_log::log;
+use sqlx::postgres::PgPoolOptions;
 
-use tokend::error::Error as TError;
 use tokend::infra::config::{DatabaseRole, DatabaseSettings, Settings};
 use tokend::infra::telemetry;
+use tokend::infra::web::{Application, Claims};
 
 // Ensure that the `tracing` stack is only initialised once using `once_cell`
 static TRACING: Lazy<()> = Lazy::new(|| {
@@ -37,8 +36,11 @@ pub async fn random_configuration() -> Settings {
     let mut config =
         tokend::infra::config::get_configuration().expect("Failed to read configuration");
     config.web.port = 0;
-    config.database.database_name =
-        format!("test_{}_{}", chrono::Utc::now().format("%Y%m%d_%H%M%S"), rand_string);
+    config.database.database_name = format!(
+        "test_{}_{}",
+        chrono::Utc::now().format("%Y%m%d_%H%M%S"),
+        rand_string
+    );
     config
 }
 
@@ -74,7 +76,7 @@ async fn create_database(settings: &DatabaseSettings) {
         sqlx::query(sql)
             .fetch_all(&pool)
             .await
-            .expect(format!("Failed to execute statement: {}", sql).as_str());
+            .unwrap_or_else(|_| panic!("Failed to execute statement: {}", sql));
     }
     tracing::info!("Database {} created", settings.database_name);
     pool.close().await
@@ -119,8 +121,47 @@ async fn create_schema(settings: &DatabaseSettings) {
     pool.close().await
 }
 
-pub async fn spawn_app() {
+pub struct TestApp {
+    pub address: String,
+    pub settings: Settings,
+}
+
+impl TestApp {
+    pub async fn tear_down(&self) {
+        drop_db(&self.settings.database).await;
+    }
+
+    pub fn bearer(&self, tenant: Option<String>, roles: &[&str]) -> String {
+        let claims = Claims {
+            sub: "007".to_string(),
+            caller_type: "user".to_string(),
+            tenant,
+            roles: roles.iter().map(|r| r.to_string()).collect(),
+            iss: self.settings.jwt.issuer.clone(),
+            exp: (chrono::Utc::now().timestamp() + 300) as usize,
+        };
+        encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(self.settings.jwt.secret.expose_secret().as_bytes()),
+        )
+        .expect("Failed to encode JWT")
+    }
+}
+
+pub async fn spawn_app() -> TestApp {
     Lazy::force(&TRACING);
+    let settings = random_configuration().await;
+    spawn_db(&settings.database).await;
+    migrate_db(&settings.database).await;
+
+    let application = Application::build(settings.clone())
+        .await
+        .expect("Failed to build application");
+    let address = format!("http://127.0.0.1:{}", application.port());
+    tokio::spawn(application.run_until_stopped());
+
+    TestApp { address, settings }
 }
 
 pub async fn spawn_db(settings: &DatabaseSettings) {
@@ -150,7 +191,7 @@ pub async fn migrate_db(settings: &DatabaseSettings) {
             .on_behalf_of
             .as_ref()
         {
-            let vec = sqlx::query(format!("SET ROLE {}", owner_role).as_str())
+            sqlx::query(format!("SET ROLE {}", owner_role).as_str())
                 .execute(&mut connection)
                 .await
                 .expect("Failed to set migration Role.");
@@ -164,7 +205,6 @@ pub async fn migrate_db(settings: &DatabaseSettings) {
     pool.close().await;
 }
 
-
 pub async fn drop_db(settings: &DatabaseSettings) {
     let options = settings.without_db(&DatabaseRole::Root);
     let pool = PgPoolOptions::new()
@@ -172,7 +212,7 @@ pub async fn drop_db(settings: &DatabaseSettings) {
         .connect_with(options)
         .await
         .expect("Failed to create connection pool");
-    sqlx::query(format!("DROP DATABASE {};", settings.database_name).as_str())
+    sqlx::query(format!("DROP DATABASE {} WITH (FORCE);", settings.database_name).as_str())
         .execute(&pool)
         .await
         .expect("Failed to drop database.");
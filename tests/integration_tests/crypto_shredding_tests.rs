@@ -0,0 +1,304 @@
+use crate::helpers::fixtures::{declare_tenant, set_up, tear_down, tenant_context};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::postgres::PgPoolOptions;
+use sqlx::PgPool;
+use tokend::core::context::Permission::{KeyRotate, KeyShred, TokenCreate, TokenRead};
+use tokend::core::context::{ExecutionContext, TenantId};
+use tokend::core::token::{
+    DefaultTokenGenerator, InMemoryRawTokenGenerator, Policy, RotationJob, SequenceFormat, Token,
+    TokenFormat, Tokenizer, VaultKeys,
+};
+use tokend::core::util::Paging;
+use tokend::error::ErrorCode;
+use tokend::infra::config::{DatabaseRole, Settings};
+use tokend::infra::db::ContextualizedPool;
+
+type SampleTokenizer =
+    Tokenizer<DefaultTokenGenerator<InMemoryRawTokenGenerator>, ContextualizedPool>;
+
+fn sample_policy(deterministic: bool) -> Policy {
+    Policy {
+        prefix: Some("PAT-".to_string()),
+        deterministic,
+        ..Policy::new(
+            "patients",
+            TokenFormat::Sequence(SequenceFormat::PaddedInt(6, '0')),
+        )
+    }
+}
+
+fn context(tenant: &TenantId) -> ExecutionContext {
+    tenant_context(tenant, &[TokenCreate, TokenRead, KeyRotate, KeyShred])
+}
+
+fn new_tokenizer(repo: &ContextualizedPool) -> SampleTokenizer {
+    Tokenizer::new(
+        DefaultTokenGenerator::new(InMemoryRawTokenGenerator::new()),
+        repo.clone(),
+    )
+}
+
+async fn tokenize(
+    tokenizer: &SampleTokenizer,
+    tenant: &TenantId,
+    subject: Option<&str>,
+    value: &str,
+) -> Token {
+    tokenizer
+        .tokenize_with_subject(
+            &context(tenant),
+            &sample_policy(false),
+            subject,
+            Secret::new(value.to_string()),
+        )
+        .await
+        .expect("Failed to tokenize")
+}
+
+#[tokio::test]
+async fn shredded_subject_values_are_erased() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let tokenizer = new_tokenizer(&repo);
+    let policy = sample_policy(false);
+    let alice = tokenize(&tokenizer, &tenant, Some("alice"), "ALICE LIDDELL").await;
+    let bob = tokenize(&tokenizer, &tenant, Some("bob"), "BOB MORANE").await;
+    let anonymous = tokenize(&tokenizer, &tenant, None, "JOHN DOE").await;
+
+    // WHEN
+    repo.shred_subject_key(&context(&tenant), "alice")
+        .await
+        .expect("Failed to shred");
+
+    // THEN the values of the subject are erased
+    let err = tokenizer
+        .detokenize(&context(&tenant), &policy, &alice)
+        .await
+        .expect_err("Value should be erased");
+    assert_eq!(err.code(), ErrorCode::Erased);
+    let err = tokenizer
+        .tokenize_with_subject(
+            &context(&tenant),
+            &policy,
+            Some("alice"),
+            Secret::new("ALICE".to_string()),
+        )
+        .await
+        .expect_err("Subject should be erased");
+    assert_eq!(err.code(), ErrorCode::Erased);
+
+    // AND the other values are left untouched
+    for (token, expected) in [(bob, "BOB MORANE"), (anonymous, "JOHN DOE")] {
+        let value = tokenizer
+            .detokenize(&context(&tenant), &policy, &token)
+            .await
+            .expect("Failed to detokenize");
+        assert_eq!(value.expose_secret(), expected);
+    }
+
+    // AND the shredding is audited
+    assert_eq!(count_shreddings(&settings, "subject_keys").await, 1);
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn shredded_tenant_values_are_erased() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let other_tenant = declare_tenant(&repo, "sncf").await;
+    let tokenizer = new_tokenizer(&repo);
+    let policy = sample_policy(false);
+    let tokens = [
+        tokenize(&tokenizer, &tenant, Some("alice"), "ALICE LIDDELL").await,
+        tokenize(&tokenizer, &tenant, None, "JOHN DOE").await,
+    ];
+    let other = tokenize(&tokenizer, &other_tenant, None, "JOHN DOE").await;
+
+    // WHEN
+    repo.shred_data_keys(&context(&tenant))
+        .await
+        .expect("Failed to shred");
+
+    // THEN
+    for token in &tokens {
+        let err = tokenizer
+            .detokenize(&context(&tenant), &policy, token)
+            .await
+            .expect_err("Value should be erased");
+        assert_eq!(err.code(), ErrorCode::Erased);
+    }
+    let err = tokenizer
+        .tokenize(&context(&tenant), &policy, Secret::new("X".to_string()))
+        .await
+        .expect_err("Tenant should be erased");
+    assert_eq!(err.code(), ErrorCode::Erased);
+    let err = repo
+        .rotate_data_key(&context(&tenant))
+        .await
+        .expect_err("Shredded keys should not be rotated");
+    assert_eq!(err.code(), ErrorCode::Erased);
+
+    // AND the other tenants are left untouched
+    let value = tokenizer
+        .detokenize(&context(&other_tenant), &policy, &other)
+        .await
+        .expect("Failed to detokenize");
+    assert_eq!(value.expose_secret(), "JOHN DOE");
+
+    // AND the shredding is audited
+    assert_eq!(count_shreddings(&settings, "tenant_keys").await, 1);
+    assert_eq!(count_shreddings(&settings, "subject_keys").await, 1);
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn deterministic_subject_tokens_are_unlinked_once_shredded() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let tokenizer = new_tokenizer(&repo);
+    let policy = sample_policy(true);
+    let mut tokens = vec![];
+    for subject in ["alice", "bob"] {
+        let token = tokenizer
+            .tokenize_with_subject(
+                &context(&tenant),
+                &policy,
+                Some(subject),
+                Secret::new("75001".to_string()),
+            )
+            .await
+            .expect("Failed to tokenize");
+        tokens.push(token);
+    }
+    // the values of each subject have a key of their own
+    assert_ne!(tokens[0], tokens[1]);
+
+    // WHEN
+    repo.shred_subject_key(&context(&tenant), "alice")
+        .await
+        .expect("Failed to shred");
+
+    // THEN the value of the subject can no longer be matched
+    assert_eq!(count_value_indexes(&settings).await, 1);
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn shred_subject_key_checks() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let tokenizer = new_tokenizer(&repo);
+    tokenize(&tokenizer, &tenant, Some("alice"), "ALICE LIDDELL").await;
+
+    let err = repo
+        .shred_subject_key(&tenant_context(&tenant, &[KeyRotate]), "alice")
+        .await
+        .expect_err("Permission should be required");
+    assert_eq!(err.code(), ErrorCode::Forbidden);
+    let err = repo
+        .shred_subject_key(&context(&tenant), "bob")
+        .await
+        .expect_err("Subject should be unknown");
+    assert_eq!(err.code(), ErrorCode::NotFound);
+
+    // shredding again is a no-op
+    for _ in 0..2 {
+        repo.shred_subject_key(&context(&tenant), "alice")
+            .await
+            .expect("Failed to shred");
+    }
+    assert_eq!(count_shreddings(&settings, "subject_keys").await, 1);
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn rotation_rewraps_the_subject_keys() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let tokenizer = new_tokenizer(&repo);
+    let policy = sample_policy(false);
+    let mut tokens = vec![];
+    for subject in ["alice", "bob", "carol"] {
+        tokens.push(tokenize(&tokenizer, &tenant, Some(subject), "75001").await);
+    }
+    repo.shred_subject_key(&context(&tenant), "carol")
+        .await
+        .expect("Failed to shred");
+
+    // WHEN
+    repo.rotate_data_key(&context(&tenant))
+        .await
+        .expect("Failed to rotate the data key");
+    RotationJob::new(repo.clone(), 1)
+        .run(&context(&tenant), None, |_| {})
+        .await
+        .expect("Failed to run the rotation");
+
+    // THEN the subject keys are wrapped with the latest data key
+    assert_eq!(subject_key_versions(&settings).await, vec![2, 2, 1]);
+    for token in &tokens[..2] {
+        let value = tokenizer
+            .detokenize(&context(&tenant), &policy, token)
+            .await
+            .expect("Failed to detokenize");
+        assert_eq!(value.expose_secret(), "75001");
+    }
+
+    // AND nothing is left to rewrap, the shredded key being left out
+    let page = repo
+        .rewrap_subject_keys(&context(&tenant), Paging::new(10, None))
+        .await
+        .unwrap();
+    assert!(page.items.is_empty());
+
+    tear_down(&settings, repo).await;
+}
+
+async fn subject_key_versions(settings: &Settings) -> Vec<i32> {
+    let pool = migration_pool(settings).await;
+    let versions: Vec<i32> =
+        sqlx::query_scalar("select tenant_key_version from subject_keys order by id")
+            .fetch_all(&pool)
+            .await
+            .expect("Failed to query subject keys");
+    pool.close().await;
+    versions
+}
+
+async fn count_shreddings(settings: &Settings, table: &str) -> i64 {
+    let pool = migration_pool(settings).await;
+    let count: i64 = sqlx::query_scalar(
+        "select count(*) from audit_log where category = 'key' and changed_table_name = $1 \
+        and changed_type = 'U' and changed_fields -> 'after' ? 'shredded_at'",
+    )
+    .bind(table)
+    .fetch_one(&pool)
+    .await
+    .expect("Failed to query audit log");
+    pool.close().await;
+    count
+}
+
+async fn count_value_indexes(settings: &Settings) -> i64 {
+    let pool = migration_pool(settings).await;
+    let count: i64 =
+        sqlx::query_scalar("select count(*) from tokens where value_index is not null")
+            .fetch_one(&pool)
+            .await
+            .expect("Failed to count value indexes");
+    pool.close().await;
+    count
+}
+
+async fn migration_pool(settings: &Settings) -> PgPool {
+    // migration role bypasses the row level security
+    PgPoolOptions::new()
+        .max_connections(1)
+        .connect_with(settings.database.with_db(&DatabaseRole::Migration))
+        .await
+        .expect("Failed to create connection pool")
+}
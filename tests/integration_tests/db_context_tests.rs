@@ -0,0 +1,114 @@
+use crate::helpers::fixtures::{declare_tenant, tenant_context};
+use crate::helpers::startup;
+use sqlx::postgres::PgPoolOptions;
+use sqlx::PgPool;
+use std::ops::DerefMut;
+use tokend::core::context::TenantId;
+use tokend::infra::config::{DatabaseRole, Settings};
+use tokend::infra::db::ContextualizedPool;
+
+/// A single connection, shared by the raw and the contextualized pools
+async fn set_up() -> (Settings, PgPool, ContextualizedPool) {
+    std::env::set_var("APP_ENVIRONMENT", "local");
+    std::env::set_var("APP_CONFIG_DIR", "./conf");
+    let settings = startup::random_configuration().await;
+    startup::spawn_db(&settings.database).await;
+    startup::migrate_db(&settings.database).await;
+
+    let repo = ContextualizedPool::connect_with(
+        PgPoolOptions::new().max_connections(1),
+        settings.database.with_db(&DatabaseRole::Application),
+    )
+    .await
+    .expect("Failed to create connection pool");
+    (settings, repo.pool().clone(), repo)
+}
+
+async fn tear_down(settings: &Settings, repo: ContextualizedPool) {
+    repo.close().await;
+    startup::drop_db(&settings.database).await;
+}
+
+async fn session_tenant(pool: &PgPool) -> String {
+    let tenant: Option<String> =
+        sqlx::query_scalar("select current_setting('var.tenant_id', true)")
+            .fetch_one(pool)
+            .await
+            .expect("Failed to read the tenant");
+    tenant.unwrap_or_default()
+}
+
+async fn count_keys(repo: &ContextualizedPool, tenant: &TenantId) -> i64 {
+    let mut tx = repo
+        .begin(&tenant_context(tenant, &[]))
+        .await
+        .expect("Failed to begin");
+    let count: i64 = sqlx::query_scalar("select count(*) from tenant_keys")
+        .fetch_one(tx.deref_mut())
+        .await
+        .expect("Failed to count keys");
+    tx.commit().await.expect("Failed to commit");
+    count
+}
+
+#[tokio::test]
+async fn context_is_reset_when_the_connection_is_released() {
+    let (settings, pool, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+
+    let conn = repo
+        .acquire(&tenant_context(&tenant, &[]))
+        .await
+        .expect("Failed to acquire");
+    drop(conn);
+
+    assert_eq!(session_tenant(&pool).await, "");
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn transaction_context_is_local() {
+    let (settings, pool, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+
+    let mut tx = repo
+        .begin(&tenant_context(&tenant, &[]))
+        .await
+        .expect("Failed to begin");
+    let current: String = sqlx::query_scalar("select current_setting('var.tenant_id')")
+        .fetch_one(tx.deref_mut())
+        .await
+        .expect("Failed to read the tenant");
+    assert_eq!(current, tenant.to_string());
+    tx.commit().await.expect("Failed to commit");
+
+    assert_eq!(session_tenant(&pool).await, "");
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn transaction_is_rolled_back_as_a_whole() {
+    let (settings, _, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+
+    for rollback in [true, false] {
+        let mut tx = repo
+            .begin(&tenant_context(&tenant, &[]))
+            .await
+            .expect("Failed to begin");
+        sqlx::query("insert into tenant_keys (wrapped_key, version) values ('\\x00', 1)")
+            .execute(tx.deref_mut())
+            .await
+            .expect("Failed to insert");
+        if rollback {
+            tx.rollback().await.expect("Failed to roll back");
+        }
+        // dropped without commit
+    }
+
+    assert_eq!(count_keys(&repo, &tenant).await, 0);
+
+    tear_down(&settings, repo).await;
+}
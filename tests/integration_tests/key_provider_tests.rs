@@ -0,0 +1,132 @@
+use secrecy::Secret;
+use std::path::PathBuf;
+use tokend::core::crypto::{CryptoError, KeyProvider};
+use tokend::infra::config::KeyProviderSettings;
+use tokend::infra::keys::{key_provider, FileKeyProvider, TransitKeyProvider};
+use wiremock::matchers::{body_json, header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const KEY: &str = "9D/ahwoUsBu3WadgUBEeWVAylBWQS70P+zrZEgoyG/w=";
+
+fn key_file() -> PathBuf {
+    let path = std::env::temp_dir().join(format!("tokend_{}.keys", uuid::Uuid::new_v4()));
+    std::fs::write(&path, format!("{KEY}\n")).expect("Failed to write the key file");
+    path
+}
+
+#[tokio::test]
+async fn file_provider_rotation_keeps_the_previous_keys() {
+    let path = key_file();
+    let provider = FileKeyProvider::open(&path).expect("Failed to open the key file");
+    let wrapped = provider.wrap(&[7u8; 32], b"dek:1").await.unwrap();
+
+    provider.rotate().await.expect("Failed to rotate");
+    let rewrapped = provider.wrap(&[7u8; 32], b"dek:1").await.unwrap();
+    assert_ne!(wrapped[..4], rewrapped[..4]);
+
+    // the new key is saved: a fresh provider unwraps both
+    let reopened = FileKeyProvider::open(&path).expect("Failed to open the key file");
+    for wrapped in [wrapped, rewrapped] {
+        let key = reopened.unwrap(&wrapped, b"dek:1").await.unwrap();
+        assert_eq!(key.as_slice(), &[7u8; 32]);
+    }
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn env_provider_reads_the_keys_from_the_environment() {
+    // unique, the tests running in parallel in the same process
+    let variable = format!("TOKEND_TEST_KEYS_{}", uuid::Uuid::new_v4().simple());
+    let settings = KeyProviderSettings::Env {
+        variable: variable.clone(),
+    };
+    assert!(key_provider(&settings).is_err());
+
+    std::env::set_var(&variable, format!("{KEY},{KEY}"));
+    let provider = key_provider(&settings).expect("Failed to read the keys");
+    let wrapped = provider.wrap(&[7u8; 32], b"dek:1").await.unwrap();
+    let key = provider.unwrap(&wrapped, b"dek:1").await.unwrap();
+    assert_eq!(key.as_slice(), &[7u8; 32]);
+    assert!(matches!(
+        provider.rotate().await,
+        Err(CryptoError::ProviderFailure(_))
+    ));
+}
+
+#[tokio::test]
+async fn transit_provider_wraps_on_the_server() {
+    let server = MockServer::start().await;
+    let provider = TransitKeyProvider::new(
+        &server.uri(),
+        Secret::new("s.token".to_string()),
+        "transit",
+        "tokend",
+    );
+    Mock::given(method("POST"))
+        .and(path("/v1/transit/encrypt/tokend"))
+        .and(header("X-Vault-Token", "s.token"))
+        .and(body_json(serde_json::json!({
+            "plaintext": "BwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwc=",
+            "associated_data": "ZGVrOjE="
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "data": { "ciphertext": "vault:v1:abcd", "key_version": 1 }
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/v1/transit/decrypt/tokend"))
+        .and(body_json(serde_json::json!({
+            "ciphertext": "vault:v1:abcd",
+            "associated_data": "ZGVrOjE="
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "data": { "plaintext": "BwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwc=" }
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/v1/transit/keys/tokend/rotate"))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let wrapped = provider.wrap(&[7u8; 32], b"dek:1").await.unwrap();
+    assert_eq!(wrapped, b"vault:v1:abcd".to_vec());
+    let key = provider.unwrap(&wrapped, b"dek:1").await.unwrap();
+    assert_eq!(key.as_slice(), &[7u8; 32]);
+    provider.rotate().await.expect("Failed to rotate");
+}
+
+#[tokio::test]
+async fn transit_provider_failures() {
+    let server = MockServer::start().await;
+    let provider = TransitKeyProvider::new(
+        &server.uri(),
+        Secret::new("s.token".to_string()),
+        "transit",
+        "tokend",
+    );
+    Mock::given(method("POST"))
+        .and(path("/v1/transit/decrypt/tokend"))
+        .respond_with(ResponseTemplate::new(400))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/v1/transit/encrypt/tokend"))
+        .respond_with(ResponseTemplate::new(403))
+        .mount(&server)
+        .await;
+
+    assert_eq!(
+        provider.unwrap(b"vault:v1:abcd", b"dek:2").await.err(),
+        Some(CryptoError::DecryptionFailure)
+    );
+    assert!(matches!(
+        provider.wrap(&[7u8; 32], b"dek:1").await,
+        Err(CryptoError::ProviderFailure(_))
+    ));
+}
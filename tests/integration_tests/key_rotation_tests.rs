@@ -0,0 +1,263 @@
+use crate::helpers::fixtures::{declare_tenant, set_up, tear_down, tenant_context};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::postgres::PgPoolOptions;
+use std::sync::Arc;
+use tokend::core::context::Permission::{KeyRotate, TokenCreate, TokenRead};
+use tokend::core::context::{ExecutionContext, TenantId};
+use tokend::core::crypto::KeyProvider;
+use tokend::core::token::{
+    Policy, RotationJob, SequenceFormat, Token, TokenFormat, TokenVault, VaultKeys,
+};
+use tokend::core::util::Paging;
+use tokend::error::ErrorCode;
+use tokend::infra::config::{DatabaseRole, Settings};
+use tokend::infra::db::ContextualizedPool;
+use tokend::infra::keys::FileKeyProvider;
+
+fn sample_policy() -> Policy {
+    Policy {
+        prefix: Some("TOK-".to_string()),
+        ..Policy::new(
+            "sales",
+            TokenFormat::Sequence(SequenceFormat::PaddedInt(6, '0')),
+        )
+    }
+}
+
+fn context(tenant: &TenantId) -> ExecutionContext {
+    tenant_context(tenant, &[TokenCreate, TokenRead, KeyRotate])
+}
+
+async fn store_tokens(repo: &ContextualizedPool, tenant: &TenantId, range: std::ops::Range<i32>) {
+    for i in range {
+        repo.store_token(
+            &context(tenant),
+            &sample_policy(),
+            None,
+            &Token::from(format!("TOK-{i}")),
+            &Secret::new(format!("VALUE {i}")),
+        )
+        .await
+        .expect("Failed to store token");
+    }
+}
+
+async fn assert_tokens_resolve(
+    repo: &ContextualizedPool,
+    tenant: &TenantId,
+    range: std::ops::Range<i32>,
+) {
+    for i in range {
+        let value = repo
+            .resolve_token(
+                &context(tenant),
+                &sample_policy(),
+                &Token::from(format!("TOK-{i}")),
+            )
+            .await
+            .expect("Failed to resolve token")
+            .expect("Token not found");
+        assert_eq!(value.expose_secret(), &format!("VALUE {i}"));
+    }
+}
+
+#[tokio::test]
+async fn previous_data_keys_still_decrypt() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    store_tokens(&repo, &tenant, 0..2).await;
+
+    let version = repo
+        .rotate_data_key(&context(&tenant))
+        .await
+        .expect("Failed to rotate the data key");
+    assert_eq!(version, 2);
+    store_tokens(&repo, &tenant, 2..4).await;
+
+    assert_tokens_resolve(&repo, &tenant, 0..4).await;
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn rotation_requires_its_own_permission() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let context = tenant_context(&tenant, &[TokenCreate, TokenRead]);
+
+    let err = repo.rotate_data_key(&context).await.unwrap_err();
+    assert_eq!(err.code(), ErrorCode::Forbidden);
+    let err = RotationJob::new(repo.clone(), 10)
+        .run(&context, None, |_| {})
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), ErrorCode::Forbidden);
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn rotation_job_reencrypts_in_batches() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let other_tenant = declare_tenant(&repo, "sncf").await;
+    store_tokens(&repo, &tenant, 0..5).await;
+    store_tokens(&repo, &other_tenant, 0..3).await;
+    repo.rotate_data_key(&context(&tenant)).await.unwrap();
+
+    let mut reports = vec![];
+    let progress = RotationJob::new(repo.clone(), 2)
+        .run(&context(&tenant), None, |progress| {
+            reports.push(progress.reencrypted)
+        })
+        .await
+        .expect("Failed to run the rotation");
+    assert_eq!(reports, vec![2, 4, 5]);
+    assert_eq!(progress.batches, 3);
+    assert_eq!(progress.after, None);
+
+    // nothing left, and the other tenant is left untouched
+    let page = repo
+        .reencrypt_tokens(&context(&tenant), Paging::new(10, None))
+        .await
+        .unwrap();
+    assert!(page.items.is_empty());
+    let page = repo
+        .reencrypt_tokens(&context(&other_tenant), Paging::new(10, None))
+        .await
+        .unwrap();
+    assert!(page.items.is_empty());
+    assert_tokens_resolve(&repo, &tenant, 0..5).await;
+    assert_tokens_resolve(&repo, &other_tenant, 0..3).await;
+
+    // re-encrypting does not change the tokens as far as the audit log goes
+    assert_eq!(count_token_updates(&settings).await, 0);
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn rotation_job_resumes_after_an_interruption() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    store_tokens(&repo, &tenant, 0..5).await;
+    repo.rotate_data_key(&context(&tenant)).await.unwrap();
+
+    // interrupted after the first batch
+    let page = repo
+        .reencrypt_tokens(&context(&tenant), Paging::new(2, None))
+        .await
+        .unwrap();
+    assert_eq!(page.items.len(), 2);
+    assert!(page.page_infos.has_next_page());
+
+    let progress = RotationJob::new(repo.clone(), 2)
+        .run(&context(&tenant), page.page_infos.after(), |_| {})
+        .await
+        .expect("Failed to resume the rotation");
+    assert_eq!(progress.reencrypted, 3);
+
+    // restarting from scratch is harmless
+    let progress = RotationJob::new(repo.clone(), 2)
+        .run(&context(&tenant), None, |_| {})
+        .await
+        .expect("Failed to restart the rotation");
+    assert_eq!(progress.reencrypted, 0);
+    assert_tokens_resolve(&repo, &tenant, 0..5).await;
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn master_key_rotation_rewraps_the_data_keys() {
+    let (settings, repo) = set_up().await;
+    let path = std::env::temp_dir().join(format!("tokend_{}.keys", uuid::Uuid::new_v4()));
+    std::fs::copy("./conf/local.keys.template", &path).expect("Failed to copy the key file");
+    let provider = Arc::new(FileKeyProvider::open(&path).unwrap());
+    let repo = repo.with_key_provider(provider.clone());
+    let tenant = declare_tenant(&repo, "idfm").await;
+    store_tokens(&repo, &tenant, 0..3).await;
+
+    provider
+        .rotate()
+        .await
+        .expect("Failed to rotate the master key");
+    let progress = RotationJob::new(repo.clone(), 2)
+        .run(&context(&tenant), None, |_| {})
+        .await
+        .expect("Failed to run the rotation");
+    assert_eq!(progress.reencrypted, 0);
+
+    // only the latest master key is needed from now on: the first one is replaced
+    let keys = std::fs::read_to_string(&path).unwrap();
+    let mut keys: Vec<&str> = keys.lines().filter(|key| !key.is_empty()).collect();
+    keys[0] = "AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=";
+    std::fs::write(&path, keys.join("\n")).unwrap();
+    let repo = repo.with_key_provider(Arc::new(FileKeyProvider::open(&path).unwrap()));
+    assert_tokens_resolve(&repo, &tenant, 0..3).await;
+
+    std::fs::remove_file(path).unwrap();
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn rotation_job_skips_undecryptable_values() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    store_tokens(&repo, &tenant, 0..5).await;
+    corrupt_token(&settings, &tenant, "TOK-2").await;
+    repo.rotate_data_key(&context(&tenant)).await.unwrap();
+
+    let progress = RotationJob::new(repo.clone(), 2)
+        .run(&context(&tenant), None, |_| {})
+        .await
+        .expect("Failed to run the rotation");
+    assert_eq!(progress.reencrypted, 4);
+    assert_eq!(progress.after, None);
+    assert_tokens_resolve(&repo, &tenant, 0..2).await;
+    assert_tokens_resolve(&repo, &tenant, 3..5).await;
+
+    tear_down(&settings, repo).await;
+}
+
+async fn corrupt_token(settings: &Settings, tenant: &TenantId, token: &str) {
+    // migration role bypasses the row level security
+    let pool = PgPoolOptions::new()
+        .max_connections(1)
+        .connect_with(settings.database.with_db(&DatabaseRole::Migration))
+        .await
+        .expect("Failed to create connection pool");
+    // the audit triggers require the caller and the tenant
+    sqlx::query(
+        "select set_config('var.caller_type', 'USER', false), \
+        set_config('var.caller_id', '007', false), set_config('var.tenant_id', $1, false)",
+    )
+    .bind(tenant.to_string())
+    .execute(&pool)
+    .await
+    .expect("Failed to contextualize the connection");
+    sqlx::query("update tokens set value = $1 where token = $2")
+        .bind(&[0u8][..])
+        .bind(token)
+        .execute(&pool)
+        .await
+        .expect("Failed to corrupt the token");
+    pool.close().await;
+}
+
+async fn count_token_updates(settings: &Settings) -> i64 {
+    // migration role bypasses the row level security
+    let pool = PgPoolOptions::new()
+        .max_connections(1)
+        .connect_with(settings.database.with_db(&DatabaseRole::Migration))
+        .await
+        .expect("Failed to create connection pool");
+    let count: i64 = sqlx::query_scalar(
+        "select count(*) from audit_log where changed_table_name = 'tokens' and changed_type = 'U'",
+    )
+    .fetch_one(&pool)
+    .await
+    .expect("Failed to query audit log");
+    pool.close().await;
+    count
+}
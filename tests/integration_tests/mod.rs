@@ -1,2 +1,10 @@
 mod config_tests;
+mod crypto_shredding_tests;
+mod db_context_tests;
+mod key_provider_tests;
+mod key_rotation_tests;
+mod policy_tests;
+mod sequence_tests;
+mod tenant_api_tests;
 mod tenant_tests;
+mod token_tests;
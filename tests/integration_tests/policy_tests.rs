@@ -0,0 +1,231 @@
+use crate::helpers::fixtures::{declare_tenant, set_up, tear_down, tenant_context};
+use tokend::core::context::Permission::{PolicyCreate, PolicyRead, PolicyUpdate};
+use tokend::core::policy::Policies;
+use tokend::core::token::{Policy, SequenceFormat, Template, TokenFormat};
+use tokend::core::util::{LengthUnit, Paging};
+use tokend::error::{Error, ErrorCode};
+
+fn sample_policy(code: &str) -> Policy {
+    Policy {
+        prefix: Some("TOK-".to_string()),
+        keep_left: 2,
+        keep_right: 3,
+        ..Policy::new(
+            code,
+            TokenFormat::Sequence(SequenceFormat::PaddedInt(6, '0')),
+        )
+    }
+}
+
+#[tokio::test]
+async fn create_and_find_policy() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let context = tenant_context(&tenant, &[PolicyCreate, PolicyRead]);
+
+    // WHEN
+    let created = repo
+        .create_policy(&context, sample_policy("sales"))
+        .await
+        .expect("Failed to create policy");
+    assert_eq!(created.row_version, 1);
+
+    // THEN
+    let found = repo
+        .find_policy_by_code(&context, "sales".to_string())
+        .await
+        .expect("Failed to query policy")
+        .expect("Policy not found");
+    assert_eq!(found.id, created.id);
+    assert_eq!(found.policy, sample_policy("sales"));
+
+    // AND
+    let res = repo.create_policy(&context, sample_policy("sales")).await;
+    assert!(matches!(
+        res,
+        Err(Error::Generic(ErrorCode::UniqueViolation, _, _))
+    ));
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn create_policy_with_template() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let context = tenant_context(&tenant, &[PolicyCreate, PolicyRead]);
+    let mut policy = sample_policy("customers");
+    policy.format = TokenFormat::Sequence(SequenceFormat::Template(
+        Template::parse("CUST-{date:%Y}-{seq:8:0}").expect("Invalid template"),
+    ));
+
+    repo.create_policy(&context, policy.clone())
+        .await
+        .expect("Failed to create policy");
+    let found = repo
+        .find_policy_by_code(&context, "customers".to_string())
+        .await
+        .expect("Failed to query policy")
+        .expect("Policy not found");
+    assert_eq!(found.policy, policy);
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn create_policy_counting_graphemes() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let context = tenant_context(&tenant, &[PolicyCreate, PolicyRead]);
+    let mut policy = sample_policy("names");
+    policy.length_unit = LengthUnit::Graphemes;
+
+    let created = repo
+        .create_policy(&context, policy.clone())
+        .await
+        .expect("Failed to create policy");
+    assert_eq!(created.policy.length_unit, LengthUnit::Graphemes);
+    let found = repo
+        .find_policy_by_code(&context, "names".to_string())
+        .await
+        .expect("Failed to query policy")
+        .expect("Policy not found");
+    assert_eq!(found.policy, policy);
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn policies_are_isolated_per_tenant() {
+    let (settings, repo) = set_up().await;
+    let tenant1 = declare_tenant(&repo, "idfm").await;
+    let tenant2 = declare_tenant(&repo, "sncf").await;
+    let permissions = [PolicyCreate, PolicyRead];
+
+    repo.create_policy(
+        &tenant_context(&tenant1, &permissions),
+        sample_policy("sales"),
+    )
+    .await
+    .expect("Failed to create policy");
+
+    let found = repo
+        .find_policy_by_code(&tenant_context(&tenant2, &permissions), "sales".to_string())
+        .await
+        .expect("Failed to query policy");
+    assert!(found.is_none());
+
+    // same code can be used by another tenant
+    repo.create_policy(
+        &tenant_context(&tenant2, &permissions),
+        sample_policy("sales"),
+    )
+    .await
+    .expect("Failed to create policy");
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn find_policies_by_page() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let context = tenant_context(&tenant, &[PolicyCreate, PolicyRead]);
+    for code in ["p1", "p2", "p3"] {
+        repo.create_policy(&context, sample_policy(code))
+            .await
+            .expect("Failed to create policy");
+    }
+
+    let page = repo
+        .find_policies(&context, Paging::new(2, None))
+        .await
+        .expect("Failed to query policies");
+    assert!(page.page_infos.has_next_page());
+    let codes: Vec<String> = page.items.iter().map(|p| p.policy.code.clone()).collect();
+    assert_eq!(codes, vec!["p1", "p2"]);
+
+    let page = repo
+        .find_policies(&context, Paging::new(2, page.page_infos.after()))
+        .await
+        .expect("Failed to query policies");
+    assert!(!page.page_infos.has_next_page());
+    let codes: Vec<String> = page.items.iter().map(|p| p.policy.code.clone()).collect();
+    assert_eq!(codes, vec!["p3"]);
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn update_policy_with_optimistic_locking() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let context = tenant_context(&tenant, &[PolicyCreate, PolicyRead, PolicyUpdate]);
+    let created = repo
+        .create_policy(&context, sample_policy("sales"))
+        .await
+        .expect("Failed to create policy");
+
+    let mut policy = sample_policy("sales");
+    policy.format = TokenFormat::Uuid;
+    policy.keep_left = 0;
+    policy.deterministic = true;
+    let updated = repo
+        .update_policy(&context, policy.clone(), created.row_version)
+        .await
+        .expect("Failed to update policy");
+    assert_eq!(updated.row_version, created.row_version + 1);
+    assert_eq!(updated.policy, policy);
+
+    // stale version
+    let res = repo
+        .update_policy(&context, policy.clone(), created.row_version)
+        .await;
+    assert!(matches!(
+        res,
+        Err(Error::Generic(ErrorCode::ConcurrentModification, _, _))
+    ));
+
+    // unknown policy
+    let res = repo
+        .update_policy(&context, sample_policy("unknown"), 1)
+        .await;
+    assert!(matches!(
+        res,
+        Err(Error::Generic(ErrorCode::NotFound, _, _))
+    ));
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn policy_operations_require_permissions() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let context = tenant_context(&tenant, &[PolicyRead]);
+
+    let res = repo.create_policy(&context, sample_policy("sales")).await;
+    assert!(matches!(
+        res,
+        Err(Error::Generic(ErrorCode::Forbidden, _, _))
+    ));
+
+    let res = repo
+        .update_policy(&context, sample_policy("sales"), 1)
+        .await;
+    assert!(matches!(
+        res,
+        Err(Error::Generic(ErrorCode::Forbidden, _, _))
+    ));
+
+    let context = tenant_context(&tenant, &[PolicyCreate]);
+    let res = repo
+        .find_policy_by_code(&context, "sales".to_string())
+        .await;
+    assert!(matches!(
+        res,
+        Err(Error::Generic(ErrorCode::Forbidden, _, _))
+    ));
+
+    tear_down(&settings, repo).await;
+}
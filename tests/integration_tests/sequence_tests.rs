@@ -0,0 +1,266 @@
+use crate::helpers::fixtures::{declare_tenant, set_up, tear_down, tenant_context};
+use secrecy::Secret;
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokend::core::context::Permission::{PolicyCreate, PolicyRead, TokenCreate};
+use tokend::core::context::{ExecutionContext, TenantId};
+use tokend::core::crypto::KeyedPermutation;
+use tokend::core::policy::Policies;
+use tokend::core::token::{
+    BlockSequenceSource, Policy, RawTokenGenerator, SequenceFormat, SequenceRawTokenGenerator,
+    SequenceSource, TokenFormat,
+};
+use tokend::error::{Error, ErrorCode};
+use tokend::infra::config::TokenizationSettings;
+use tokend::infra::db::ContextualizedPool;
+use tokend::infra::tokenization;
+
+fn sample_policy(code: &str) -> Policy {
+    Policy::new(
+        code,
+        TokenFormat::Sequence(SequenceFormat::PaddedInt(4, '0')),
+    )
+}
+
+async fn create_policy(repo: &ContextualizedPool, tenant: &TenantId, code: &str) -> Policy {
+    repo.create_policy(
+        &tenant_context(tenant, &[PolicyCreate]),
+        sample_policy(code),
+    )
+    .await
+    .expect("Failed to create policy")
+    .policy
+}
+
+async fn next_token(
+    generator: &SequenceRawTokenGenerator<ContextualizedPool>,
+    context: &ExecutionContext,
+    policy: &Policy,
+) -> String {
+    generator
+        .generate(context, policy, &Secret::new("CARMEN".to_string()))
+        .await
+        .expect("Failed to generate token")
+}
+
+#[tokio::test]
+async fn sequences_are_kept_per_tenant_and_policy() {
+    let (settings, repo) = set_up().await;
+    let tenant1 = declare_tenant(&repo, "idfm").await;
+    let tenant2 = declare_tenant(&repo, "sncf").await;
+    let sales = create_policy(&repo, &tenant1, "sales").await;
+    let customers = create_policy(&repo, &tenant1, "customers").await;
+    create_policy(&repo, &tenant2, "sales").await;
+    let context1 = tenant_context(&tenant1, &[TokenCreate]);
+    let context2 = tenant_context(&tenant2, &[TokenCreate]);
+    let generator = SequenceRawTokenGenerator::new(repo.clone());
+
+    assert_eq!(next_token(&generator, &context1, &sales).await, "0001");
+    assert_eq!(next_token(&generator, &context1, &sales).await, "0002");
+    assert_eq!(next_token(&generator, &context1, &customers).await, "0001");
+    assert_eq!(next_token(&generator, &context2, &sales).await, "0001");
+
+    // a new instance (e.g. after a restart) goes on with the same sequence
+    let generator = SequenceRawTokenGenerator::new(repo.clone());
+    assert_eq!(next_token(&generator, &context1, &sales).await, "0003");
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn permuted_sequences_use_a_key_per_tenant() {
+    let (settings, repo) = set_up().await;
+    let tenant1 = declare_tenant(&repo, "idfm").await;
+    let tenant2 = declare_tenant(&repo, "sncf").await;
+    let mut policy = create_policy(&repo, &tenant1, "sales").await;
+    create_policy(&repo, &tenant2, "sales").await;
+    policy.format = TokenFormat::Sequence(SequenceFormat::Permuted(8));
+    let context1 = tenant_context(&tenant1, &[TokenCreate]);
+    let context2 = tenant_context(&tenant2, &[TokenCreate]);
+    let permutation = Arc::new(KeyedPermutation::new(&[4u8; 32]).unwrap());
+    let generator =
+        SequenceRawTokenGenerator::new(repo.clone()).with_permutation(permutation.clone());
+
+    // both tenants are at the same point of their sequence
+    let token1 = next_token(&generator, &context1, &policy).await;
+    let token2 = next_token(&generator, &context2, &policy).await;
+    assert_eq!(token1.len(), 8);
+    assert_ne!(token1, "00000001");
+    assert_ne!(token1, token2);
+
+    // the token is the permuted counter
+    assert_eq!(
+        permutation.unpermute(
+            &tenant1.to_string(),
+            "sales",
+            token1.parse().unwrap(),
+            100_000_000
+        ),
+        Ok(1)
+    );
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn permutation_key_comes_from_the_settings() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let mut policy = create_policy(&repo, &tenant, "sales").await;
+    policy.format = TokenFormat::Sequence(SequenceFormat::Permuted(8));
+    let context = tenant_context(&tenant, &[TokenCreate]);
+    let generator = tokenization::sequence_generator(&settings.tokenization, repo.clone())
+        .expect("Invalid tokenization settings");
+
+    let token = next_token(&generator, &context, &policy).await;
+    assert_eq!(token.len(), 8);
+    assert_ne!(token, "00000001");
+
+    // AND without key
+    let generator =
+        tokenization::sequence_generator(&TokenizationSettings::default(), repo.clone())
+            .expect("Invalid tokenization settings");
+    let err = generator
+        .generate(&context, &policy, &Secret::new("CARMEN".to_string()))
+        .await
+        .expect_err("Permutation key should be missing");
+    assert!(matches!(err, Error::MissingConfig(_)));
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn remaining_capacity_of_a_policy() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let policy = create_policy(&repo, &tenant, "sales").await;
+    let context = tenant_context(&tenant, &[TokenCreate, PolicyRead]);
+    let generator = SequenceRawTokenGenerator::new(repo.clone());
+
+    let capacity = repo.capacity(&context, &policy).await.unwrap().unwrap();
+    assert_eq!(capacity.capacity, 9999);
+    assert_eq!(capacity.remaining(), 9999);
+
+    repo.reserve(&context, &policy, 9997).await.unwrap();
+    assert_eq!(next_token(&generator, &context, &policy).await, "9998");
+    assert_eq!(next_token(&generator, &context, &policy).await, "9999");
+    let capacity = repo.capacity(&context, &policy).await.unwrap().unwrap();
+    assert_eq!(capacity.remaining(), 0);
+
+    let exhausted = generator
+        .generate(&context, &policy, &Secret::new("CARMEN".to_string()))
+        .await
+        .unwrap_err();
+    assert_eq!(exhausted.code(), ErrorCode::SequenceExhausted);
+
+    // unbounded formats
+    let mut raw = policy.clone();
+    raw.format = TokenFormat::Sequence(SequenceFormat::Raw);
+    assert_eq!(repo.capacity(&context, &raw).await.unwrap(), None);
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn reserve_a_range_of_values() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let policy = create_policy(&repo, &tenant, "sales").await;
+    let context = tenant_context(&tenant, &[TokenCreate]);
+
+    assert_eq!(repo.reserve(&context, &policy, 1000).await.unwrap(), 1);
+    assert_eq!(repo.reserve(&context, &policy, 1).await.unwrap(), 1001);
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn concurrent_reservations_never_overlap() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let policy = create_policy(&repo, &tenant, "sales").await;
+    let context = tenant_context(&tenant, &[TokenCreate]);
+
+    let handles: Vec<_> = (0..8)
+        .map(|_| {
+            let repo = repo.clone();
+            let context = context.clone();
+            let policy = policy.clone();
+            tokio::spawn(async move { repo.reserve(&context, &policy, 10).await.unwrap() })
+        })
+        .collect();
+    let mut firsts = Vec::new();
+    for handle in handles {
+        firsts.push(handle.await.unwrap());
+    }
+    firsts.sort();
+    assert_eq!(firsts, (0..8).map(|i| 1 + i * 10).collect::<Vec<i64>>());
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn block_allocation_across_instances_never_duplicates() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let policy = create_policy(&repo, &tenant, "sales").await;
+    let context = tenant_context(&tenant, &[TokenCreate]);
+
+    // two running instances, each with its own blocks
+    let instances = [
+        Arc::new(BlockSequenceSource::new(repo.clone(), 10)),
+        Arc::new(BlockSequenceSource::new(repo.clone(), 10)),
+    ];
+    let handles: Vec<_> = (0..4)
+        .map(|i| {
+            let blocks = instances[i % 2].clone();
+            let context = context.clone();
+            let policy = policy.clone();
+            tokio::spawn(async move {
+                let mut values = Vec::new();
+                for _ in 0..50 {
+                    values.push(blocks.reserve(&context, &policy, 1).await.unwrap());
+                }
+                values
+            })
+        })
+        .collect();
+    let mut values = HashSet::new();
+    for handle in handles {
+        for value in handle.await.unwrap() {
+            assert!(values.insert(value), "duplicated value {value}");
+        }
+    }
+    assert_eq!(values.len(), 200);
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn reserve_requires_an_existing_policy_and_permission() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let policy = create_policy(&repo, &tenant, "sales").await;
+
+    let res = repo
+        .reserve(
+            &tenant_context(&tenant, &[TokenCreate]),
+            &sample_policy("unknown"),
+            1,
+        )
+        .await;
+    assert!(matches!(
+        res,
+        Err(Error::Generic(ErrorCode::InvalidReference, _, _))
+    ));
+
+    let res = repo
+        .reserve(&tenant_context(&tenant, &[PolicyCreate]), &policy, 1)
+        .await;
+    assert!(matches!(
+        res,
+        Err(Error::Generic(ErrorCode::Forbidden, _, _))
+    ));
+
+    tear_down(&settings, repo).await;
+}
@@ -0,0 +1,213 @@
+use crate::helpers::startup;
+use tokend::infra::web::{Problem, PROBLEM_JSON};
+
+fn set_up_env() {
+    std::env::set_var("APP_ENVIRONMENT", "local");
+    std::env::set_var("APP_CONFIG_DIR", "./conf");
+}
+
+#[tokio::test]
+async fn health_check_works() {
+    set_up_env();
+    let app = startup::spawn_app().await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .get(format!("{}/health_check", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert!(response.status().is_success());
+    assert_eq!(Some(0), response.content_length());
+
+    app.tear_down().await;
+}
+
+#[tokio::test]
+async fn tenant_routes_require_a_bearer_token() {
+    set_up_env();
+    let app = startup::spawn_app().await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(format!("{}/tenants", app.address))
+        .json(&serde_json::json!({"code": "idfm"}))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = client
+        .get(format!("{}/tenants/idfm", app.address))
+        .bearer_auth("not.a.jwt")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = client
+        .get(format!("{}/tenants?first=5", app.address))
+        .bearer_auth(app.bearer(None, &["grumpf"]))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.tear_down().await;
+}
+
+#[tokio::test]
+async fn declare_and_find_tenants() {
+    set_up_env();
+    let app = startup::spawn_app().await;
+    let client = reqwest::Client::new();
+    let token = app.bearer(None, &["root"]);
+
+    let response = client
+        .post(format!("{}/tenants", app.address))
+        .bearer_auth(&token)
+        .json(&serde_json::json!({"code": "idfm"}))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status().as_u16(), 201);
+
+    let response = client
+        .get(format!("{}/tenants/idfm", app.address))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status().as_u16(), 200);
+    let tenant: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(tenant["code"], "idfm");
+
+    let response = client
+        .get(format!("{}/tenants/unknown", app.address))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status().as_u16(), 404);
+    assert_eq!(response.headers()["content-type"], PROBLEM_JSON);
+    let problem: Problem = response.json().await.unwrap();
+    assert_eq!(problem.code, "NOT_FOUND");
+    assert_eq!(problem.details.get("code"), Some(&"unknown".to_string()));
+
+    let response = client
+        .get(format!("{}/tenants?first=5", app.address))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status().as_u16(), 200);
+    let page: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(page["items"].as_array().unwrap().len(), 1);
+
+    app.tear_down().await;
+}
+
+#[tokio::test]
+async fn find_tenants_by_pages() {
+    set_up_env();
+    let app = startup::spawn_app().await;
+    let client = reqwest::Client::new();
+    let token = app.bearer(None, &["root"]);
+    for code in ["idfm", "sncf", "ratp"] {
+        client
+            .post(format!("{}/tenants", app.address))
+            .bearer_auth(&token)
+            .json(&serde_json::json!({ "code": code }))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+    }
+
+    let response = client
+        .get(format!("{}/tenants?first=2", app.address))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status().as_u16(), 200);
+    let page: serde_json::Value = response.json().await.unwrap();
+    let codes: Vec<&str> = page["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|t| t["code"].as_str().unwrap())
+        .collect();
+    assert_eq!(codes, vec!["idfm", "sncf"]);
+    assert_eq!(page["page_infos"]["has_next_page"], true);
+    let after = page["page_infos"]["after"].as_str().unwrap();
+
+    let response = client
+        .get(format!("{}/tenants", app.address))
+        .query(&[("first", "2"), ("after", after)])
+        .bearer_auth(&token)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status().as_u16(), 200);
+    let page: serde_json::Value = response.json().await.unwrap();
+    let codes: Vec<&str> = page["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|t| t["code"].as_str().unwrap())
+        .collect();
+    assert_eq!(codes, vec!["ratp"]);
+    assert_eq!(page["page_infos"]["has_next_page"], false);
+
+    app.tear_down().await;
+}
+
+#[tokio::test]
+async fn tenant_errors_are_rendered_as_problems() {
+    set_up_env();
+    let app = startup::spawn_app().await;
+    let client = reqwest::Client::new();
+    let token = app.bearer(None, &["root"]);
+
+    client
+        .post(format!("{}/tenants", app.address))
+        .bearer_auth(&token)
+        .json(&serde_json::json!({"code": "idfm"}))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    let response = client
+        .post(format!("{}/tenants", app.address))
+        .bearer_auth(&token)
+        .json(&serde_json::json!({"code": "idfm"}))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status().as_u16(), 409);
+    let problem: Problem = response.json().await.unwrap();
+    assert_eq!(problem.code, "UNIQUE_VIOLATION");
+
+    let response = client
+        .post(format!("{}/tenants", app.address))
+        .bearer_auth(&token)
+        .json(&serde_json::json!({"name": "idfm"}))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status().as_u16(), 400);
+    let problem: Problem = response.json().await.unwrap();
+    assert_eq!(problem.code, "BAD_REQUEST");
+
+    let response = client
+        .get(format!("{}/tenants", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(response.headers()["content-type"], PROBLEM_JSON);
+    let problem: Problem = response.json().await.unwrap();
+    assert_eq!(problem.code, "UNAUTHORIZED");
+
+    app.tear_down().await;
+}
@@ -38,7 +38,7 @@ async fn create_new_tenants() {
         .find_tenants(&context, Paging::new(5, None))
         .await
         .expect("Failed to query tenants");
-    assert_eq!(page.page_infos.has_next_page(), false);
+    assert!(!page.page_infos.has_next_page());
     assert!(page.page_infos.after.is_none());
     assert_eq!(page.items.len(), 1);
 
@@ -59,12 +59,9 @@ async fn set_up() -> (Settings, ContextualizedPool) {
     startup::migrate_db(&settings.database).await;
 
     let connect_options = settings.database.with_db(&DatabaseRole::Application);
-    let pool = PgPoolOptions::new()
-        .max_connections(2)
-        .connect_with(connect_options)
-        .await
-        .expect("Failed to create connection pool");
-
-    let repo = ContextualizedPool::new(pool);
+    let repo =
+        ContextualizedPool::connect_with(PgPoolOptions::new().max_connections(2), connect_options)
+            .await
+            .expect("Failed to create connection pool");
     (settings, repo)
 }
@@ -0,0 +1,940 @@
+use crate::helpers::fixtures::{declare_tenant, set_up, tear_down, tenant_context};
+use chrono::NaiveDate;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::postgres::PgPoolOptions;
+use sqlx::PgPool;
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokend::core::context::Permission::{TokenCreate, TokenMask, TokenRead};
+use tokend::core::context::TenantId;
+use tokend::core::crypto::{
+    Aes256GcmCipher, Cipher, FpeAlgorithm, FpeCipher, KeyedOffset, Pseudonymizer,
+};
+use tokend::core::token::{
+    Charset, DateShiftFormat, DefaultTokenGenerator, EmailFormat, FpeFormat,
+    InMemoryRawTokenGenerator, MaskFormat, MaskLength, Policy, PseudonymFormat, RandomFormat,
+    SequenceFormat, Token, TokenError, TokenFormat, Tokenizer,
+};
+use tokend::error::{Error, ErrorCode};
+use tokend::infra::config::{DatabaseRole, KeyProviderSettings, Settings, TokenizationSettings};
+use tokend::infra::db::ContextualizedPool;
+use tokend::infra::tokenization;
+
+type SampleTokenizer =
+    Tokenizer<DefaultTokenGenerator<InMemoryRawTokenGenerator>, ContextualizedPool>;
+
+fn sample_policy() -> Policy {
+    Policy {
+        prefix: Some("TOK-".to_string()),
+        ..Policy::new(
+            "sales",
+            TokenFormat::Sequence(SequenceFormat::PaddedInt(6, '0')),
+        )
+    }
+}
+
+#[tokio::test]
+async fn tokenize_and_detokenize() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let context = tenant_context(&tenant, &[TokenCreate, TokenRead]);
+    let tokenizer = new_tokenizer(&repo);
+    let policy = sample_policy();
+
+    // WHEN
+    let token = tokenizer
+        .tokenize(
+            &context,
+            &policy,
+            Secret::new("CARMEN MCCALLUM".to_string()),
+        )
+        .await
+        .expect("Failed to tokenize");
+    assert_eq!(token.as_str(), "TOK-000001");
+
+    // THEN
+    let value = tokenizer
+        .detokenize(&context, &policy, &token)
+        .await
+        .expect("Failed to detokenize");
+    assert_eq!(value.expose_secret(), "CARMEN MCCALLUM");
+
+    // AND the value is not stored in clear
+    let raw = raw_value(&settings, &token).await;
+    assert_ne!(raw, b"CARMEN MCCALLUM".to_vec());
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn vault_values_are_encrypted_with_a_key_per_tenant() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let other_tenant = declare_tenant(&repo, "sncf").await;
+    let tokenizer = new_tokenizer(&repo);
+    let mut tokens = vec![];
+    for tenant in [&tenant, &other_tenant] {
+        let token = tokenizer
+            .tokenize(
+                &tenant_context(tenant, &[TokenCreate]),
+                &sample_policy(),
+                Secret::new("CARMEN MCCALLUM".to_string()),
+            )
+            .await
+            .expect("Failed to tokenize");
+        tokens.push(token);
+    }
+
+    // the master key does not decrypt the values by itself
+    let raw = raw_tenant_value(&settings, &tenant).await;
+    let KeyProviderSettings::File { path } = &settings.keys else {
+        panic!("Local key file expected")
+    };
+    let key = std::fs::read_to_string(path).expect("Failed to read the key file");
+    let master_key = Aes256GcmCipher::from_base64(&Secret::new(key.trim().to_string())).unwrap();
+    let aad = format!("{}:{}:{}", tenant, sample_policy().code, tokens[0].as_str());
+    assert!(master_key.decrypt(&raw, aad.as_bytes()).is_err());
+
+    // a value copied from another tenant cannot be decrypted
+    overwrite_tenant_value(&settings, &other_tenant, &raw).await;
+    let err = tokenizer
+        .detokenize(
+            &tenant_context(&other_tenant, &[TokenRead]),
+            &sample_policy(),
+            &tokens[1],
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), ErrorCode::ServerError);
+
+    // while the genuine one still can
+    let value = tokenizer
+        .detokenize(
+            &tenant_context(&tenant, &[TokenRead]),
+            &sample_policy(),
+            &tokens[0],
+        )
+        .await
+        .expect("Failed to detokenize");
+    assert_eq!(value.expose_secret(), "CARMEN MCCALLUM");
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn detokenize_unknown_token() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let context = tenant_context(&tenant, &[TokenCreate, TokenRead]);
+    let tokenizer = new_tokenizer(&repo);
+
+    let res = tokenizer
+        .detokenize(
+            &context,
+            &sample_policy(),
+            &Token::from("TOK-999".to_string()),
+        )
+        .await;
+    assert!(matches!(
+        res,
+        Err(Error::Generic(ErrorCode::NotFound, _, _))
+    ));
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn tokens_are_isolated_per_tenant() {
+    let (settings, repo) = set_up().await;
+    let tenant1 = declare_tenant(&repo, "idfm").await;
+    let tenant2 = declare_tenant(&repo, "sncf").await;
+    let tokenizer = new_tokenizer(&repo);
+    let policy = sample_policy();
+
+    let token = tokenizer
+        .tokenize(
+            &tenant_context(&tenant1, &[TokenCreate]),
+            &policy,
+            Secret::new("CARMEN MCCALLUM".to_string()),
+        )
+        .await
+        .expect("Failed to tokenize");
+
+    let res = tokenizer
+        .detokenize(&tenant_context(&tenant2, &[TokenRead]), &policy, &token)
+        .await;
+    assert!(matches!(
+        res,
+        Err(Error::Generic(ErrorCode::NotFound, _, _))
+    ));
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn tokenize_and_detokenize_require_permissions() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let tokenizer = new_tokenizer(&repo);
+    let policy = sample_policy();
+
+    let res = tokenizer
+        .tokenize(
+            &tenant_context(&tenant, &[TokenRead]),
+            &policy,
+            Secret::new("CARMEN MCCALLUM".to_string()),
+        )
+        .await;
+    assert!(matches!(
+        res,
+        Err(Error::Generic(ErrorCode::Forbidden, _, _))
+    ));
+
+    let token = tokenizer
+        .tokenize(
+            &tenant_context(&tenant, &[TokenCreate]),
+            &policy,
+            Secret::new("CARMEN MCCALLUM".to_string()),
+        )
+        .await
+        .expect("Failed to tokenize");
+    let res = tokenizer
+        .detokenize(&tenant_context(&tenant, &[TokenCreate]), &policy, &token)
+        .await;
+    assert!(matches!(
+        res,
+        Err(Error::Generic(ErrorCode::Forbidden, _, _))
+    ));
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn deterministic_policy_yields_the_same_token() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let context = tenant_context(&tenant, &[TokenCreate, TokenRead]);
+    let tokenizer = new_tokenizer(&repo);
+    let mut policy = sample_policy();
+    policy.deterministic = true;
+
+    let token1 = tokenizer
+        .tokenize(
+            &context,
+            &policy,
+            Secret::new("CARMEN MCCALLUM".to_string()),
+        )
+        .await
+        .expect("Failed to tokenize");
+    let token2 = tokenizer
+        .tokenize(
+            &context,
+            &policy,
+            Secret::new("CARMEN MCCALLUM".to_string()),
+        )
+        .await
+        .expect("Failed to tokenize");
+    let token3 = tokenizer
+        .tokenize(&context, &policy, Secret::new("SALLY MCCALLUM".to_string()))
+        .await
+        .expect("Failed to tokenize");
+    assert_eq!(token1, token2);
+    assert_ne!(token1, token3);
+
+    // non deterministic policy
+    let token4 = tokenizer
+        .tokenize(
+            &context,
+            &sample_policy(),
+            Secret::new("CARMEN MCCALLUM".to_string()),
+        )
+        .await
+        .expect("Failed to tokenize");
+    assert_ne!(token1, token4);
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn deterministic_policy_concurrent_tokenizations_yield_one_mapping() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let context = tenant_context(&tenant, &[TokenCreate, TokenRead]);
+    let tokenizer = Arc::new(new_tokenizer(&repo));
+    let mut policy = sample_policy();
+    policy.deterministic = true;
+
+    let handles: Vec<_> = (0..8)
+        .map(|_| {
+            let tokenizer = tokenizer.clone();
+            let context = context.clone();
+            let policy = policy.clone();
+            tokio::spawn(async move {
+                tokenizer
+                    .tokenize(
+                        &context,
+                        &policy,
+                        Secret::new("CARMEN MCCALLUM".to_string()),
+                    )
+                    .await
+                    .expect("Failed to tokenize")
+            })
+        })
+        .collect();
+    let mut tokens = Vec::new();
+    for handle in handles {
+        tokens.push(handle.await.unwrap());
+    }
+    tokens.dedup();
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(count_tokens(&settings).await, 1);
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn tokenize_retries_on_token_collision() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let context = tenant_context(&tenant, &[TokenCreate, TokenRead]);
+    let policy = sample_policy();
+
+    let token1 = new_tokenizer(&repo)
+        .tokenize(&context, &policy, Secret::new("CARMEN".to_string()))
+        .await
+        .expect("Failed to tokenize");
+    // a fresh sequence starts over, colliding with the first token
+    let token2 = new_tokenizer(&repo)
+        .tokenize(&context, &policy, Secret::new("SALLY".to_string()))
+        .await
+        .expect("Failed to tokenize");
+    assert_eq!(token1.as_str(), "TOK-000001");
+    assert_eq!(token2.as_str(), "TOK-000002");
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn tokenize_gives_up_when_no_token_is_free() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let context = tenant_context(&tenant, &[TokenCreate, TokenRead]);
+    let tokenizer = new_tokenizer(&repo);
+    let mut policy = sample_policy();
+    policy.format = TokenFormat::Sequence(SequenceFormat::Raw);
+    policy.prefix = None;
+    for value in ["CARMEN", "SALLY"] {
+        tokenizer
+            .tokenize(&context, &policy, Secret::new(value.to_string()))
+            .await
+            .expect("Failed to tokenize");
+    }
+
+    // "1" and "2" are the only tokens available, and both are taken
+    policy.format = TokenFormat::Random(RandomFormat {
+        length: 1,
+        charset: Charset::Custom("12".to_string()),
+    });
+    let res = tokenizer
+        .tokenize(&context, &policy, Secret::new("ZOE".to_string()))
+        .await;
+    assert!(matches!(
+        res,
+        Err(Error::TokenError(TokenError::GenerationFailure(_)))
+    ));
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn fpe_policy_is_vaultless() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let context = tenant_context(&tenant, &[TokenCreate, TokenRead]);
+    let tokenizer = new_tokenizer(&repo);
+
+    for algorithm in [FpeAlgorithm::Ff1, FpeAlgorithm::Ff31] {
+        let policy = fpe_policy(algorithm);
+        let token = tokenizer
+            .tokenize(
+                &context,
+                &policy,
+                Secret::new("4111111111111111".to_string()),
+            )
+            .await
+            .expect("Failed to tokenize");
+        assert_eq!(token.len(), "PAN-4111111111111111".len());
+        assert!(token.starts_with("PAN-411111"));
+        assert!(token.ends_with("1111"));
+        assert_ne!(token.as_str(), "PAN-4111111111111111");
+        assert!(token.chars().skip(4).all(|c| c.is_ascii_digit()));
+
+        let value = tokenizer
+            .detokenize(&context, &policy, &token)
+            .await
+            .expect("Failed to detokenize");
+        assert_eq!(value.expose_secret(), "4111111111111111");
+    }
+    assert_eq!(count_tokens(&settings).await, 0);
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn fpe_policy_uses_a_key_per_tenant() {
+    let (settings, repo) = set_up().await;
+    let tenant1 = declare_tenant(&repo, "idfm").await;
+    let tenant2 = declare_tenant(&repo, "sncf").await;
+    let tokenizer = new_tokenizer(&repo);
+    let policy = fpe_policy(FpeAlgorithm::Ff1);
+
+    let mut tokens = Vec::new();
+    for tenant in [&tenant1, &tenant2] {
+        tokens.push(
+            tokenizer
+                .tokenize(
+                    &tenant_context(tenant, &[TokenCreate]),
+                    &policy,
+                    Secret::new("4111111111111111".to_string()),
+                )
+                .await
+                .expect("Failed to tokenize"),
+        );
+    }
+    assert_ne!(tokens[0], tokens[1]);
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn fpe_policy_rejects_value_out_of_alphabet() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let context = tenant_context(&tenant, &[TokenCreate, TokenRead]);
+    let tokenizer = new_tokenizer(&repo);
+    let policy = fpe_policy(FpeAlgorithm::Ff1);
+
+    let res = tokenizer
+        .tokenize(
+            &context,
+            &policy,
+            Secret::new("4111-1111-1111-1111".to_string()),
+        )
+        .await;
+    assert_eq!(
+        res.expect_err("Expecting error").code(),
+        ErrorCode::BadRequest
+    );
+
+    let res = tokenizer
+        .detokenize(
+            &context,
+            &policy,
+            &Token::from("4111111111111111".to_string()),
+        )
+        .await;
+    assert!(matches!(
+        res,
+        Err(Error::Generic(ErrorCode::NotFound, _, _))
+    ));
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn class_preserving_policy_round_trip() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let context = tenant_context(&tenant, &[TokenCreate, TokenRead]);
+    let tokenizer = new_tokenizer(&repo);
+    let mut policy = sample_policy();
+    policy.format = TokenFormat::ClassPreserving;
+    policy.prefix = None;
+
+    let token = tokenizer
+        .tokenize(&context, &policy, Secret::new("X-1234-ab".to_string()))
+        .await
+        .expect("Failed to tokenize");
+    let shape = regex::Regex::new(r"^[A-Z]-[0-9]{4}-[a-z]{2}$").unwrap();
+    assert!(
+        shape.is_match(&token),
+        "{} does not look like X-1234-ab",
+        *token
+    );
+
+    let value = tokenizer
+        .detokenize(&context, &policy, &token)
+        .await
+        .expect("Failed to detokenize");
+    assert_eq!(value.expose_secret(), "X-1234-ab");
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn email_policy_keeps_the_domain() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let context = tenant_context(&tenant, &[TokenCreate, TokenRead]);
+    let tokenizer = new_tokenizer(&repo);
+    let policy = email_policy(false);
+
+    let token = tokenizer
+        .tokenize(
+            &context,
+            &policy,
+            Secret::new("carmen.mccallum@example.com".to_string()),
+        )
+        .await
+        .expect("Failed to tokenize");
+    assert_eq!(token.as_str(), "TOK-000001@example.com");
+
+    let value = tokenizer
+        .detokenize(&context, &policy, &token)
+        .await
+        .expect("Failed to detokenize");
+    assert_eq!(value.expose_secret(), "carmen.mccallum@example.com");
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn email_policy_may_tokenize_the_domain() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let context = tenant_context(&tenant, &[TokenCreate, TokenRead]);
+    let tokenizer = new_tokenizer(&repo);
+    let policy = email_policy(true);
+
+    let token = tokenizer
+        .tokenize(
+            &context,
+            &policy,
+            Secret::new("carmen@hr.internal-corp.fr".to_string()),
+        )
+        .await
+        .expect("Failed to tokenize");
+    let shape =
+        regex::Regex::new(r"^TOK-000001@[a-z0-9]{2}\.[a-z0-9]{8}-[a-z0-9]{4}\.fr$").unwrap();
+    assert!(shape.is_match(&token), "unexpected token {}", *token);
+
+    let value = tokenizer
+        .detokenize(&context, &policy, &token)
+        .await
+        .expect("Failed to detokenize");
+    assert_eq!(value.expose_secret(), "carmen@hr.internal-corp.fr");
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn email_policy_rejects_invalid_addresses() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let context = tenant_context(&tenant, &[TokenCreate]);
+    let tokenizer = new_tokenizer(&repo);
+
+    let err = tokenizer
+        .tokenize(
+            &context,
+            &email_policy(false),
+            Secret::new("carmen@example..com".to_string()),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), ErrorCode::BadRequest);
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn date_shift_policy_is_vaultless() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let tokenizer = new_tokenizer(&repo);
+    let policy = date_shift_policy(false);
+
+    let token = tokenizer
+        .tokenize(
+            &tenant_context(&tenant, &[TokenCreate]),
+            &policy,
+            Secret::new("17/05/1984".to_string()),
+        )
+        .await
+        .expect("Failed to tokenize");
+    let shifted = NaiveDate::parse_from_str(&token, "%d/%m/%Y").expect("Token is not a date");
+    let days = (shifted - NaiveDate::from_ymd_opt(1984, 5, 17).unwrap()).num_days();
+    assert!(days != 0 && days.abs() <= 30);
+    assert_eq!(count_tokens(&settings).await, 0);
+
+    // reverting requires TokenRead
+    let err = tokenizer
+        .detokenize(&tenant_context(&tenant, &[TokenCreate]), &policy, &token)
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), ErrorCode::Forbidden);
+    let value = tokenizer
+        .detokenize(&tenant_context(&tenant, &[TokenRead]), &policy, &token)
+        .await
+        .expect("Failed to detokenize");
+    assert_eq!(value.expose_secret(), "17/05/1984");
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn date_shift_per_subject_keeps_intervals() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let context = tenant_context(&tenant, &[TokenCreate, TokenRead]);
+    let tokenizer = new_tokenizer(&repo);
+    let policy = date_shift_policy(true);
+    let shift = |subject: &str, value: &str| {
+        let token = tokenizer
+            .shift_date(
+                &context,
+                &policy,
+                Some(subject),
+                &Secret::new(value.to_string()),
+            )
+            .expect("Failed to shift date");
+        NaiveDate::parse_from_str(&token, "%d/%m/%Y").expect("Token is not a date")
+    };
+
+    let (birth, admission) = (shift("carmen", "17/05/1984"), shift("carmen", "01/03/2023"));
+    let original = NaiveDate::from_ymd_opt(2023, 3, 1).unwrap()
+        - NaiveDate::from_ymd_opt(1984, 5, 17).unwrap();
+    assert_eq!(admission - birth, original);
+
+    // other subjects get other offsets
+    let offsets: HashSet<NaiveDate> = ["a", "b", "c", "d", "e", "f"]
+        .iter()
+        .map(|subject| shift(subject, "17/05/1984"))
+        .collect();
+    assert!(offsets.len() > 1);
+
+    // and the subject is required
+    let err = tokenizer
+        .tokenize(&context, &policy, Secret::new("17/05/1984".to_string()))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), ErrorCode::BadRequest);
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn date_shift_per_subject_round_trips() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let context = tenant_context(&tenant, &[TokenCreate, TokenRead]);
+    let tokenizer = new_tokenizer(&repo);
+    let policy = date_shift_policy(true);
+
+    let token = tokenizer
+        .tokenize_with_subject(
+            &context,
+            &policy,
+            Some("carmen"),
+            Secret::new("17/05/1984".to_string()),
+        )
+        .await
+        .expect("Failed to tokenize");
+    let value = tokenizer
+        .detokenize_with_subject(&context, &policy, Some("carmen"), &token)
+        .await
+        .expect("Failed to detokenize");
+    assert_eq!(value.expose_secret(), "17/05/1984");
+
+    // the subject is required to revert the shift too
+    let err = tokenizer
+        .detokenize(&context, &policy, &token)
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), ErrorCode::BadRequest);
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn mask_never_touches_the_vault() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let tokenizer = new_tokenizer(&repo);
+    let policy = mask_policy();
+    let value = Secret::new("4111-1111-1111-1234".to_string());
+
+    let masked = tokenizer
+        .mask(&tenant_context(&tenant, &[TokenMask]), &policy, &value)
+        .expect("Failed to mask");
+    assert_eq!(masked, "****-****-****-1234");
+    assert_eq!(count_tokens(&settings).await, 0);
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn mask_requires_its_own_permission() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let tokenizer = new_tokenizer(&repo);
+    let value = Secret::new("4111-1111-1111-1234".to_string());
+
+    // tokenizing does not allow to mask...
+    let err = tokenizer
+        .mask(
+            &tenant_context(&tenant, &[TokenCreate, TokenRead]),
+            &mask_policy(),
+            &value,
+        )
+        .unwrap_err();
+    assert_eq!(err.code(), ErrorCode::Forbidden);
+
+    // ...masking does not allow to tokenize
+    let err = tokenizer
+        .tokenize(
+            &tenant_context(&tenant, &[TokenMask]),
+            &sample_policy(),
+            Secret::new("CARMEN".to_string()),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), ErrorCode::Forbidden);
+
+    // and a mask policy does not produce tokens
+    let err = tokenizer
+        .tokenize(
+            &tenant_context(&tenant, &[TokenCreate]),
+            &mask_policy(),
+            value,
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), ErrorCode::BadRequest);
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn pseudonym_policy_is_stable_and_irreversible() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let other_tenant = declare_tenant(&repo, "sncf").await;
+    let tokenizer = new_tokenizer(&repo);
+    let policy = pseudonym_policy();
+
+    let token = pseudonym(&tokenizer, &tenant, &policy)
+        .await
+        .expect("Failed to pseudonymize");
+    assert!(token.starts_with("PSN-"));
+    assert_eq!(token.len(), 4 + 20);
+    assert_eq!(
+        token,
+        pseudonym(&tokenizer, &tenant, &policy).await.unwrap()
+    );
+    assert_ne!(
+        token,
+        pseudonym(&tokenizer, &other_tenant, &policy).await.unwrap()
+    );
+    let mut other_policy = pseudonym_policy();
+    other_policy.code = "cohorts".to_string();
+    assert_ne!(
+        token,
+        pseudonym(&tokenizer, &tenant, &other_policy).await.unwrap()
+    );
+    assert_eq!(count_tokens(&settings).await, 0);
+
+    let err = tokenizer
+        .detokenize(
+            &tenant_context(&tenant, &[TokenCreate, TokenRead]),
+            &policy,
+            &token,
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), ErrorCode::Forbidden);
+
+    tear_down(&settings, repo).await;
+}
+
+#[tokio::test]
+async fn vaultless_keys_come_from_the_settings() {
+    let (settings, repo) = set_up().await;
+    let tenant = declare_tenant(&repo, "idfm").await;
+    let context = tenant_context(&tenant, &[TokenCreate, TokenRead]);
+    let generator = DefaultTokenGenerator::new(InMemoryRawTokenGenerator::new());
+    let tokenizer = tokenization::tokenizer(&settings.tokenization, generator, repo.clone())
+        .expect("Invalid tokenization settings");
+    let policies = [
+        (fpe_policy(FpeAlgorithm::Ff1), "4111111111111111"),
+        (date_shift_policy(false), "17/05/1984"),
+        (pseudonym_policy(), "CARMEN"),
+    ];
+
+    for (policy, value) in &policies {
+        tokenizer
+            .tokenize(&context, policy, Secret::new(value.to_string()))
+            .await
+            .expect("Failed to tokenize");
+    }
+
+    // AND without keys
+    let generator = DefaultTokenGenerator::new(InMemoryRawTokenGenerator::new());
+    let tokenizer =
+        tokenization::tokenizer(&TokenizationSettings::default(), generator, repo.clone())
+            .expect("Invalid tokenization settings");
+    for (policy, value) in &policies {
+        let err = tokenizer
+            .tokenize(&context, policy, Secret::new(value.to_string()))
+            .await
+            .expect_err("Vaultless key should be missing");
+        assert!(matches!(err, Error::MissingConfig(_)));
+    }
+
+    tear_down(&settings, repo).await;
+}
+
+async fn pseudonym(
+    tokenizer: &SampleTokenizer,
+    tenant: &TenantId,
+    policy: &Policy,
+) -> Result<Token, Error> {
+    tokenizer
+        .tokenize(
+            &tenant_context(tenant, &[TokenCreate]),
+            policy,
+            Secret::new("CARMEN".to_string()),
+        )
+        .await
+}
+
+fn pseudonym_policy() -> Policy {
+    let mut policy = sample_policy();
+    policy.code = "patients".to_string();
+    policy.prefix = Some("PSN-".to_string());
+    policy.format = TokenFormat::Pseudonym(PseudonymFormat {
+        length: 20,
+        charset: Charset::CrockfordBase32,
+    });
+    policy
+}
+
+fn email_policy(tokenize_domain: bool) -> Policy {
+    let mut policy = sample_policy();
+    policy.format = TokenFormat::Email(EmailFormat {
+        local: Box::new(TokenFormat::Sequence(SequenceFormat::PaddedInt(6, '0'))),
+        tokenize_domain,
+    });
+    policy
+}
+
+fn date_shift_policy(per_subject: bool) -> Policy {
+    let mut policy = sample_policy();
+    policy.code = "births".to_string();
+    policy.format = TokenFormat::DateShift(DateShiftFormat {
+        pattern: "%d/%m/%Y".to_string(),
+        window_days: 30,
+        per_subject,
+    });
+    policy
+}
+
+fn mask_policy() -> Policy {
+    Policy {
+        keep_right: 4,
+        ..Policy::new(
+            "display",
+            TokenFormat::Mask(MaskFormat {
+                mask_char: '*',
+                separators: Some("-".to_string()),
+                length: MaskLength::Preserve,
+                keep_domain: false,
+            }),
+        )
+    }
+}
+
+fn fpe_policy(algorithm: FpeAlgorithm) -> Policy {
+    Policy {
+        prefix: Some("PAN-".to_string()),
+        keep_left: 6,
+        keep_right: 4,
+        ..Policy::new("pan", TokenFormat::Fpe(FpeFormat::numeric(algorithm)))
+    }
+}
+
+fn new_tokenizer(repo: &ContextualizedPool) -> SampleTokenizer {
+    Tokenizer::new(
+        DefaultTokenGenerator::new(InMemoryRawTokenGenerator::new()),
+        repo.clone(),
+    )
+    .with_fpe(Arc::new(FpeCipher::new(&[5u8; 32]).unwrap()))
+    .with_date_shift(Arc::new(KeyedOffset::new(&[6u8; 32]).unwrap()))
+    .with_pseudonymizer(Arc::new(Pseudonymizer::new(&[7u8; 32]).unwrap()))
+}
+
+async fn count_tokens(settings: &Settings) -> i64 {
+    let pool = PgPoolOptions::new()
+        .max_connections(1)
+        .connect_with(settings.database.with_db(&DatabaseRole::Migration))
+        .await
+        .expect("Failed to create connection pool");
+    let count: i64 = sqlx::query_scalar("select count(*) from tokens")
+        .fetch_one(&pool)
+        .await
+        .expect("Failed to count tokens");
+    pool.close().await;
+    count
+}
+
+async fn raw_value(settings: &Settings, token: &Token) -> Vec<u8> {
+    let pool = migration_pool(settings).await;
+    let value: Vec<u8> = sqlx::query_scalar("select value from tokens where token = $1")
+        .bind(token.as_str())
+        .fetch_one(&pool)
+        .await
+        .expect("Failed to query token");
+    pool.close().await;
+    value
+}
+
+async fn raw_tenant_value(settings: &Settings, tenant: &TenantId) -> Vec<u8> {
+    let pool = migration_pool(settings).await;
+    let value: Vec<u8> =
+        sqlx::query_scalar("select value from tokens where tenant_id = $1::bigint")
+            .bind(tenant.to_string())
+            .fetch_one(&pool)
+            .await
+            .expect("Failed to query token");
+    pool.close().await;
+    value
+}
+
+async fn overwrite_tenant_value(settings: &Settings, tenant: &TenantId, raw: &[u8]) {
+    let pool = migration_pool(settings).await;
+    // the audit triggers require the caller and the tenant
+    sqlx::query(
+        "select set_config('var.caller_type', 'USER', false), \
+        set_config('var.caller_id', '007', false), set_config('var.tenant_id', $1, false)",
+    )
+    .bind(tenant.to_string())
+    .execute(&pool)
+    .await
+    .expect("Failed to contextualize the connection");
+    sqlx::query("update tokens set value = $1 where tenant_id = $2::bigint")
+        .bind(raw)
+        .bind(tenant.to_string())
+        .execute(&pool)
+        .await
+        .expect("Failed to update token");
+    pool.close().await;
+}
+
+async fn migration_pool(settings: &Settings) -> PgPool {
+    // migration role bypasses the row level security
+    PgPoolOptions::new()
+        .max_connections(1)
+        .connect_with(settings.database.with_db(&DatabaseRole::Migration))
+        .await
+        .expect("Failed to create connection pool")
+}